@@ -1,10 +1,11 @@
 use color::{Color, rgb};
-use geometry::Au;
+use geometry::{Au, to_frac_px};
 use image::base::Image;
 use render_context::RenderContext;
 use text::SendableTextRun;
 use util::range::Range;
 
+use azure::AzFloat;
 use azure::azure_hl::DrawTarget;
 use core::dvec::DVec;
 use clone_arc = std::arc::clone;
@@ -22,6 +23,25 @@ impl DisplayItemData {
     }
 }
 
+/* The radius of each corner of a rounded rect, clockwise from the top left. */
+pub struct CornerRadii {
+    top_left: Au,
+    top_right: Au,
+    bottom_right: Au,
+    bottom_left: Au
+}
+
+pub impl CornerRadii {
+    static pure fn zero() -> CornerRadii {
+        CornerRadii { top_left: Au(0), top_right: Au(0), bottom_right: Au(0), bottom_left: Au(0) }
+    }
+
+    pure fn is_zero(&self) -> bool {
+        self.top_left == Au(0) && self.top_right == Au(0)
+            && self.bottom_right == Au(0) && self.bottom_left == Au(0)
+    }
+}
+
 pub enum DisplayItem {
     SolidColor(DisplayItemData, Color),
     // TODO: need to provide spacing data for text run.
@@ -29,7 +49,16 @@ pub enum DisplayItem {
     // TODO: don't copy text runs, ever.
     Text(DisplayItemData, ~SendableTextRun, Range, Color),
     Image(DisplayItemData, ARC<~image::base::Image>),
-    Border(DisplayItemData, Au, Color)
+    Border(DisplayItemData, Au, Color),
+    // A solid color with rounded corners ('border-radius').
+    RoundedRect(DisplayItemData, CornerRadii, Color),
+    // Translates all items up to the matching PopTranslate.
+    PushTranslate(Point2D<Au>),
+    PopTranslate,
+    // Puts all items up to the matching PopScrollLayer in a layer whose
+    // content (the second rect) is scrolled within the clip (the first).
+    PushScrollLayer(Rect<Au>, Rect<Au>),
+    PopScrollLayer
 }
 
 impl DisplayItem {
@@ -38,7 +67,10 @@ impl DisplayItem {
             SolidColor(ref d, _) => d,
             Text(ref d, _, _, _) => d,
             Image(ref d, _) => d,
-            Border(ref d, _, _) => d
+            Border(ref d, _, _) => d,
+            RoundedRect(ref d, _, _) => d,
+            PushTranslate(*) | PopTranslate => fail ~"Translation markers have no item data",
+            PushScrollLayer(*) | PopScrollLayer => fail ~"Layer markers have no item data"
         }
     }
     
@@ -57,6 +89,10 @@ impl DisplayItem {
                 ctx.draw_image(self.d().bounds, clone_arc(img));
             }
             &Border(_, width, color) => ctx.draw_border(&self.d().bounds, width, color),
+            // TODO: clip the corners to the radii
+            &RoundedRect(_, _, color) => ctx.draw_solid_color(&self.d().bounds, color),
+            &PushTranslate(*) | &PopTranslate => fail ~"Translation markers are drawn by DisplayList",
+            &PushScrollLayer(*) | &PopScrollLayer => fail ~"Layer markers are drawn by DisplayList"
         }
 
         debug!("%?", {
@@ -68,6 +104,11 @@ impl DisplayItem {
         SolidColor(DisplayItemData::new(bounds), color)
     }
 
+    static pure fn new_RoundedRect(bounds: &Rect<Au>, radii: &CornerRadii,
+                                   color: Color) -> DisplayItem {
+        RoundedRect(DisplayItemData::new(bounds), copy *radii, color)
+    }
+
     static pure fn new_Border(bounds: &Rect<Au>, width: Au, color: Color) -> DisplayItem {
         Border(DisplayItemData::new(bounds), width, color)
     }
@@ -79,6 +120,22 @@ impl DisplayItem {
         Text(DisplayItemData::new(bounds), move run, move range, color)
     }
 
+    static pure fn new_PushTranslate(offset: &Point2D<Au>) -> DisplayItem {
+        PushTranslate(copy *offset)
+    }
+
+    static pure fn new_PopTranslate() -> DisplayItem {
+        PopTranslate
+    }
+
+    static pure fn new_PushScrollLayer(clip: &Rect<Au>, content: &Rect<Au>) -> DisplayItem {
+        PushScrollLayer(copy *clip, copy *content)
+    }
+
+    static pure fn new_PopScrollLayer() -> DisplayItem {
+        PopScrollLayer
+    }
+
     // ARC should be cloned into ImageData, but Images are not sendable
     static pure fn new_Image(bounds: &Rect<Au>, image: ARC<~image::base::Image>) -> DisplayItem {
         Image(DisplayItemData::new(bounds), move image)
@@ -110,11 +167,26 @@ impl DisplayList : DisplayListMethods {
 
     fn draw_into_context(ctx: &RenderContext) {
         debug!("beginning display list");
+        let draw_target = ctx.get_draw_target();
+        let mut saved_transforms = ~[];
         for self.list.each |item| {
             // FIXME(Issue #150): crashes
             //debug!("drawing %?", *item);
-            item.draw_into_context(ctx);
+            match **item {
+                PushTranslate(ref offset) => {
+                    let transform = draw_target.get_transform();
+                    draw_target.set_transform(&transform.translate(&(to_frac_px(offset.x) as AzFloat),
+                                                                   &(to_frac_px(offset.y) as AzFloat)));
+                    saved_transforms.push(move transform);
+                }
+                PopTranslate => draw_target.set_transform(&saved_transforms.pop()),
+                // TODO: composite scroll layers separately; for now their
+                // content is drawn in place, unclipped.
+                PushScrollLayer(*) | PopScrollLayer => {}
+                _ => item.draw_into_context(ctx)
+            }
         }
+        assert saved_transforms.is_empty();
         debug!("ending display list");
     }
 }
@@ -76,6 +76,16 @@ impl Au {
         Au((f * 60f) as i32)
     }
 
+    /// `self + other`, or None if the sum is out of the range of `Au`.
+    pub pure fn checked_add(&self, other: Au) -> Option<Au> {
+        let sum = (**self as i64) + (*other as i64);
+        if sum > (i32::max_value as i64) || sum < (i32::min_value as i64) {
+            None
+        } else {
+            Some(Au(sum as i32))
+        }
+    }
+
     static pub pure fn min(x: Au, y: Au) -> Au { if *x < *y { x } else { y } }
     static pub pure fn max(x: Au, y: Au) -> Au { if *x > *y { x } else { y } }
 }
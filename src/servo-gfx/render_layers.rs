@@ -15,7 +15,8 @@ use std::arc::ARC;
 use std::arc;
 
 pub struct RenderLayer {
-    display_list: DisplayList,
+    // shared, so that layout can keep the list it sent for reuse
+    display_list: ARC<DisplayList>,
     size: Size2D<uint>
 }
 
@@ -143,7 +143,7 @@ impl<C: Compositor Owned> Renderer<C> {
                         let render_layer: &RenderLayer = unsafe {
                             cast::transmute(render_layer_ref)
                         };
-                        render_layer.display_list.draw_into_context(&ctx);
+                        arc::get(&render_layer.display_list).draw_into_context(&ctx);
                     }
 
                     // Send back the buffer.
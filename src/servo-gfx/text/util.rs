@@ -31,7 +31,20 @@ impl CompressionMode : cmp::Eq {
 //
 // * Untracked: various edge cases for bidi, CJK, etc.
 pub fn transform_text(text: &str, mode: CompressionMode) -> ~str {
+    let (out_str, _) = transform_text_in_context(text, mode, false);
+    return move out_str;
+}
+
+/**
+Like `transform_text`, but for text that follows other text in the same
+run. `incoming_whitespace` tells whether the preceding text ended in
+whitespace that was compressed; the returned flag tells the same about
+this text, to be passed along to the text that follows.
+*/
+pub fn transform_text_in_context(text: &str, mode: CompressionMode,
+                                 incoming_whitespace: bool) -> (~str, bool) {
     let mut out_str: ~str = ~"";
+    let mut out_whitespace = false;
     match mode {
         CompressNone | DiscardNewline => {
             for str::each_char(text) |ch: char| {
@@ -48,7 +61,7 @@ pub fn transform_text(text: &str, mode: CompressionMode) -> ~str {
         },
 
         CompressWhitespace | CompressWhitespaceNewline => {
-            let mut in_whitespace: bool = false;
+            let mut in_whitespace: bool = incoming_whitespace;
             for str::each_char(text) |ch: char| {
                 // TODO: discard newlines between CJK chars
                 let mut next_in_whitespace: bool = match (ch, mode) {
@@ -78,10 +91,11 @@ pub fn transform_text(text: &str, mode: CompressionMode) -> ~str {
                 // save whitespace context for next char
                 in_whitespace = next_in_whitespace;
             } /* /for str::each_char */
+            out_whitespace = in_whitespace;
         } 
     }
 
-    return move out_str;
+    return (move out_str, out_whitespace);
 
     fn is_discardable_char(ch: char, mode: CompressionMode) -> bool {
         if is_always_discardable_char(ch) {
@@ -3,6 +3,21 @@ use std::map::HashMap;
 use std::net::url;
 use std::net::url::Url;
 
+/// Errors produced while resolving a URL string.
+pub enum UrlError {
+    /// The resolved string could not be parsed as a URL.
+    ParseError(~str),
+    /// A relative reference was given, but the base URL can't have
+    /// relative references resolved against it (e.g. `about:blank`).
+    RelativeWithoutBase,
+    /// The base URL was loaded over the network, but the reference
+    /// resolved to a local `file` URL. Only `try_make_url_strict` refuses these.
+    FileFromNetwork,
+    /// The URL's scheme isn't one of those that the caller allows.
+    /// Only `try_make_url_allowed` reports this.
+    SchemeNotAllowed(~str),
+}
+
 /**
 Create a URL object from a string. Does various helpful browsery things like
 
@@ -14,31 +29,322 @@ Create a URL object from a string. Does various helpful browsery things like
 */
 #[allow(non_implicitly_copyable_typarams)]
 pub fn make_url(str_url: ~str, current_url: Option<Url>) -> Url {
-    let mut schm = url::get_scheme(str_url);
-    let str_url = if result::is_err(&schm) {
-        if current_url.is_none() {
+    // FIXME: Need to handle errors
+    try_make_url(move str_url, move current_url).get()
+}
+
+/**
+Like `make_url`, but returns an error instead of failing when the string
+can't be resolved to a valid URL.
+
+A relative reference can't be resolved against an opaque base such as
+`about:blank` or a `data:` URL, because those have no hierarchical path
+to merge with; that is reported as `RelativeWithoutBase`.
+*/
+#[allow(non_implicitly_copyable_typarams)]
+pub fn try_make_url(str_url: ~str, current_url: Option<Url>) -> Result<Url, UrlError> {
+    match current_url {
+        Some(ref base) => resolve_against(str_url, Some(base)),
+        None => resolve_against(str_url, None)
+    }
+}
+
+/**
+Like `make_url`, but returns `fallback` instead of failing when the string
+can't be resolved, e.g. so that a broken link can still be shown with a
+placeholder.
+*/
+#[allow(non_implicitly_copyable_typarams)]
+pub fn make_url_or(str_url: ~str, current_url: Option<Url>, fallback: Url) -> Url {
+    match try_make_url(move str_url, move current_url) {
+        Ok(move url) => move url,
+        Err(move e) => {
+            debug!("make_url_or: using fallback, because of %?", e);
+            move fallback
+        }
+    }
+}
+
+/**
+Like `try_make_url` with a base URL, but borrows the base instead of taking
+it, so that many strings can be resolved against one base without copying
+it each time.
+*/
+pub fn resolve(base: &Url, str_url: &str) -> Result<Url, UrlError> {
+    resolve_against(str_url, Some(base))
+}
+
+fn resolve_against(str_url: &str, base: Option<&Url>) -> Result<Url, UrlError> {
+    // A drive letter looks like a one-letter scheme, so Windows paths are
+    // recognized before looking for a scheme.
+    let str_url = match windows_file_url(str_url) {
+        Some(move file_url) => move file_url,
+        None => str::from_slice(str_url)
+    };
+    // Browsers treat backslashes in URLs of the special schemes as slashes.
+    let str_url = match url::get_scheme(str_url) {
+        Ok((ref scheme, _)) if is_special_scheme(*scheme) => backslashes_to_slashes(str_url),
+        _ => move str_url
+    };
+    match opaque_url(str_url) {
+        Some(move url) => return Ok(move url),
+        None => {}
+    }
+    let str_url = match classify_reference(str_url) {
+        AbsoluteReference => move str_url,
+        kind => match base {
             // If all we have is a filename, assume it's a local relative file
             // and build an absolute path with the cwd
-            ~"file://" + os::getcwd().push(str_url).to_str()
-        } else {
-            let current_url = current_url.get();
-            debug!("make_url: current_url: %?", current_url);
-            if current_url.path.is_empty() || current_url.path.ends_with("/") {
-                current_url.scheme + "://" + current_url.host + "/" + str_url
-            } else {
-                let path = str::split_char(current_url.path, '/');
-                let path = path.init();
-                let path = str::connect(path + ~[move str_url], "/");
+            None => ~"file://" + os::getcwd().push(str_url).to_str(),
+            Some(base) => {
+                debug!("make_url: current_url: %?", *base);
+                if is_opaque(base) {
+                    return Err(RelativeWithoutBase);
+                }
+                resolve_reference(base, kind, str_url)
+            }
+        }
+    };
+
+    match url::from_str(str_url) {
+        Ok(move url) => Ok(move url),
+        Err(move e) => Err(ParseError(move e))
+    }
+}
+
+// Reads the backslashes of `str_url` as slashes, except those in its query
+// and fragment.
+priv fn backslashes_to_slashes(str_url: &str) -> ~str {
+    let end = str::find(str_url, |c| c == '?' || c == '#').get_default(str_url.len());
+    str::replace(str::slice(str_url, 0, end), "\\", "/") + str::slice(str_url, end, str_url.len())
+}
+
+/**
+Like `try_make_url`, but refuses to let a document loaded over the network
+refer to local files: if the base URL has a network scheme such as `http`
+and the string resolves to a `file` URL, the result is `FileFromNetwork`.
+This is what the loader uses for the resources that pages refer to.
+*/
+#[allow(non_implicitly_copyable_typarams)]
+pub fn try_make_url_strict(str_url: ~str, current_url: Option<Url>) -> Result<Url, UrlError> {
+    let from_network = match current_url {
+        Some(ref base) => is_network_scheme(base.scheme),
+        None => false
+    };
+    let result = try_make_url(move str_url, move current_url);
+    let refused = match result {
+        Ok(ref url) => from_network && str::to_lower(url.scheme) == ~"file",
+        Err(*) => false
+    };
+    if refused { Err(FileFromNetwork) } else { move result }
+}
+
+/// The forms of URL reference, by what they replace in the base URL
+/// (RFC 3986, Section 4.2).
+pub enum ReferenceKind {
+    /// `http://example.com/a`: replaces everything
+    AbsoluteReference,
+    /// `//example.com/a`: replaces everything but the scheme
+    NetworkPathReference,
+    /// `/a/b`: replaces the path, query and fragment
+    AbsolutePathReference,
+    /// `a/b`: is merged with the directory of the base's path
+    RelativePathReference,
+    /// `?q`: replaces the query and fragment
+    QueryReference,
+    /// `#f`: replaces the fragment
+    FragmentReference,
+    /// the empty string: refers to the base, without its fragment
+    EmptyReference
+}
+
+impl ReferenceKind : cmp::Eq {
+    pure fn eq(&self, other: &ReferenceKind) -> bool {
+        (*self as uint) == (*other as uint)
+    }
+    pure fn ne(&self, other: &ReferenceKind) -> bool {
+        !(*self).eq(other)
+    }
+}
+
+/// Classifies a URL reference by its form, without resolving it.
+pub fn classify_reference(reference: &str) -> ReferenceKind {
+    if reference.is_empty() {
+        EmptyReference
+    } else if url::get_scheme(reference).is_ok() {
+        AbsoluteReference
+    } else if reference.starts_with("//") {
+        NetworkPathReference
+    } else if reference.starts_with("/") {
+        AbsolutePathReference
+    } else if reference.starts_with("?") {
+        QueryReference
+    } else if reference.starts_with("#") {
+        FragmentReference
+    } else {
+        RelativePathReference
+    }
+}
 
-                current_url.scheme + "://" + current_url.host + path
+/// Whether the scheme of `url` is one of `allowed`, ignoring case.
+pub fn is_scheme_allowed(url: &Url, allowed: &[~str]) -> bool {
+    let scheme = str::to_lower(url.scheme);
+    allowed.any(|allowed_scheme| str::to_lower(*allowed_scheme) == scheme)
+}
+
+/**
+Like `try_make_url`, but only resolves to URLs whose scheme is one of
+`allowed`; others are reported as `SchemeNotAllowed`. This is a policy
+check, for sandboxed content; it doesn't affect how the URL is resolved.
+*/
+#[allow(non_implicitly_copyable_typarams)]
+pub fn try_make_url_allowed(str_url: ~str, current_url: Option<Url>,
+                            allowed: &[~str]) -> Result<Url, UrlError> {
+    match try_make_url(move str_url, move current_url) {
+        Ok(move url) => {
+            if is_scheme_allowed(&url, allowed) {
+                Ok(move url)
+            } else {
+                Err(SchemeNotAllowed(copy url.scheme))
             }
         }
+        Err(move e) => Err(move e)
+    }
+}
+
+/**
+Resolves a relative reference of the given kind against a hierarchical base
+URL, as a string (RFC 3986, Section 5.2.2). The base's query is only kept
+by fragment and empty references, and its fragment is never kept.
+*/
+fn resolve_reference(base: &Url, kind: ReferenceKind, reference: &str) -> ~str {
+    let base_query = if base.query.is_empty() {
+        ~""
     } else {
-        move str_url
+        ~"?" + url::query_to_str(&base.query)
+    };
+    // dot segments are only removed from the reference's path, not from
+    // its query or fragment
+    let path_end = str::find(reference, |c| c == '?' || c == '#').get_default(reference.len());
+    let ref_path = str::slice(reference, 0, path_end);
+    let ref_rest = str::slice(reference, path_end, reference.len());
+    let path = match kind {
+        // a network-path reference replaces the host, too
+        NetworkPathReference => return base.scheme + ":" + reference,
+        AbsoluteReference => return str::from_slice(reference),
+        AbsolutePathReference => remove_dot_segments(ref_path) + ref_rest,
+        RelativePathReference => remove_dot_segments(merge_paths(base, ref_path)) + ref_rest,
+        QueryReference => base.path + reference,
+        FragmentReference => base.path + base_query + reference,
+        EmptyReference => base.path + base_query
     };
 
-    // FIXME: Need to handle errors
-    url::from_str(str_url).get()
+    let authority = match base.port {
+        Some(ref port) => base.host + ":" + *port,
+        None => copy base.host
+    };
+    base.scheme + "://" + authority + path
+}
+
+/// Appends a relative-path reference to the directory of the base's path
+/// (RFC 3986, Section 5.2.3). Empty segments are kept.
+fn merge_paths(base: &Url, reference: &str) -> ~str {
+    match str::rfind_char(base.path, '/') {
+        Some(last_slash) => str::slice(base.path, 0, last_slash + 1) + reference,
+        None => ~"/" + reference
+    }
+}
+
+/**
+Converts a Windows path with a drive letter (`C:\site\index.html`), or
+a UNC path (`\\server\share\index.html`), to the string of a `file` URL.
+*/
+#[cfg(target_os = "win32")]
+fn windows_file_url(path: &str) -> Option<~str> {
+    let slashed = str::replace(path, "\\", "/");
+    if is_drive_path(path) {
+        Some(~"file:///" + slashed)
+    } else if path.starts_with("\\\\") {
+        // the server name becomes the host
+        Some(~"file:" + slashed)
+    } else {
+        None
+    }
+}
+
+#[cfg(target_os = "win32")]
+fn is_drive_path(path: &str) -> bool {
+    path.len() >= 3 && char::is_alphabetic(path[0] as char) && path[1] == ':' as u8
+        && (path[2] == '\\' as u8 || path[2] == '/' as u8)
+}
+
+#[cfg(not(target_os = "win32"))]
+fn windows_file_url(_path: &str) -> Option<~str> { None }
+
+/// Whether `scheme` is one of the schemes with hierarchical, host-based URLs
+/// that browsers give special treatment to.
+fn is_special_scheme(scheme: &str) -> bool {
+    match str::to_lower(scheme) {
+        ~"http" | ~"https" | ~"ws" | ~"wss" | ~"ftp" | ~"file" => true,
+        _ => false
+    }
+}
+
+/// Whether URLs of `scheme` are fetched over the network.
+fn is_network_scheme(scheme: &str) -> bool {
+    match str::to_lower(scheme) {
+        ~"http" | ~"https" | ~"ws" | ~"wss" | ~"ftp" => true,
+        _ => false
+    }
+}
+
+/**
+Parses a URL of a non-special scheme and no authority, like
+`javascript:alert(1)`, whose whole part after the colon is an opaque path.
+The path is kept verbatim: unlike with `url::from_str`, no query or
+fragment is split off, and nothing is decoded. None for other URLs, and
+for strings that don't parse as URLs at all.
+*/
+fn opaque_url(str_url: &str) -> Option<Url> {
+    let (scheme, rest) = match url::get_scheme(str_url) {
+        Ok(move parts) => move parts,
+        Err(*) => return None
+    };
+    if is_special_scheme(scheme) || rest.starts_with("//") || url::from_str(str_url).is_err() {
+        return None;
+    }
+    Some(Url(move scheme, None, ~"", None, move rest, ~[], None))
+}
+
+/// Whether a URL has an opaque (non-hierarchical) path, like `about:blank`.
+fn is_opaque(url: &Url) -> bool {
+    url.host.is_empty() && !url.path.starts_with("/")
+}
+
+/**
+Resolve a URL string as `make_url` does, and return its path as a list
+of percent-decoded segments, with `.` and `..` segments resolved as in
+RFC 3986, Section 5.2.4.
+*/
+pub fn resolved_path_segments(str_url: ~str, base: Option<Url>) -> Result<~[~str], UrlError> {
+    match try_make_url(move str_url, move base) {
+        Ok(ref url) => Ok(path_segments(url.path)),
+        Err(move e) => Err(move e)
+    }
+}
+
+fn path_segments(path: &str) -> ~[~str] {
+    let mut segments: ~[~str] = ~[];
+    for str::split_char(path, '/').each |segment| {
+        if segment.is_empty() || *segment == ~"." {
+            loop;
+        } else if *segment == ~".." {
+            if segments.is_not_empty() { segments.pop(); }
+        } else {
+            segments.push(url::decode_component(*segment));
+        }
+    }
+    move segments
 }
 
 mod make_url_tests {
@@ -96,6 +402,793 @@ mod make_url_tests {
         assert new_url.path == ~"/snarf/crumpet.html";
     }
 
+    #[test]
+    #[cfg(target_os = "win32")]
+    fn should_create_file_url_from_windows_drive_path() {
+        let url = make_url(~"C:\\site\\index.html", None);
+        assert url.scheme == ~"file";
+        assert url.host == ~"";
+        assert url.path == ~"/C:/site/index.html";
+    }
+
+    #[test]
+    #[cfg(target_os = "win32")]
+    fn should_create_file_url_from_unc_path() {
+        let url = make_url(~"\\\\server\\share\\index.html", None);
+        assert url.scheme == ~"file";
+        assert url.host == ~"server";
+        assert url.path == ~"/share/index.html";
+    }
+
+    #[test]
+    fn should_drop_query_and_fragment_of_old_url() {
+        let old_str = ~"http://example.com/a/b.html?x=1#frag";
+        let old_url = make_url(move old_str, None);
+        let new_str = ~"c.html";
+        let new_url = make_url(move new_str, Some(move old_url));
+        assert new_url.host == ~"example.com";
+        assert new_url.path == ~"/a/c.html";
+        assert new_url.query.is_empty();
+        assert new_url.fragment.is_none();
+    }
+
+    #[test]
+    fn should_resolve_against_directory_of_old_url() {
+        let old_url = make_url(~"http://example.com/a/", None);
+        let new_url = make_url(~"b.html", Some(move old_url));
+        assert new_url.path == ~"/a/b.html";
+    }
+
+    #[test]
+    fn should_keep_empty_segments_of_old_url() {
+        let old_url = make_url(~"http://example.com/a//b/c.html", None);
+        let new_url = make_url(~"d.html", Some(move old_url));
+        assert new_url.path == ~"/a//b/d.html";
+    }
+
+    #[test]
+    fn should_keep_port_of_old_url() {
+        let old_url = make_url(~"http://example.com:8080/a/b.html", None);
+        let new_url = make_url(~"c.html", Some(move old_url));
+        assert new_url.port == Some(~"8080");
+        assert new_url.path == ~"/a/c.html";
+    }
+
+    #[test]
+    fn should_replace_path_for_reference_starting_with_slash() {
+        let old_url = make_url(~"http://example.com/a//b/c.html", None);
+        let new_url = make_url(~"/d/e.html", Some(move old_url));
+        assert new_url.host == ~"example.com";
+        assert new_url.path == ~"/d/e.html";
+    }
+
+    #[test]
+    fn should_not_remove_dot_segments_from_query_or_fragment() {
+        let old_url = make_url(~"http://example.com/a/b.html", None);
+        let new_url = make_url(~"../c.html?next=/x/../y#/z/..", Some(move old_url));
+        assert new_url.path == ~"/c.html";
+        assert new_url.query == ~[(~"next", ~"/x/../y")];
+        assert new_url.fragment == Some(~"/z/..");
+    }
+
+    #[test]
+    fn should_not_remove_dot_segments_from_query_of_absolute_path() {
+        let old_url = make_url(~"http://example.com/a/b.html", None);
+        let new_url = make_url(~"/d/./e.html?p=a/../b", Some(move old_url));
+        assert new_url.path == ~"/d/e.html";
+        assert new_url.query == ~[(~"p", ~"a/../b")];
+    }
+
+    #[test]
+    fn should_replace_host_for_reference_starting_with_two_slashes() {
+        let old_url = make_url(~"http://example.com/a/b.html", None);
+        let new_url = make_url(~"//example.org/c.html", Some(move old_url));
+        assert new_url.scheme == ~"http";
+        assert new_url.host == ~"example.org";
+        assert new_url.path == ~"/c.html";
+    }
+
+    #[test]
+    fn should_keep_trailing_slashes_of_reference() {
+        let old_url = make_url(~"http://example.com/a/b.html", None);
+        let new_url = make_url(~"c//", Some(move old_url));
+        assert new_url.path == ~"/a/c//";
+    }
+
+    #[test]
+    fn should_treat_backslashes_in_http_url_as_slashes() {
+        let url = make_url(~"http://example.com\\a\\b.html", None);
+        assert url.host == ~"example.com";
+        assert url.path == ~"/a/b.html";
+    }
+
+    #[test]
+    fn should_keep_backslashes_in_query_and_fragment() {
+        let url = make_url(~"http://example.com\\a?x=b\\c#d\\e", None);
+        assert url.path == ~"/a";
+        assert url.query == ~[(~"x", ~"b\\c")];
+        assert url.fragment == Some(~"d\\e");
+    }
+
+    #[test]
+    fn should_leave_backslashes_in_other_schemes() {
+        let url = make_url(~"mailto:a\\b@example.com", None);
+        assert url.scheme == ~"mailto";
+        assert str::contains(url.path, "\\");
+    }
+
+    #[test]
+    fn should_keep_opaque_path_verbatim() {
+        let base = make_url(~"http://example.com/a/b.html", None);
+        let url = make_url(~"javascript:alert('a?b#c%20d')", Some(move base));
+        assert url.scheme == ~"javascript";
+        assert url.host.is_empty();
+        assert url.path == ~"alert('a?b#c%20d')";
+        assert url.query.is_empty();
+        assert url.fragment.is_none();
+    }
+
+    #[test]
+    fn should_replace_only_query_for_query_reference() {
+        let old_url = make_url(~"http://example.com/a/b.html?x=1#frag", None);
+        let new_url = make_url(~"?y=2", Some(move old_url));
+        assert new_url.path == ~"/a/b.html";
+        assert new_url.query == ~[(~"y", ~"2")];
+        assert new_url.fragment.is_none();
+    }
+
+    #[test]
+    fn should_resolve_against_borrowed_base() {
+        let base = make_url(~"http://example.com/a/b.html", None);
+        assert resolve(&base, "c.html").get() == make_url(~"c.html", Some(copy base));
+        assert resolve(&base, "../d.html").get().path == ~"/d.html";
+        assert base.path == ~"/a/b.html";
+    }
+
+    #[test]
+    fn should_keep_query_for_fragment_reference() {
+        let old_url = make_url(~"http://example.com/a/b.html?x=1#frag", None);
+        let new_url = make_url(~"#other", Some(copy old_url));
+        assert new_url.path == ~"/a/b.html";
+        assert new_url.fragment == Some(~"other");
+        assert is_same_document(&new_url, &old_url);
+    }
+
+    #[test]
+    fn should_not_resolve_relative_url_against_opaque_base() {
+        let old_url = make_url(~"about:blank", None);
+        match try_make_url(~"x.html", Some(move old_url)) {
+            Err(RelativeWithoutBase) => {}
+            _ => fail ~"expected RelativeWithoutBase"
+        }
+    }
+
+    #[test]
+    fn should_refuse_file_url_from_network_base_when_strict() {
+        let base = make_url(~"http://example.com/index.html", None);
+        match try_make_url_strict(~"file:///etc/passwd", Some(copy base)) {
+            Err(FileFromNetwork) => {}
+            _ => fail ~"expected FileFromNetwork"
+        }
+        // the default resolver still allows it
+        let url = make_url(~"file:///etc/passwd", Some(move base));
+        assert url.scheme == ~"file";
+    }
+
+    #[test]
+    fn should_allow_file_url_from_file_base_when_strict() {
+        let base = make_url(~"file:///home/user/index.html", None);
+        let url = try_make_url_strict(~"file:///home/user/a.png", Some(move base)).get();
+        assert url.path == ~"/home/user/a.png";
+    }
+
+    #[test]
+    fn should_use_fallback_for_unresolvable_url() {
+        let fallback = make_url(~"about:blank", None);
+        let base = make_url(~"about:blank", None);
+        let url = make_url_or(~"x.html", Some(move base), copy fallback);
+        assert url.to_str() == fallback.to_str();
+
+        let base = make_url(~"http://example.com/", None);
+        let url = make_url_or(~"x.html", Some(move base), move fallback);
+        assert url.to_str() == ~"http://example.com/x.html";
+    }
+
+}
+
+mod resolve_benchmark {
+    use std::time::precise_time_ns;
+
+    /*
+    Measures how fast a realistic mix of references is resolved against one
+    base. It's ignored by default; run the tests with `--ignored` to see the
+    report. Every result is checked, so that none of the work can be skipped.
+    */
+    #[test]
+    #[ignore]
+    fn bench_resolve_mixed_references() {
+        let base = make_url(~"http://www.example.com/articles/2012/index.html?page=2", None);
+        let cases = [
+            (~"https://cdn.example.net/lib.js", ~"/lib.js"),
+            (~"images/header.png", ~"/articles/2012/images/header.png"),
+            (~"../../style/main.css", ~"/style/main.css"),
+            (~"./a/../b.html", ~"/articles/2012/b.html"),
+            (~"/about.html", ~"/about.html"),
+            (~"?page=3", ~"/articles/2012/index.html"),
+            (~"#comments", ~"/articles/2012/index.html"),
+            (~"//static.example.com/x.gif", ~"/x.gif")
+        ];
+        let iterations = 5000;
+
+        let start = precise_time_ns();
+        let mut checked = 0;
+        for uint::range(0, iterations) |_i| {
+            for cases.each |case| {
+                let (reference, expected_path) = copy *case;
+                let url = resolve(&base, reference).get();
+                assert url.path == expected_path;
+                checked += 1;
+            }
+        }
+        let elapsed_ns = precise_time_ns() - start;
+
+        assert checked == iterations * cases.len();
+        io::println(fmt!("resolve: %u references in %u ms (%u ns each)",
+                         checked, (elapsed_ns / 1000000u64) as uint,
+                         (elapsed_ns / (checked as u64)) as uint));
+    }
+}
+
+mod scheme_allowlist_tests {
+
+    #[test]
+    fn should_allow_listed_schemes_ignoring_case() {
+        let allowed = [~"http", ~"https"];
+        assert is_scheme_allowed(&make_url(~"http://example.com/", None), allowed);
+        assert is_scheme_allowed(&make_url(~"HTTPS://example.com/", None), allowed);
+        assert !is_scheme_allowed(&make_url(~"file:///etc/passwd", None), allowed);
+    }
+
+    #[test]
+    fn should_reject_unlisted_scheme_when_resolving() {
+        let allowed = [~"http", ~"https"];
+        let base = make_url(~"http://example.com/a.html", None);
+        assert try_make_url_allowed(~"b.html", Some(copy base), allowed).is_ok();
+        match try_make_url_allowed(~"file:///etc/passwd", Some(move base), allowed) {
+            Err(SchemeNotAllowed(ref scheme)) => assert *scheme == ~"file",
+            _ => fail ~"expected SchemeNotAllowed"
+        }
+    }
+}
+
+mod classify_reference_tests {
+
+    #[test]
+    fn should_classify_absolute_reference() {
+        assert classify_reference("http://example.com/a") == AbsoluteReference;
+        assert classify_reference("mailto:a@example.com") == AbsoluteReference;
+    }
+
+    #[test]
+    fn should_classify_network_path_reference() {
+        assert classify_reference("//example.com/a") == NetworkPathReference;
+    }
+
+    #[test]
+    fn should_classify_absolute_path_reference() {
+        assert classify_reference("/a/b.html") == AbsolutePathReference;
+    }
+
+    #[test]
+    fn should_classify_relative_path_reference() {
+        assert classify_reference("a/b.html") == RelativePathReference;
+        assert classify_reference("../b.html") == RelativePathReference;
+    }
+
+    #[test]
+    fn should_classify_query_reference() {
+        assert classify_reference("?q=1") == QueryReference;
+    }
+
+    #[test]
+    fn should_classify_fragment_reference() {
+        assert classify_reference("#top") == FragmentReference;
+    }
+
+    #[test]
+    fn should_classify_empty_reference() {
+        assert classify_reference("") == EmptyReference;
+    }
+}
+
+mod resolved_path_segments_tests {
+
+    #[test]
+    fn should_resolve_dot_segments() {
+        let base = make_url(~"http://example.com/", None);
+        let segments = resolved_path_segments(~"a/../b/c.html", Some(move base)).get();
+        assert segments == ~[~"b", ~"c.html"];
+    }
+
+    #[test]
+    fn should_decode_segments() {
+        let base = make_url(~"http://example.com/", None);
+        let segments = resolved_path_segments(~"my%20docs/./index.html", Some(move base)).get();
+        assert segments == ~[~"my docs", ~"index.html"];
+    }
+
+}
+
+/**
+Whether two URLs share an origin: the same scheme, the same host
+(compared case-insensitively), and the same port once the scheme's
+default port is taken into account.
+*/
+pub fn same_origin(a: &Url, b: &Url) -> bool {
+    str::to_lower(a.scheme) == str::to_lower(b.scheme)
+        && str::to_lower(a.host) == str::to_lower(b.host)
+        && effective_port(a) == effective_port(b)
+}
+
+fn effective_port(url: &Url) -> Option<u16> {
+    match url.port {
+        Some(ref port) => u16::from_str(*port),
+        None => default_port(url.scheme)
+    }
+}
+
+/// The port used for `scheme` when a URL doesn't name one, if known.
+pub fn default_port(scheme: &str) -> Option<u16> {
+    match str::to_lower(scheme) {
+        ~"http" | ~"ws" => Some(80),
+        ~"https" | ~"wss" => Some(443),
+        ~"ftp" => Some(21),
+        _ => None
+    }
+}
+
+/**
+Rewrites a URL into the normal form used to compare URLs: the scheme and
+host are lowercased, the host is converted to ASCII as by `to_ascii_host`,
+and a port that is the scheme's default is dropped.
+*/
+pub fn normalize_url(url: &Url) -> Url {
+    let scheme = str::to_lower(url.scheme);
+    let port = match url.port {
+        Some(ref port) if u16::from_str(*port) == default_port(scheme) => None,
+        _ => copy url.port
+    };
+    Url(move scheme, copy url.user, to_ascii_host(str::to_lower(url.host)), move port,
+        copy url.path, copy url.query, copy url.fragment)
+}
+
+/**
+Converts a host name to the ASCII form used on the network. An ASCII host
+is returned as it is.
+
+FIXME: Internationalized domain names should be converted label by label
+to punycode (IDNA, RFC 3490), e.g. `例え.jp` to `xn--r8jz45g.jp`. There is
+no IDNA implementation yet, so non-ASCII hosts are passed through too.
+*/
+pub fn to_ascii_host(host: &str) -> ~str {
+    if str::is_ascii(host) {
+        return str::from_slice(host);
+    }
+    // IDNA conversion goes here.
+    str::from_slice(host)
+}
+
+/**
+Serializes a URL in its canonical form: normalized as by `normalize_url`,
+and with `.` and `..` segments removed from its path. This is the form
+used for `UrlMap` keys and for display.
+*/
+pub fn url_to_string(url: &Url) -> ~str {
+    let normal = normalize_url(url);
+    let path = remove_dot_segments(normal.path);
+    url::to_str(Url(copy normal.scheme, copy normal.user, copy normal.host, copy normal.port,
+                     move path, copy normal.query, copy normal.fragment))
+}
+
+/**
+Whether two URLs are the same once normalized as by `normalize_url`, with an
+empty path taken to be `/`. Unlike `Url` equality, this makes `http://x.com`,
+`http://x.com/` and `http://x.com:80/` all equal.
+*/
+pub fn url_eq_loose(a: &Url, b: &Url) -> bool {
+    let a = normalize_url(a);
+    let b = normalize_url(b);
+    a.scheme == b.scheme
+        && a.user == b.user
+        && a.host == b.host
+        && a.port == b.port
+        && loose_path(a.path) == loose_path(b.path)
+        && a.query == b.query
+        && a.fragment == b.fragment
+}
+
+fn loose_path(path: &str) -> ~str {
+    if path.is_empty() { ~"/" } else { str::from_slice(path) }
+}
+
+/**
+Removes the `.` and `..` segments of an absolute path (RFC 3986, Section
+5.2.4). Unlike `path_segments`, this leaves the segments encoded, and keeps
+empty segments and a trailing slash. Other paths are returned as they are.
+*/
+fn remove_dot_segments(path: &str) -> ~str {
+    if !path.starts_with("/") {
+        return str::from_slice(path);
+    }
+    let parts = str::split_char(path, '/').tail();
+    let mut segments: ~[~str] = ~[];
+    for parts.each |segment| {
+        if *segment == ~"." {
+            loop;
+        } else if *segment == ~".." {
+            if segments.is_not_empty() { segments.pop(); }
+        } else {
+            segments.push(copy *segment);
+        }
+    }
+    // a path ending in `.` or `..` names a directory
+    match parts.last() {
+        ~"." | ~".." => segments.push(~""),
+        _ => {}
+    }
+    ~"/" + str::connect(segments, "/")
+}
+
+mod same_origin_tests {
+
+    #[test]
+    fn should_match_identical_origins() {
+        let a = make_url(~"http://example.com/a.html", None);
+        let b = make_url(~"http://EXAMPLE.com/b/c.html", None);
+        assert same_origin(&a, &b);
+    }
+
+    #[test]
+    fn should_match_default_port() {
+        let a = make_url(~"http://example.com/", None);
+        let b = make_url(~"http://example.com:80/", None);
+        assert same_origin(&a, &b);
+    }
+
+    #[test]
+    fn should_not_match_different_scheme() {
+        let a = make_url(~"http://example.com/", None);
+        let b = make_url(~"https://example.com/", None);
+        assert !same_origin(&a, &b);
+    }
+
+    #[test]
+    fn should_not_match_different_port() {
+        let a = make_url(~"http://example.com/", None);
+        let b = make_url(~"http://example.com:8080/", None);
+        assert !same_origin(&a, &b);
+    }
+
+    #[test]
+    fn should_not_match_different_host() {
+        let a = make_url(~"http://example.com/", None);
+        let b = make_url(~"http://example.org/", None);
+        assert !same_origin(&a, &b);
+    }
+
+}
+
+/**
+Whether `resolved` refers to the same document as `base`, differing from it
+at most in the fragment, so that navigating to it is an in-page jump rather
+than a new load.
+*/
+pub fn is_same_document(resolved: &Url, base: &Url) -> bool {
+    resolved.scheme == base.scheme
+        && resolved.user == base.user
+        && resolved.host == base.host
+        && resolved.port == base.port
+        && resolved.path == base.path
+        && resolved.query == base.query
+}
+
+mod is_same_document_tests {
+
+    #[test]
+    fn should_match_fragment_only_change() {
+        let base = make_url(~"http://example.com/a/b.html?x=1", None);
+        let resolved = make_url(~"http://example.com/a/b.html?x=1#section", None);
+        assert is_same_document(&resolved, &base);
+    }
+
+    #[test]
+    fn should_not_match_path_change() {
+        let base = make_url(~"http://example.com/a/b.html", None);
+        let resolved = make_url(~"http://example.com/a/c.html", None);
+        assert !is_same_document(&resolved, &base);
+    }
+}
+
+mod default_port_tests {
+
+    #[test]
+    fn should_know_common_schemes() {
+        assert default_port("http") == Some(80);
+        assert default_port("https") == Some(443);
+        assert default_port("ftp") == Some(21);
+        assert default_port("ws") == Some(80);
+        assert default_port("wss") == Some(443);
+        assert default_port("HTTP") == Some(80);
+    }
+
+    #[test]
+    fn should_not_know_other_schemes() {
+        assert default_port("gopher") == None;
+    }
+
+    #[test]
+    fn should_drop_default_port_when_normalizing() {
+        let url = normalize_url(&make_url(~"HTTP://Example.COM:80/a", None));
+        assert url.scheme == ~"http";
+        assert url.host == ~"example.com";
+        assert url.port == None;
+        assert url.path == ~"/a";
+    }
+
+    #[test]
+    fn should_keep_ascii_host() {
+        assert to_ascii_host("www.example.com") == ~"www.example.com";
+    }
+
+    #[test]
+    fn should_keep_other_ports_when_normalizing() {
+        let url = normalize_url(&make_url(~"http://example.com:8080/", None));
+        assert url.port == Some(~"8080");
+    }
+}
+
+mod url_to_string_tests {
+
+    #[test]
+    fn should_serialize_canonical_form() {
+        let url = make_url(~"HTTP://Example.COM:80/a/./b/../c.html?x=1#top", None);
+        assert url_to_string(&url) == ~"http://example.com/a/c.html?x=1#top";
+    }
+
+    #[test]
+    fn should_keep_trailing_slash() {
+        let url = make_url(~"http://example.com/a/b/..", None);
+        assert url_to_string(&url) == ~"http://example.com/a/";
+    }
+
+    #[test]
+    fn should_round_trip() {
+        let url = make_url(~"https://Example.com:8443/x/../y/?q=1#f", None);
+        let string = url_to_string(&url);
+        let reparsed = make_url(copy string, None);
+        assert reparsed == make_url(~"https://example.com:8443/y/?q=1#f", None);
+        assert url_to_string(&reparsed) == string;
+    }
+}
+
+mod url_eq_loose_tests {
+
+    fn loosely_equal(a: ~str, b: ~str) -> bool {
+        url_eq_loose(&make_url(move a, None), &make_url(move b, None))
+    }
+
+    #[test]
+    fn should_treat_empty_path_as_slash() {
+        assert loosely_equal(~"http://x.com", ~"http://x.com/");
+    }
+
+    #[test]
+    fn should_ignore_default_port() {
+        assert loosely_equal(~"http://x.com/", ~"http://x.com:80/");
+        assert loosely_equal(~"http://x.com", ~"http://x.com:80/");
+    }
+
+    #[test]
+    fn should_not_match_different_urls() {
+        assert !loosely_equal(~"http://x.com/", ~"http://x.com:8080/");
+        assert !loosely_equal(~"http://x.com/", ~"http://x.com/a");
+    }
+}
+
+/**
+Assembles a URL from its components, for code that would otherwise
+concatenate strings and parse the result. Setters may be called in any
+order; unset components are left empty.
+*/
+pub struct UrlBuilder {
+    priv mut scheme: Option<~str>,
+    priv mut host: ~str,
+    priv mut port: Option<u16>,
+    priv mut path_segments: ~[~str],
+    priv mut query: ~[(~str, ~str)],
+    priv mut fragment: Option<~str>
+}
+
+pub fn UrlBuilder() -> UrlBuilder {
+    UrlBuilder {
+        scheme: None,
+        host: ~"",
+        port: None,
+        path_segments: ~[],
+        query: ~[],
+        fragment: None
+    }
+}
+
+pub impl UrlBuilder {
+    fn scheme(&self, scheme: &str) {
+        self.scheme = Some(str::from_slice(scheme));
+    }
+
+    fn host(&self, host: &str) {
+        self.host = str::from_slice(host);
+    }
+
+    fn port(&self, port: u16) {
+        self.port = Some(port);
+    }
+
+    /// The path, as unencoded segments; `~[~"a", ~"b"]` is `/a/b`.
+    fn path_segments(&self, segments: &[~str]) {
+        self.path_segments = vec::from_slice(segments);
+    }
+
+    fn query(&self, query: &[(~str, ~str)]) {
+        self.query = vec::from_slice(query);
+    }
+
+    fn fragment(&self, fragment: &str) {
+        self.fragment = Some(str::from_slice(fragment));
+    }
+
+    /**
+    Builds the URL. The components are serialized and parsed again, so
+    that the result is exactly the URL that its string form would give.
+    */
+    fn build(&self) -> Result<Url, UrlError> {
+        let scheme = match copy self.scheme {
+            Some(move scheme) => move scheme,
+            None => return Err(ParseError(~"URL has no scheme"))
+        };
+        let path = if self.path_segments.is_empty() {
+            ~""
+        } else {
+            let encoded = self.path_segments.map(|segment| url::encode_component(*segment));
+            ~"/" + str::connect(encoded, "/")
+        };
+        let url = Url(move scheme, None, copy self.host, self.port.map(|port| port.to_str()),
+                      move path, copy self.query, copy self.fragment);
+        match url::from_str(url::to_str(move url)) {
+            Ok(move url) => Ok(move url),
+            Err(move e) => Err(ParseError(move e))
+        }
+    }
+}
+
+mod url_builder_tests {
+
+    #[test]
+    fn should_build_each_component() {
+        let builder = UrlBuilder();
+        builder.scheme("https");
+        builder.host("example.com");
+        builder.port(8443);
+        builder.path_segments([~"a", ~"b"]);
+        builder.query([(~"x", ~"1")]);
+        builder.fragment("f");
+        let url = builder.build().get();
+
+        assert url.scheme == ~"https";
+        assert url.host == ~"example.com";
+        assert url.port == Some(~"8443");
+        assert url.path == ~"/a/b";
+        assert url.query == ~[(~"x", ~"1")];
+        assert url.fragment == Some(~"f");
+        assert url == make_url(~"https://example.com:8443/a/b?x=1#f", None);
+    }
+
+    #[test]
+    fn should_require_scheme() {
+        let builder = UrlBuilder();
+        builder.host("example.com");
+        assert builder.build().is_err();
+    }
+}
+
+/**
+The name-value pairs of a URL's query, in order, percent-decoded. Pairs
+are separated by `&` or `;`; a name without `=` gets an empty value, and
+every pair of a repeated name is kept.
+
+`url::from_str` has already split the query on `&` and the first `=`, and
+decoded it, so only the `;` separators are left to split on here. A `;`
+that was encoded as `%3B` can't be told from a separator any more, and is
+split on too; use `parse_query_pairs` on the query as written to keep it.
+*/
+pub fn query_pairs(url: &Url) -> ~[(~str, ~str)] {
+    let mut pairs = ~[];
+    for url.query.each |pair| {
+        let (name, value) = copy *pair;
+        if !str::contains_char(name, ';') && !str::contains_char(value, ';') {
+            pairs.push((move name, move value));
+            loop;
+        }
+        let joined = if value.is_empty() { move name } else { name + "=" + value };
+        for str::split_char(joined, ';').each |part| {
+            if part.is_empty() {
+                loop;
+            }
+            pairs.push(match str::find_char(*part, '=') {
+                Some(i) => (part.slice(0, i), part.slice(i + 1, part.len())),
+                None => (copy *part, ~"")
+            });
+        }
+    }
+    move pairs
+}
+
+/**
+Like `query_pairs`, but for a raw query string, such as the part of a URL
+string between `?` and `#`. The string is split before it is decoded, so
+an encoded `%3B` or `%26` stays in its value.
+*/
+pub fn parse_query_pairs(raw_query: &str) -> ~[(~str, ~str)] {
+    let mut pairs = ~[];
+    for str::split(raw_query, |c| c == '&' || c == ';').each |part| {
+        if part.is_empty() {
+            loop;
+        }
+        let (name, value) = match str::find_char(*part, '=') {
+            Some(i) => (part.slice(0, i), part.slice(i + 1, part.len())),
+            None => (copy *part, ~"")
+        };
+        pairs.push((url::decode_component(name), url::decode_component(value)));
+    }
+    move pairs
+}
+
+mod query_pairs_tests {
+    #[test]
+    fn should_split_pairs_of_url() {
+        let url = make_url(~"http://example.com/?a=1;a=2&b=x%20y", None);
+        assert query_pairs(&url) == ~[(~"a", ~"1"), (~"a", ~"2"), (~"b", ~"x y")];
+    }
+
+    #[test]
+    fn should_split_encoded_semicolon_of_url() {
+        let url = make_url(~"http://example.com/?a=1%3Bb", None);
+        assert query_pairs(&url) == ~[(~"a", ~"1"), (~"b", ~"")];
+    }
+
+    #[test]
+    fn should_split_pairs() {
+        assert parse_query_pairs("a=1&b=2") == ~[(~"a", ~"1"), (~"b", ~"2")];
+    }
+
+    #[test]
+    fn should_give_valueless_name_an_empty_value() {
+        assert parse_query_pairs("flag&a=1") == ~[(~"flag", ~""), (~"a", ~"1")];
+    }
+
+    #[test]
+    fn should_keep_every_pair_of_repeated_name() {
+        assert parse_query_pairs("a=1;a=2&a=3") == ~[(~"a", ~"1"), (~"a", ~"2"), (~"a", ~"3")];
+    }
+
+    #[test]
+    fn should_decode_names_and_values() {
+        assert parse_query_pairs("a%20b=c%26d") == ~[(~"a b", ~"c&d")];
+    }
+
+    #[test]
+    fn should_not_split_on_encoded_separators() {
+        assert parse_query_pairs("a=1%3Bb%3D2&c=3") == ~[(~"a", ~"1;b=2"), (~"c", ~"3")];
+    }
 }
 
 pub type UrlMap<T: Copy> = HashMap<Url, T>;
@@ -105,3 +1198,40 @@ pub fn url_map<T: Copy>() -> UrlMap<T> {
 
     HashMap::<Url, T>()
 }
+
+/// The entries of a `UrlMap`, in order of the URLs' canonical strings,
+/// so that dumps of the map are reproducible.
+pub fn url_map_entries_sorted<T: Copy>(map: &UrlMap<T>) -> ~[(Url, T)] {
+    let mut keyed = ~[];
+    for map.each |url, value| {
+        keyed.push((url_to_string(&url), url, value));
+    }
+    let sorted = std::sort::merge_sort(keyed, |a, b| {
+        match (a, b) {
+            (&(ref key_a, _, _), &(ref key_b, _, _)) => *key_a <= *key_b
+        }
+    });
+    do sorted.map |entry| {
+        match *entry {
+            (_, ref url, ref value) => (copy *url, copy *value)
+        }
+    }
+}
+
+mod url_map_tests {
+    #[test]
+    fn should_return_entries_sorted_by_url() {
+        let map = url_map();
+        map.insert(make_url(~"http://example.com/c", None), 3);
+        map.insert(make_url(~"http://example.com/a", None), 1);
+        map.insert(make_url(~"http://example.com/b", None), 2);
+
+        let entries = url_map_entries_sorted(&map);
+        let paths = do entries.map |entry| {
+            match *entry {
+                (ref url, value) => (copy url.path, value)
+            }
+        };
+        assert paths == ~[(~"/a", 1), (~"/b", 2), (~"/c", 3)];
+    }
+}
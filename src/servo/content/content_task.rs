@@ -11,6 +11,7 @@ use dom::window::Window;
 use layout::layout_task;
 use layout::layout_task::{AddStylesheet, BuildData, BuildMsg, Damage, LayoutTask};
 use layout::layout_task::{MatchSelectorsDamage, NoDamage, ReflowDamage};
+use layout::traverse::CancelToken;
 use util::task::spawn_listener;
 
 use core::oldcomm::{Port, Chan, listen, select2};
@@ -314,6 +315,7 @@ impl Content {
             window_size: self.window_size,
             content_join_chan: move join_chan,
             damage: replace(&mut self.damage, NoDamage),
+            cancel_token: CancelToken::new(),
         };
 
         self.layout_task.send(BuildMsg(move data));
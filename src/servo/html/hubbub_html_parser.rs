@@ -10,6 +10,7 @@ use resource::resource_task::{Done, Load, Payload, ResourceTask};
 use util::task::{spawn_listener, spawn_conversation};
 
 use core::oldcomm::{Chan, Port};
+use gfx::util::url::try_make_url_strict;
 use html::cssparse::{InlineProvenance, StylesheetProvenance, UrlProvenance, spawn_css_parser};
 use hubbub::hubbub::Attribute;
 use hubbub::hubbub;
@@ -254,8 +255,12 @@ pub fn parse_html(scope: NodeScope,
                         (Some(move rel), Some(move href)) => {
                             if rel == ~"stylesheet" {
                                 debug!("found CSS stylesheet: %s", href);
-                                css_chan.send(CSSTaskNewFile(UrlProvenance(make_url(move href,
-                                                                           Some(copy *url)))));
+                                match try_make_url_strict(copy href, Some(copy *url)) {
+                                    Ok(move css_url) => {
+                                        css_chan.send(CSSTaskNewFile(UrlProvenance(move css_url)))
+                                    }
+                                    Err(*) => debug!("refusing to load stylesheet: %s", href)
+                                }
                             }
                         }
                         _ => {}
@@ -263,11 +268,15 @@ pub fn parse_html(scope: NodeScope,
                 },
                 ~HTMLImageElement(ref d) => {
                     do elem.get_attr(~"src").iter |img_url_str| {
-                        let img_url = make_url(copy *img_url_str, Some(copy *url));
-                        d.image = Some(copy img_url);
-                        // inform the image cache to load this, but don't store a handle.
-                        // TODO (Issue #84): don't prefetch if we are within a <noscript> tag.
-                        image_cache_task.send(image_cache_task::Prefetch(move img_url));
+                        match try_make_url_strict(copy *img_url_str, Some(copy *url)) {
+                            Ok(move img_url) => {
+                                d.image = Some(copy img_url);
+                                // inform the image cache to load this, but don't store a handle.
+                                // TODO (Issue #84): don't prefetch if we are within a <noscript> tag.
+                                image_cache_task.send(image_cache_task::Prefetch(move img_url));
+                            }
+                            Err(*) => debug!("refusing to load image: %s", *img_url_str)
+                        }
                     }
                 }
                 //TODO (Issue #86): handle inline styles ('style' attr)
@@ -345,8 +354,10 @@ pub fn parse_html(scope: NodeScope,
                             match element.get_attr(~"src") {
                                 Some(move src) => {
                                     debug!("found script: %s", src);
-                                    let new_url = make_url(move src, Some(copy *url));
-                                    js_chan.send(JSTaskNewFile(move new_url));
+                                    match try_make_url_strict(copy src, Some(copy *url)) {
+                                        Ok(move new_url) => js_chan.send(JSTaskNewFile(move new_url)),
+                                        Err(*) => debug!("refusing to load script: %s", src)
+                                    }
                                 }
                                 None => {}
                             }
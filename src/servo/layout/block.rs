@@ -3,31 +3,49 @@ use newcss::values::*;
 use geom::point::Point2D;
 use geom::rect::Rect;
 use geom::size::Size2D;
-use gfx::display_list::DisplayList;
+use gfx::color::rgb;
+use gfx::display_list::{DisplayItem, DisplayList};
 use gfx::geometry::Au;
 use layout::box::{RenderBox};
 use layout::context::LayoutContext;
 use layout::display_list_builder::DisplayListBuilder;
-use layout::flow::{FlowContext, FlowTree, InlineBlockFlow, BlockFlow, RootFlow};
+use layout::float::{ClearNone, FloatLeft, FloatRight};
+use layout::flow::{AbsoluteFlow, FlowContext, FlowTree, InlineBlockFlow, BlockFlow, RootFlow};
+use layout::flow::TableFlow;
+use layout::flow::{BorderBox, ContentBox, DeclaredHeight, FlowData, Hidden, Visible};
 use util::tree;
 
 pub struct BlockFlowData {
-    mut box: Option<@RenderBox>
+    mut box: Option<@RenderBox>,
+    // the list item marker (bullet), if this block is a list item. It is
+    // placed outside the content box, to the left.
+    mut marker: Option<@RenderBox>
 }
 
 pub fn BlockFlowData() -> BlockFlowData {
     BlockFlowData {
-        box: None
+        box: None,
+        marker: None
     }
 }
 
+/* The width and height of a disc list item marker, and the gap between
+   the marker and the list item's content.
+   TODO: size markers by the list item's font; support 'list-style-type'. */
+pure fn marker_size() -> Au { Au::from_px(8) }
+
 trait BlockLayout {
     pure fn starts_block_flow() -> bool;
     pure fn with_block_box(@self, fn(box: &@RenderBox) -> ()) -> ();
 
     fn bubble_widths_block(@self, ctx: &LayoutContext);
+    fn intrinsic_widths_block(@self, ctx: &LayoutContext, child_widths: &[(Au, Au)]) -> (Au, Au);
     fn assign_widths_block(@self, ctx: &LayoutContext);
+    fn bubble_widths_shrink_to_fit(@self, ctx: &LayoutContext);
+    fn assign_widths_shrink_to_fit(@self, ctx: &LayoutContext);
     fn assign_height_block(@self, ctx: &LayoutContext);
+    fn assign_height_absolute(@self, ctx: &LayoutContext);
+    fn assign_height_inline_block(@self, ctx: &LayoutContext);
     fn build_display_list_block(@self, a: &DisplayListBuilder, b: &Rect<Au>,
                                 c: &Point2D<Au>, d: &mut DisplayList);
 }
@@ -69,15 +87,31 @@ impl FlowContext : BlockLayout {
     fn bubble_widths_block(@self, ctx: &LayoutContext) {
         assert self.starts_block_flow();
 
+        let mut child_widths = ~[];
+        for FlowTree.each_child(self) |child_ctx| {
+            assert is_block_container_child(child_ctx);
+            child_widths.push((child_ctx.d().min_width, child_ctx.d().pref_width));
+        }
+
+        // used widths are never negative (CSS 2.1, 10.2)
+        let (min_width, pref_width) = self.intrinsic_widths_block(ctx, child_widths);
+        self.d().min_width = Au::max(min_width, Au(0));
+        self.d().pref_width = Au::max(pref_width, Au(0));
+    }
+
+    /* Combines the (min, pref) widths of the child contexts, given in
+       order, with those of the block's own box. */
+    fn intrinsic_widths_block(@self, ctx: &LayoutContext, child_widths: &[(Au, Au)]) -> (Au, Au) {
+        assert self.starts_block_flow();
+
         let mut min_width = Au(0);
         let mut pref_width = Au(0);
 
         /* find max width from child block contexts */
-        for FlowTree.each_child(self) |child_ctx| {
-            assert child_ctx.starts_block_flow() || child_ctx.starts_inline_flow();
-
-            min_width  = au::max(min_width, child_ctx.d().min_width);
-            pref_width = au::max(pref_width, child_ctx.d().pref_width);
+        for child_widths.each |widths| {
+            let (child_min, child_pref) = *widths;
+            min_width  = au::max(min_width, child_min);
+            pref_width = au::max(pref_width, child_pref);
         }
 
         /* if not an anonymous block context, add in block box's widths.
@@ -87,49 +121,143 @@ impl FlowContext : BlockLayout {
             pref_width = pref_width.add(&box.get_pref_width(ctx));
         }
 
-        self.d().min_width = min_width;
-        self.d().pref_width = pref_width;
+        (min_width, pref_width)
     }
  
     /* Recursively (top-down) determines the actual width of child
     contexts and boxes. When called on this context, the context has
     had its width set by the parent context.
 
-    Dual boxes consume some width first, and the remainder is assigned to
-    all child (block) contexts. */
+    The flow's position covers its border box, and the width assigned by
+    the parent is that of the containing block. The width and horizontal
+    margins are resolved against it as in CSS 2.1 Section 10.3.3: a
+    declared width is resolved to a content width according to
+    'box-sizing', and an 'auto' width fills the containing block less the
+    margins. Dual boxes consume some width first, and the remainder is
+    assigned to all child (block) contexts. */
 
-    fn assign_widths_block(@self, _ctx: &LayoutContext) { 
+    fn assign_widths_block(@self, ctx: &LayoutContext) { 
         assert self.starts_block_flow();
 
-        let mut remaining_width = self.d().position.size.width;
-        let mut _right_used = Au(0);
-        let mut left_used = Au(0);
+        let zoom = ctx.zoom;
+        let containing_width = self.d().position.size.width;
+        let content_left = left_fringe(self.d(), zoom);
+        let fringe = horizontal_fringe(self.d(), zoom);
+        let width = self.d().width.zoomed(zoom).resolve(containing_width);
+        let (content_width, margin_left) =
+            resolve_width_and_margins(self.d(), zoom, containing_width, width);
+
+        /* 'max-width' and then 'min-width' clamp the width, by resolving it
+           again as if it had been declared (CSS 2.1, 10.4). So if the
+           minimum is larger than the maximum, the minimum wins. */
+        let (content_width, margin_left) =
+            match self.d().max_width_constraint.zoomed(zoom).resolve(containing_width) {
+                Some(max) if content_width > content_width_for(self.d(), zoom, max) => {
+                    resolve_width_and_margins(self.d(), zoom, containing_width, Some(max))
+                }
+                _ => (content_width, margin_left)
+            };
+        let (content_width, margin_left) =
+            match self.d().min_width_constraint.zoomed(zoom).resolve(containing_width) {
+                Some(min) if content_width < content_width_for(self.d(), zoom, min) => {
+                    resolve_width_and_margins(self.d(), zoom, containing_width, Some(min))
+                }
+                _ => (content_width, margin_left)
+            };
+
+        // padding and borders may not fit in the width; the content width
+        // is never negative, so they overflow it instead.
+        let content_width = Au::max(content_width, Au(0));
+        self.d().position.origin.x += margin_left;
+        self.d().position.size.width = content_width + fringe;
+
+        let mut remaining_width = content_width;
+        let mut left_used = content_left;
 
         /* Let the box consume some width. It will return the amount remaining
            for its children. */
         do self.with_block_box |box| {
-            box.d().position.size.width = remaining_width;
-            let (left_used, right_used) = box.get_used_width();
-            remaining_width -= left_used.add(&right_used);
+            box.d().position.size.width = self.d().position.size.width;
+            let (box_left, box_right) = box.get_used_width();
+            left_used += box_left;
+            remaining_width = Au::max(remaining_width - box_left.add(&box_right), Au(0));
+        }
+
+        /* The marker hangs to the left of the content edge. */
+        do self.block().marker.iter |marker| {
+            marker.d().position = Rect(Point2D(content_left - marker_size().scale_by(2f), Au(0)),
+                                       Size2D(marker_size(), marker_size()));
         }
 
+        let mut total_weight = 0;
         for FlowTree.each_child(self) |child_ctx| {
-            assert child_ctx.starts_block_flow() || child_ctx.starts_inline_flow();
+            assert is_block_container_child(child_ctx);
             child_ctx.d().position.origin.x = left_used;
-            child_ctx.d().position.size.width = remaining_width;
+            match child_ctx.d().width_weight {
+                Some(weight) => total_weight += weight,
+                None => child_ctx.d().position.size.width = remaining_width
+            }
+        }
+
+        /* Weighted children split the width in proportion to their weights.
+           TODO: lay weighted children out side by side, as a flex container would. */
+        if total_weight > 0 {
+            let mut weight_so_far = 0;
+            let mut width_so_far = Au(0);
+            for FlowTree.each_child(self) |child_ctx| {
+                match child_ctx.d().width_weight {
+                    Some(weight) => {
+                        // compute each child's right edge, so rounding errors don't accumulate
+                        weight_so_far += weight;
+                        let right = Au(*remaining_width * (weight_so_far as i32) / (total_weight as i32));
+                        child_ctx.d().position.size.width = right - width_so_far;
+                        width_so_far = right;
+                    }
+                    None => {}
+                }
+            }
         }
     }
 
-    fn assign_height_block(@self, _ctx: &LayoutContext) {
-        assert self.starts_block_flow();
+    /* The (min, pref) widths of a flow that shrinks to fit its content,
+       such as a float: those of its widest child contexts. They don't
+       include the flow's own padding and borders. */
+    fn bubble_widths_shrink_to_fit(@self, _ctx: &LayoutContext) {
+        let mut child_widths = ~[];
+        for FlowTree.each_child(self) |child_ctx| {
+            child_widths.push((child_ctx.d().min_width, child_ctx.d().pref_width));
+        }
+        let (min_width, pref_width) = widest_children(child_widths);
+        self.d().min_width = min_width;
+        self.d().pref_width = pref_width;
+    }
 
-        let mut cur_y = Au(0);
+    /* Floats (and other flows that are taken out of the block flow) are
+       as wide as declared, or else shrink to fit their content, within
+       the width of the containing block that the parent assigned them
+       (CSS 2.1, 10.3.5). Child contexts take up the whole content width. */
+    fn assign_widths_shrink_to_fit(@self, ctx: &LayoutContext) {
+        let available_width = self.d().position.size.width;
+        let fringe = horizontal_fringe(self.d(), ctx.zoom);
+        let content_width = match self.d().width.zoomed(ctx.zoom).resolve(available_width) {
+            Some(width) => content_width_for(self.d(), ctx.zoom, width),
+            None => Au::min(Au::max(self.d().min_width, available_width - fringe),
+                            self.d().pref_width)
+        };
+        let content_width = Au::max(content_width, Au(0));
+        self.d().position.size.width = content_width + fringe;
 
+        let content_left = left_fringe(self.d(), ctx.zoom);
         for FlowTree.each_child(self) |child_ctx| {
-            child_ctx.d().position.origin.y = cur_y;
-            cur_y += child_ctx.d().position.size.height;
+            child_ctx.d().position.origin.x = content_left;
+            child_ctx.d().position.size.width = content_width;
         }
+    }
+
+    fn assign_height_block(@self, ctx: &LayoutContext) {
+        assert self.starts_block_flow();
 
+        let cur_y = self.used_height(ctx, stack_child_flows(self, ctx.zoom));
         self.d().position.size.height = cur_y;
 
         let _used_top = Au(0);
@@ -142,21 +270,729 @@ impl FlowContext : BlockLayout {
         }
     }
 
+    /* An absolutely positioned flow is as tall as its declared height,
+       or else as tall as its stacked child contexts. */
+    fn assign_height_absolute(@self, ctx: &LayoutContext) {
+        match self {
+            @AbsoluteFlow(*) => {},
+            _ => fail fmt!("Tried to assign_height_absolute of flow: f%d", self.d().id)
+        }
+
+        let content_height = stack_child_flows(self, ctx.zoom);
+        self.d().position.size.height = self.used_height(ctx, content_height);
+    }
+
+    /* An inline-block establishes its own formatting context, so its height
+       is that of the child contexts inside it, unless a height is declared. */
+    fn assign_height_inline_block(@self, ctx: &LayoutContext) {
+        match self {
+            @InlineBlockFlow(*) => {},
+            _ => fail fmt!("Tried to assign_height_inline_block of flow: f%d", self.d().id)
+        }
+
+        let content_height = stack_child_flows(self, ctx.zoom);
+        self.d().position.size.height = self.used_height(ctx, content_height);
+    }
+
     fn build_display_list_block(@self, builder: &DisplayListBuilder, dirty: &Rect<Au>, 
                                 offset: &Point2D<Au>, list: &mut DisplayList) {
 
         assert self.starts_block_flow();
-        
-        // add box that starts block context
-        do self.with_block_box |box| {
-            box.build_display_list(builder, dirty, offset, list)
-        }
+
+        build_own_display_items(self, builder, dirty, offset, list);
 
         // TODO: handle any out-of-flow elements
 
-        // go deeper into the flow tree
+        // go deeper into the flow tree. Flows with a 'z-index' other than 0
+        // are painted in stacking order by build_display_list instead.
         for FlowTree.each_child(self) |child| {
+            if child.effective_z_index() != 0 {
+                loop;
+            }
             self.build_display_list_for_child(builder, child, dirty, offset, list)
         }
     }
 }
+
+/* Paints what a block itself shows: its background, then its box and its
+   list item marker, if any. A hidden block paints nothing of its own, but
+   its children may be visible. */
+pub fn build_own_display_items(flow: @FlowContext, builder: &DisplayListBuilder, dirty: &Rect<Au>,
+                               offset: &Point2D<Au>, list: &mut DisplayList) {
+    match flow.d().visibility {
+        Visible => {}
+        Hidden => return
+    }
+
+    // the background goes beneath everything the block contains
+    do flow.d().style.iter |style| {
+        do style.background_color.iter |color| {
+            let bounds = Rect(copy *offset, copy flow.d().position.size);
+            let item = if style.border_radius.is_zero() {
+                DisplayItem::new_SolidColor(&bounds, *color)
+            } else {
+                DisplayItem::new_RoundedRect(&bounds, &style.border_radius, *color)
+            };
+            list.append_item(~item);
+        }
+    }
+
+    // add box that starts block context
+    do flow.with_block_box |box| {
+        box.build_display_list(builder, dirty, offset, list)
+    }
+
+    // TODO: draw a disc, rather than a square. The root, which is built
+    // as a block, has no marker.
+    match *flow {
+        BlockFlow(_, ref b) => {
+            do b.marker.iter |marker| {
+                let bounds = marker.d().position.translate(offset);
+                list.append_item(~DisplayItem::new_SolidColor(&bounds, rgb(0, 0, 0)));
+            }
+        }
+        _ => {}
+    }
+}
+
+/* Whether `flow` may be a child context of a block: roots and tables
+   can't be. */
+priv fn is_block_container_child(flow: @FlowContext) -> bool {
+    match *flow {
+        RootFlow(*) | TableFlow(*) => false,
+        _ => true
+    }
+}
+
+/* The largest of the (min, pref) widths of some child contexts. */
+pub fn widest_children(child_widths: &[(Au, Au)]) -> (Au, Au) {
+    let mut min_width = Au(0);
+    let mut pref_width = Au(0);
+    for child_widths.each |widths| {
+        let (child_min, child_pref) = *widths;
+        min_width = Au::max(min_width, child_min);
+        pref_width = Au::max(pref_width, child_pref);
+    }
+    (min_width, pref_width)
+}
+
+/* The width of the left padding and border of `d`, at `zoom`. */
+pub fn left_fringe(d: &FlowData, zoom: float) -> Au {
+    d.padding.zoomed(zoom).left + d.border.zoomed(zoom).left
+}
+
+/* The width of the right padding and border of `d`, at `zoom`. */
+pub fn right_fringe(d: &FlowData, zoom: float) -> Au {
+    d.padding.zoomed(zoom).right + d.border.zoomed(zoom).right
+}
+
+/* The width of the padding and borders of `d` on both sides, at `zoom`. */
+pub fn horizontal_fringe(d: &FlowData, zoom: float) -> Au {
+    left_fringe(d, zoom) + right_fringe(d, zoom)
+}
+
+/* The content width of `d` if its declared width were `width`, given
+   its 'box-sizing'. `width` is already zoomed; the padding and borders
+   are zoomed by `zoom`. */
+pub fn content_width_for(d: &FlowData, zoom: float, width: Au) -> Au {
+    match d.box_sizing {
+        ContentBox => width,
+        BorderBox  => width - horizontal_fringe(d, zoom)
+    }
+}
+
+/* Resolves the content width and the left margin of `d` as in CSS 2.1
+   Section 10.3.3, if its width is `width` (None for 'auto'). The margins,
+   padding and borders are zoomed by `zoom`; `width` already is. */
+priv fn resolve_width_and_margins(d: &FlowData, zoom: float, containing_width: Au,
+                                  width: Option<Au>) -> (Au, Au) {
+    let fringe = horizontal_fringe(d, zoom);
+    let margin_left = d.margin_left.zoomed(zoom).resolve(containing_width);
+    let margin_right = d.margin_right.zoomed(zoom).resolve(containing_width);
+
+    match width {
+        // 'auto' margins are zero, and the width takes up the rest
+        None => {
+            let margin_left = margin_left.get_default(Au(0));
+            let margin_right = margin_right.get_default(Au(0));
+            (containing_width - margin_left - margin_right - fringe, margin_left)
+        }
+        Some(width) => {
+            let content_width = content_width_for(d, zoom, width);
+            let remaining = containing_width - Au::max(content_width, Au(0)) - fringe;
+            let margin_left = match (margin_left, margin_right) {
+                // two 'auto' margins center the flow
+                (None, None) => remaining.scale_by(0.5f),
+                (None, Some(right)) => remaining - right,
+                // if over-constrained, 'margin-right' gives way (for ltr)
+                (Some(left), _) => left
+            };
+            (content_width, margin_left)
+        }
+    }
+}
+
+/* Places child contexts of `flow` one below the other, and returns their
+   total height. Floats don't take up any height; they're recorded in
+   `flow`'s float list, and children with 'clear' start below them.
+   `zoom` is that of the layout, which `flow`'s padding and borders are
+   zoomed by. */
+pub fn stack_child_flows(flow: @FlowContext, zoom: float) -> Au {
+    let mut cur_y = Au(0);
+    let floats = &flow.d().floats;
+    floats.reset();
+
+    for FlowTree.each_child(flow) |child_ctx| {
+        // flows that aren't displayed neither float nor clear
+        if !child_ctx.d().is_displayed {
+            loop;
+        }
+        if child_ctx.starts_float_flow() {
+            place_float(flow, zoom, child_ctx, cur_y);
+            loop;
+        }
+
+        match child_ctx.d().clear {
+            ClearNone => {},
+            clear => cur_y = Au::max(cur_y, floats.clearance(clear))
+        }
+        child_ctx.d().position.origin.y = cur_y;
+        cur_y += child_ctx.d().position.size.height;
+    }
+
+    cur_y
+}
+
+/* Moves `float_ctx` to the first position, from `top` down, where it fits
+   beside the floats already placed among `flow`'s children, and adds it to
+   `flow`'s float list. A float that is wider than `flow` is placed below
+   the other floats, where it overflows `flow` instead of them. */
+pub fn place_float(flow: @FlowContext, zoom: float, float_ctx: @FlowContext, top: Au) {
+    let d = flow.d();
+    let left = left_fringe(d, zoom);
+    let right = d.position.size.width - right_fringe(d, zoom);
+    let size = copy float_ctx.d().position.size;
+    let side = float_ctx.float_data().side;
+
+    let mut y = top;
+    loop {
+        let (free_left, free_right) = d.floats.free_span(y, size.height, left, right);
+        let fits = size.width <= free_right - free_left;
+        match d.floats.next_float_bottom(y, size.height) {
+            Some(bottom) if !fits => y = bottom,
+            _ => {
+                let x = match side {
+                    FloatLeft => free_left,
+                    FloatRight => free_right - size.width
+                };
+                float_ctx.d().position.origin = Point2D(x, y);
+                break;
+            }
+        }
+    }
+    d.floats.add(side, &float_ctx.d().position);
+}
+
+#[cfg(test)]
+mod test {
+    use gfx::display_list::{CornerRadii, RoundedRect, SolidColor};
+    use layout::box::{GenericBox, InlineBlockBox, RenderBoxData};
+    use layout::context::test_layout_context;
+    use layout::float::{ClearLeft, FloatFlowData};
+    use layout::flow::{Auto, AutoHeight, BoxSizing, ComputedStyle, FixedHeight, FloatFlow};
+    use layout::flow::{BoxOffsets, FlowData, InlineFlow, Length};
+    use layout::flow::{HeightAssigned, PercentHeight};
+    use layout::inline::InlineFlowData;
+    use layout::test_builder;
+    use layout::traverse::*;
+
+    fn padded_block_with_child(sizing: BoxSizing) -> (@FlowContext, @FlowContext) {
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        let child = @BlockFlow(FlowData(1), BlockFlowData());
+        FlowTree.add_child(parent, child);
+
+        parent.d().width = Length(Au::from_px(200));
+        parent.d().box_sizing = sizing;
+        parent.d().padding.left = Au::from_px(10);
+        parent.d().padding.right = Au::from_px(10);
+        (parent, child)
+    }
+
+    #[test]
+    fn content_box_sizing_excludes_padding_from_width() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let (parent, child) = padded_block_with_child(ContentBox);
+        parent.assign_widths_block(&ctx);
+
+        assert parent.d().position.size.width == Au::from_px(220);
+        assert child.d().position.origin.x == Au::from_px(10);
+        assert child.d().position.size.width == Au::from_px(200);
+    }
+
+    #[test]
+    fn border_box_sizing_includes_padding_in_width() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let (parent, child) = padded_block_with_child(BorderBox);
+        parent.assign_widths_block(&ctx);
+
+        assert parent.d().position.size.width == Au::from_px(200);
+        assert child.d().position.origin.x == Au::from_px(10);
+        assert child.d().position.size.width == Au::from_px(180);
+    }
+
+    fn flow_with_children(parent: @FlowContext, heights: &[int]) -> @FlowContext {
+        for heights.eachi |i, height| {
+            let child = @BlockFlow(FlowData(i as int + 1), BlockFlowData());
+            child.d().position.size.height = Au::from_px(*height);
+            FlowTree.add_child(parent, child);
+        }
+        parent
+    }
+
+    #[test]
+    fn absolute_flow_uses_declared_height() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = flow_with_children(@AbsoluteFlow(FlowData(0)), [10, 20]);
+        flow.d().height = FixedHeight(Au::from_px(100));
+        flow.bubble_widths(&ctx);
+        flow.assign_widths(&ctx);
+        flow.assign_height(&ctx);
+
+        assert flow.d().position.size.height == Au::from_px(100);
+    }
+
+    // Declares the heights that flow_with_children gave the children of
+    // `flow`, so that they survive the height pass.
+    fn fix_child_heights(flow: @FlowContext) {
+        for FlowTree.each_child(flow) |child| {
+            child.d().height = FixedHeight(child.d().position.size.height);
+        }
+    }
+
+    #[test]
+    fn reflow_lays_out_absolute_and_inline_block_flows() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let parent = tree::first_child(&FlowTree, &root).get();
+        let absolute = flow_with_children(@AbsoluteFlow(FlowData(10)), [10, 20]);
+        absolute.d().width = Length(Au::from_px(120));
+        fix_child_heights(absolute);
+        FlowTree.add_child(parent, absolute);
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+        assert absolute.d().position.size == Size2D(Au::from_px(120), Au::from_px(30));
+        let child = tree::first_child(&FlowTree, &absolute).get();
+        assert child.d().position.size.width == Au::from_px(120);
+
+        // an inline-block is laid out as the root of its own subtree
+        let inline_block = flow_with_children(@InlineBlockFlow(FlowData(20)), [10]);
+        inline_block.d().width = Length(Au::from_px(80));
+        fix_child_heights(inline_block);
+        inline_block.d().position.size.width = Au::from_px(300);
+        do inline_block.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do inline_block.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do inline_block.traverse_postorder |f| { f.assign_height(&ctx) }
+        assert inline_block.d().position.size == Size2D(Au::from_px(80), Au::from_px(10));
+    }
+
+    #[test]
+    fn inline_block_flow_is_sized_by_content() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = flow_with_children(@InlineBlockFlow(FlowData(0)), [10, 20]);
+        flow.bubble_widths(&ctx);
+        flow.assign_widths(&ctx);
+        flow.assign_height(&ctx);
+
+        assert flow.d().position.size.height == Au::from_px(30);
+        let second = tree::last_child(&FlowTree, &flow).get();
+        assert second.d().position.origin.y == Au::from_px(10);
+    }
+
+    // A block of the given declared height, holding a 50% high block that
+    // holds a 30px high block.
+    fn percent_child_in_parent(parent_height: DeclaredHeight) -> (@FlowContext, @FlowContext) {
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        let child = @BlockFlow(FlowData(1), BlockFlowData());
+        let grandchild = @BlockFlow(FlowData(2), BlockFlowData());
+        FlowTree.add_child(parent, child);
+        FlowTree.add_child(child, grandchild);
+
+        parent.d().height = parent_height;
+        child.d().height = PercentHeight(50f);
+        grandchild.d().height = FixedHeight(Au::from_px(30));
+        (parent, child)
+    }
+
+    #[test]
+    fn percent_height_resolves_against_definite_parent_height() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let (parent, child) = percent_child_in_parent(FixedHeight(Au::from_px(200)));
+        do parent.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do parent.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do parent.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert child.d().position.size.height == Au::from_px(100);
+        assert parent.d().position.size.height == Au::from_px(200);
+    }
+
+    #[test]
+    fn percent_height_is_auto_when_parent_height_is_auto() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let (parent, child) = percent_child_in_parent(AutoHeight);
+        do parent.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do parent.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do parent.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert child.d().position.size.height == Au::from_px(30);
+        assert parent.d().position.size.height == Au::from_px(30);
+    }
+
+    #[test]
+    fn list_item_marker_is_painted_left_of_content() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().inline_boxes(1).build();
+        let item = tree::first_child(&FlowTree, &root).get();
+        let inline = tree::first_child(&FlowTree, &item).get();
+        let marker = @GenericBox(RenderBoxData(inline.inline().boxes[0].d().node, item, 10));
+        item.block().marker = Some(marker);
+        item.d().position.size.width = Au::from_px(200);
+        item.d().padding.left = Au::from_px(20);
+
+        item.assign_widths_block(&ctx);
+        inline.d().phase = HeightAssigned;
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        let dirty = Rect(Point2D(Au(-100), Au(-100)), Size2D(Au::from_px(1000), Au::from_px(1000)));
+        item.build_display_list_block(&builder, &dirty, &Point2D(Au(0), Au(0)), &mut list);
+
+        match *list.list[0] {
+            SolidColor(ref d, _) => {
+                assert d.bounds.origin.x + d.bounds.size.width < Au::from_px(20);
+            }
+            _ => fail ~"expected the marker to be painted first"
+        }
+    }
+
+    fn background_items(radii: CornerRadii) -> DisplayList {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.set_style(@ComputedStyle {
+            width: Length(Au::from_px(100)),
+            height: FixedHeight(Au::from_px(50)),
+            box_sizing: ContentBox,
+            padding: BoxOffsets::zero(),
+            border: BoxOffsets::zero(),
+            background_color: Some(rgb(255, 0, 0)),
+            border_radius: radii,
+            before_content: None,
+            after_content: None
+        });
+        block.d().position.size = Size2D(Au::from_px(100), Au::from_px(50));
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        let dirty = Rect(Point2D(Au(-100), Au(-100)), Size2D(Au::from_px(1000), Au::from_px(1000)));
+        block.build_display_list_block(&builder, &dirty, &Point2D(Au(0), Au(0)), &mut list);
+        move list
+    }
+
+    #[test]
+    fn background_with_radius_is_a_rounded_rect() {
+        let list = background_items(CornerRadii {
+            top_left: Au::from_px(5),
+            top_right: Au(0),
+            bottom_right: Au::from_px(8),
+            bottom_left: Au(0)
+        });
+
+        match *list.list[0] {
+            RoundedRect(ref d, ref radii, _) => {
+                assert d.bounds.size == Size2D(Au::from_px(100), Au::from_px(50));
+                assert radii.top_left == Au::from_px(5);
+                assert radii.top_right == Au(0);
+                assert radii.bottom_right == Au::from_px(8);
+            }
+            _ => fail ~"expected a rounded background"
+        }
+    }
+
+    #[test]
+    fn hidden_block_paints_its_visible_child_but_not_its_background() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().block().build();
+        let parent = tree::first_child(&FlowTree, &root).get();
+        let child = tree::first_child(&FlowTree, &parent).get();
+        for [parent, child].each |flow| {
+            flow.set_style(@ComputedStyle {
+                width: Auto,
+                height: AutoHeight,
+                box_sizing: ContentBox,
+                padding: BoxOffsets::zero(),
+                border: BoxOffsets::zero(),
+                background_color: Some(rgb(255, 0, 0)),
+                border_radius: CornerRadii::zero(),
+                before_content: None,
+                after_content: None
+            });
+            flow.d().phase = HeightAssigned;
+        }
+        parent.d().visibility = Hidden;
+        parent.d().position.size = Size2D(Au::from_px(100), Au::from_px(50));
+        child.d().position.size = Size2D(Au::from_px(40), Au::from_px(20));
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        let dirty = Rect(Point2D(Au(-100), Au(-100)), Size2D(Au::from_px(1000), Au::from_px(1000)));
+        parent.build_display_list_block(&builder, &dirty, &Point2D(Au(0), Au(0)), &mut list);
+
+        let mut backgrounds = ~[];
+        for list.list.each |item| {
+            match **item {
+                SolidColor(ref d, _) => backgrounds.push(copy d.bounds.size),
+                _ => {}
+            }
+        }
+        assert backgrounds == ~[Size2D(Au::from_px(40), Au::from_px(20))];
+    }
+
+    #[test]
+    fn background_without_radius_is_a_plain_rect() {
+        let list = background_items(CornerRadii::zero());
+
+        match *list.list[0] {
+            SolidColor(*) => {}
+            _ => fail ~"expected a plain background"
+        }
+    }
+
+    #[test]
+    fn excessive_padding_clamps_content_width_to_zero() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let (parent, child) = padded_block_with_child(BorderBox);
+        parent.d().padding.left = Au::from_px(150);
+        parent.d().padding.right = Au::from_px(150);
+        parent.assign_widths_block(&ctx);
+
+        assert parent.d().position.size.width == Au::from_px(300);
+        assert child.d().position.size.width == Au(0);
+    }
+
+    #[test]
+    fn weighted_children_split_remaining_width() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        let first = @BlockFlow(FlowData(1), BlockFlowData());
+        let second = @BlockFlow(FlowData(2), BlockFlowData());
+        let unweighted = @BlockFlow(FlowData(3), BlockFlowData());
+        FlowTree.add_child(parent, first);
+        FlowTree.add_child(parent, second);
+        FlowTree.add_child(parent, unweighted);
+        first.d().width_weight = Some(1);
+        second.d().width_weight = Some(2);
+
+        parent.d().position.size.width = Au::from_px(300);
+        parent.assign_widths_block(&ctx);
+
+        assert first.d().position.size.width == Au::from_px(100);
+        assert second.d().position.size.width == Au::from_px(200);
+        assert unweighted.d().position.size.width == Au::from_px(300);
+    }
+
+    #[test]
+    fn reflow_lays_out_float_among_blocks() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let builder = test_builder::root().block();
+        let node = builder.new_node();
+        let root = builder.build();
+        let parent = tree::first_child(&FlowTree, &root).get();
+        let float = @FloatFlow(FlowData(10), FloatFlowData());
+        float.d().width = Length(Au::from_px(100));
+        let inline = @InlineFlow(FlowData(11), InlineFlowData());
+        inline.inline().boxes.push(@GenericBox(RenderBoxData(node, inline, 0)));
+        FlowTree.add_child(parent, float);
+        FlowTree.add_child(float, inline);
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert float.d().position.size == Size2D(Au::from_px(100), Au::from_px(30));
+        assert inline.d().position.size.width == Au::from_px(100);
+        // floats take up no height in their containing block
+        assert parent.d().position.size.height == Au(0);
+    }
+
+    #[test]
+    fn cleared_block_starts_below_preceding_float() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        let left_float = @FloatFlow(FlowData(1), FloatFlowData());
+        let cleared = @BlockFlow(FlowData(2), BlockFlowData());
+        FlowTree.add_child(parent, left_float);
+        FlowTree.add_child(parent, cleared);
+        left_float.d().height = FixedHeight(Au::from_px(50));
+        cleared.d().height = FixedHeight(Au::from_px(10));
+        cleared.d().clear = ClearLeft;
+
+        do parent.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do parent.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do parent.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert left_float.d().position.origin.y == Au(0);
+        assert cleared.d().position.origin.y >= Au::from_px(50);
+        assert parent.d().position.size.height == Au::from_px(60);
+    }
+
+    #[test]
+    fn undisplayed_float_is_not_cleared() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        let hidden_float = @FloatFlow(FlowData(1), FloatFlowData());
+        let cleared = @BlockFlow(FlowData(2), BlockFlowData());
+        FlowTree.add_child(parent, hidden_float);
+        FlowTree.add_child(parent, cleared);
+        hidden_float.d().is_displayed = false;
+        hidden_float.d().height = FixedHeight(Au::from_px(50));
+        cleared.d().height = FixedHeight(Au::from_px(10));
+        cleared.d().clear = ClearLeft;
+
+        do parent.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do parent.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do parent.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert parent.d().floats.len() == 0;
+        assert cleared.d().position.origin.y == Au(0);
+        assert parent.d().position.size.height == Au::from_px(10);
+    }
+
+    fn block_with_left_floats(width: int, float_widths: &[int]) -> ~[@FlowContext] {
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        parent.d().position.size.width = Au::from_px(width);
+        let mut floats = ~[];
+        for float_widths.eachi |i, float_width| {
+            let float_ctx = @FloatFlow(FlowData(i as int + 1), FloatFlowData());
+            float_ctx.d().position.size = Size2D(Au::from_px(*float_width), Au::from_px(40));
+            FlowTree.add_child(parent, float_ctx);
+            floats.push(float_ctx);
+        }
+        stack_child_flows(parent, 1f);
+        move floats
+    }
+
+    #[test]
+    fn narrow_floats_are_placed_side_by_side() {
+        let floats = block_with_left_floats(100, [40, 40]);
+
+        assert floats[0].d().position.origin == Point2D(Au(0), Au(0));
+        assert floats[1].d().position.origin == Point2D(Au::from_px(40), Au(0));
+    }
+
+    #[test]
+    fn floats_wider_than_container_stack_vertically() {
+        let floats = block_with_left_floats(100, [150, 150]);
+
+        assert floats[0].d().position.origin == Point2D(Au(0), Au(0));
+        assert floats[1].d().position.origin == Point2D(Au(0), Au::from_px(40));
+    }
+
+    #[test]
+    fn auto_width_fills_containing_block_less_margins() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().margin_left = Length(Au::from_px(20));
+        block.d().margin_right = Auto;
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+
+        assert block.d().position.origin.x == Au::from_px(20);
+        assert block.d().position.size.width == Au::from_px(780);
+    }
+
+    #[test]
+    fn auto_margins_center_definite_width() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().width = Length(Au::from_px(200));
+        block.d().margin_left = Auto;
+        block.d().margin_right = Auto;
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+
+        assert block.d().position.origin.x == Au::from_px(300);
+        assert block.d().position.size.width == Au::from_px(200);
+    }
+
+    #[test]
+    fn zoom_scales_fixed_lengths() {
+        let ctx = LayoutContext {
+            zoom: 2f,
+            .. test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)))
+        };
+        let builder = test_builder::root().block().block();
+        let child = builder.last_flow();
+        let root = builder.build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().width = Length(Au::from_px(100));
+        block.d().height = FixedHeight(Au::from_px(50));
+        block.d().margin_left = Length(Au::from_px(10));
+        block.d().padding.left = Au::from_px(5);
+        block.d().border.right = Au::from_px(1);
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert block.d().position.origin.x == Au::from_px(20);
+        assert block.d().position.size == Size2D(Au::from_px(212), Au::from_px(100));
+        assert child.d().position.origin.x == Au::from_px(10);
+        assert child.d().position.size.width == Au::from_px(200);
+
+        // a shrink-to-fit inline-block's content widths are already zoomed,
+        // but its declared width, padding and borders aren't
+        let inline_block = @InlineBlockFlow(FlowData(3));
+        inline_block.d().padding.left = Au::from_px(5);
+        inline_block.d().pref_width = Au::from_px(40);
+        let box = @InlineBlockBox(RenderBoxData(builder.new_node(), block, 4), inline_block);
+        assert box.get_pref_width(&ctx) == Au::from_px(50);
+        inline_block.d().width = Length(Au::from_px(30));
+        assert box.get_pref_width(&ctx) == Au::from_px(70);
+    }
+
+    #[test]
+    fn max_width_clamps_used_width() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().width = Length(Au::from_px(300));
+        block.d().max_width_constraint = Length(Au::from_px(200));
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        assert block.d().position.size.width == Au::from_px(200);
+    }
+
+    #[test]
+    fn min_width_raises_used_width_and_wins_over_max() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().width = Length(Au::from_px(50));
+        block.d().min_width_constraint = Length(Au::from_px(100));
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        assert block.d().position.size.width == Au::from_px(100);
+
+        block.d().max_width_constraint = Length(Au::from_px(80));
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        assert block.d().position.size.width == Au::from_px(100);
+    }
+}
@@ -2,10 +2,11 @@
 
 use dom::element::{ElementKind, HTMLDivElement, HTMLImageElement};
 use dom::node::{Element, Node, NodeData, NodeKind, NodeTree};
+use layout::block::{content_width_for, horizontal_fringe};
 use layout::context::LayoutContext;
 use layout::debug::BoxedDebugMethods;
 use layout::display_list_builder::DisplayListBuilder;
-use layout::flow::FlowContext;
+use layout::flow::{FlowContext, FlowTree};
 use layout::text::TextBoxData;
 use util::tree::ReadMethods;
 
@@ -29,7 +30,7 @@ use newcss::values::{CSSBackgroundColorColor, CSSBackgroundColorTransparent, CSS
 use newcss::values::{CSSBorderWidthLength, CSSBorderWidthMedium, CSSDisplay};
 use newcss::values::{CSSFontFamilyFamilyName, CSSFontFamilyGenericFamily, CSSPositionAbsolute};
 use newcss::values::{CSSFontSizeLength, CSSFontStyleItalic, CSSFontStyleNormal};
-use newcss::values::{CSSFontStyleOblique, CSSTextAlign, Specified};
+use newcss::values::{CSSFontStyleOblique, CSSTextAlign, CSSTextAlignLeft, Specified};
 use std::arc::ARC;
 use std::net::url::Url;
 
@@ -63,6 +64,10 @@ padding, backgrounds. It is analogous to a CSS nonreplaced content box.
    node. Split text boxes are implemented by referring to subsets of a
    master TextRun object.
 
+ * InlineBlockBox: an atomic box on a line, representing an
+   inline-block. Its content is laid out in its own flow, which
+   it holds.
+
 */
 
 /* A box's kind influences how its styles are interpreted during
@@ -74,16 +79,40 @@ padding, backgrounds. It is analogous to a CSS nonreplaced content box.
 pub struct RenderBoxData {
     /* originating DOM node */
     node : Node,
+    /* the pseudo-element of `node` that generated this box, if any. Such
+       boxes take `node`'s style, but aren't among the boxes of `node`. */
+    pseudo : Option<PseudoElement>,
     /* reference to containing flow context, which this box
        participates in */
-    ctx  : @FlowContext,
+    mut ctx  : @FlowContext,
     /* position of this box relative to owning flow */
     mut position : Rect<Au>,
     font_size : Length,
+    /* replaced content's own size, and its width / height ratio, if it
+       has them. Images that don't say are measured from their image. */
+    mut intrinsic_size: Option<Size2D<Au>>,
+    mut intrinsic_ratio: Option<float>,
+    /* TODO: these should be read from CSS 'width' and 'height'; None for 'auto' */
+    mut declared_width: Option<Au>,
+    mut declared_height: Option<Au>,
     /* TODO (Issue #87): debug only */
     mut id: int
 }
 
+/* The pseudo-elements that generate boxes (CSS 2.1 Section 12.1). */
+pub enum PseudoElement {
+    BeforePseudo,
+    AfterPseudo
+}
+
+impl RenderBoxData {
+    /* Whether this is one of the boxes of `node` itself, rather than of
+       one of its pseudo-elements. */
+    pure fn is_box_of(node: Node) -> bool {
+        self.node == node && self.pseudo.is_none()
+    }
+}
+
 enum RenderBoxType {
     RenderBox_Generic,
     RenderBox_Image,
@@ -94,7 +123,52 @@ pub enum RenderBox {
     GenericBox(RenderBoxData),
     ImageBox(RenderBoxData, ImageHolder),
     TextBox(RenderBoxData, TextBoxData),
-    UnscannedTextBox(RenderBoxData, ~str)
+    UnscannedTextBox(RenderBoxData, ~str),
+    InlineBlockBox(RenderBoxData, @FlowContext)
+}
+
+/* The used size of replaced content with the given declared width and
+   height, and intrinsic dimensions (CSS 2.1, 10.3.2 and 10.6.2). An auto
+   dimension follows the other one by the intrinsic ratio, which is taken
+   from the intrinsic size if it isn't given. Content with no intrinsic
+   dimensions at all is 300px by 150px. */
+pub pure fn used_replaced_size(width: Option<Au>, height: Option<Au>,
+                               intrinsic_size: Option<Size2D<Au>>,
+                               intrinsic_ratio: Option<float>) -> Size2D<Au> {
+    let ratio = match (intrinsic_ratio, intrinsic_size) {
+        (Some(ratio), _) if ratio > 0f => Some(ratio),
+        (None, Some(size)) if size.height > Au(0) => {
+            Some((*size.width as float) / (*size.height as float))
+        }
+        _ => None
+    };
+    let default_width = Au::from_px(300);
+    let default_height = Au::from_px(150);
+
+    match (width, height) {
+        (Some(width), Some(height)) => Size2D(width, height),
+        (Some(width), None) => {
+            let height = match (ratio, intrinsic_size) {
+                (Some(ratio), _) => width.scale_by(1f / ratio),
+                (None, Some(size)) => size.height,
+                (None, None) => default_height
+            };
+            Size2D(width, height)
+        }
+        (None, Some(height)) => {
+            let width = match (ratio, intrinsic_size) {
+                (Some(ratio), _) => height.scale_by(ratio),
+                (None, Some(size)) => size.width,
+                (None, None) => default_width
+            };
+            Size2D(width, height)
+        }
+        (None, None) => match (intrinsic_size, ratio) {
+            (Some(size), _) => size,
+            (None, Some(ratio)) => Size2D(default_width, default_width.scale_by(1f / ratio)),
+            (None, None) => Size2D(default_width, default_height)
+        }
+    }
 }
 
 pub enum SplitBoxResult {
@@ -108,13 +182,29 @@ pub enum SplitBoxResult {
 pub fn RenderBoxData(node: Node, ctx: @FlowContext, id: int) -> RenderBoxData {
     RenderBoxData {
         node : node,
+        pseudo : None,
         mut ctx  : ctx,
         mut position : Au::zero_rect(),
         font_size: Px(0.0),
+        intrinsic_size: None,
+        intrinsic_ratio: None,
+        declared_width: None,
+        declared_height: None,
         id : id
     }
 }
 
+/* The border box width of an inline-block: its declared width, or else
+   `content_width`, with its padding and borders, all at `zoom`. */
+priv fn inline_block_width(flow: @FlowContext, zoom: float, content_width: Au) -> Au {
+    let d = flow.d();
+    let fringe = horizontal_fringe(d, zoom);
+    match d.width.zoomed(zoom).definite_length() {
+        Some(width) => content_width_for(d, zoom, width) + fringe,
+        None => content_width + fringe
+    }
+}
+
 impl RenderBox  {
     pure fn d(&self) -> &self/RenderBoxData {
         match *self {
@@ -122,6 +212,7 @@ impl RenderBox  {
             ImageBox(ref d, _) => d,
             TextBox(ref d, _)  => d,
             UnscannedTextBox(ref d, _) => d,
+            InlineBlockBox(ref d, _) => d,
         }
     }
 
@@ -146,6 +237,14 @@ impl RenderBox  {
         }
     }
 
+    pure fn ends_with_newline() -> bool {
+        match &self {
+            &TextBox(_, ref data) => data.range.length() > 0
+                && data.run.glyphs.char_is_newline(data.range.end() - 1),
+            _ => false
+        }
+    }
+
     fn can_merge_with_box(@self, other: @RenderBox) -> bool {
         assert !core::managed::ptr_eq(self, other);
 
@@ -162,6 +261,7 @@ impl RenderBox  {
         match self {
             @GenericBox(*) => CannotSplit(self),
             @ImageBox(*) => CannotSplit(self),
+            @InlineBlockBox(*) => CannotSplit(self),
             @UnscannedTextBox(*) => fail ~"WAT: shouldn't be an unscanned text box here.",
             @TextBox(_,data) => {
 
@@ -234,7 +334,7 @@ impl RenderBox  {
      * may cause glyphs to be allocated. For now, it's impure because of 
      * holder.get_image()
     */
-    fn get_min_width(_ctx: &LayoutContext) -> Au {
+    fn get_min_width(ctx: &LayoutContext) -> Au {
         match &self {
             // TODO: this should account for min/pref widths of the
             // box element in isolation. That includes
@@ -242,15 +342,15 @@ impl RenderBox  {
             // FlowContext will combine the width of this element and
             // that of its children to arrive at the context width.
             &GenericBox(*) => Au(0),
-            // TODO: consult CSS 'width', margin, border.
-            // TODO: If image isn't available, consult 'width'.
-            &ImageBox(_, ref i) => Au::from_px(i.get_size().get_default(Size2D(0,0)).width),
+            // TODO: consult CSS margin, border.
+            &ImageBox(*) => self.replaced_size().width,
             &TextBox(_,d) => d.run.min_width_for_range(&const d.range),
+            &InlineBlockBox(_, flow) => inline_block_width(flow, ctx.zoom, flow.d().min_width),
             &UnscannedTextBox(*) => fail ~"Shouldn't see unscanned boxes here."
         }
     }
 
-    fn get_pref_width(_ctx: &LayoutContext) -> Au {
+    fn get_pref_width(ctx: &LayoutContext) -> Au {
         match &self {
             // TODO: this should account for min/pref widths of the
             // box element in isolation. That includes
@@ -258,7 +358,7 @@ impl RenderBox  {
             // FlowContext will combine the width of this element and
             // that of its children to arrive at the context width.
             &GenericBox(*) => Au(0),
-            &ImageBox(_, ref i) => Au::from_px(i.get_size().get_default(Size2D(0,0)).width),
+            &ImageBox(*) => self.replaced_size().width,
 
             // a text box cannot span lines, so assume that this is an unsplit text box.
 
@@ -278,10 +378,27 @@ impl RenderBox  {
 
                 max_line_width
             },
+            &InlineBlockBox(_, flow) => inline_block_width(flow, ctx.zoom, flow.d().pref_width),
             &UnscannedTextBox(*) => fail ~"Shouldn't see unscanned boxes here."
         }
     }
 
+    /* The used size of replaced content (CSS 2.1, 10.3.2 and 10.6.2). An
+       image that hasn't loaded yet, and has no intrinsic size set, is
+       taken to be empty. */
+    fn replaced_size() -> Size2D<Au> {
+        let d = self.d();
+        let intrinsic_size = match (copy d.intrinsic_size, &self) {
+            (Some(size), _) => Some(size),
+            (None, &ImageBox(_, ref i)) => {
+                let size = i.get_size().get_default(Size2D(0, 0));
+                Some(Size2D(Au::from_px(size.width), Au::from_px(size.height)))
+            }
+            (None, _) => None
+        };
+        used_replaced_size(d.declared_width, d.declared_height, intrinsic_size, d.intrinsic_ratio)
+    }
+
     /* Returns the amount of left, right "fringe" used by this
     box. This should be based on margin, border, padding, width. */
     fn get_used_width() -> (Au, Au) {
@@ -304,14 +421,8 @@ impl RenderBox  {
        Coordinates are relative to the owning flow. */
     pure fn content_box() -> Rect<Au> {
         match &self {
-            &ImageBox(_, ref i) => {
-                let size = i.size();
-                Rect {
-                    origin: copy self.d().position.origin,
-                    size:   Size2D(Au::from_px(size.width),
-                                   Au::from_px(size.height))
-                }
-            },
+            // the used size of replaced content is assigned during layout
+            &ImageBox(*) => copy self.d().position,
             &GenericBox(*) => {
                 copy self.d().position
                 /* FIXME: The following hits an ICE for whatever reason
@@ -327,7 +438,7 @@ impl RenderBox  {
                                    size.height - (offset_top + offset_bottom))
                 }*/
             },
-            &TextBox(*) => {
+            &TextBox(*) | &InlineBlockBox(*) => {
                 copy self.d().position
             },
             &UnscannedTextBox(*) => fail ~"Shouldn't see unscanned boxes here."
@@ -379,7 +490,7 @@ impl RenderBox  {
     * `origin` - Total offset from display list root flow to this box's owning flow
     * `list` - List to which items should be appended
     */
-    fn build_display_list(@self, _builder: &DisplayListBuilder, dirty: &Rect<Au>,
+    fn build_display_list(@self, builder: &DisplayListBuilder, dirty: &Rect<Au>,
                           offset: &Point2D<Au>, list: &mut DisplayList) {
 
         let box_bounds = self.d().position;
@@ -426,6 +537,13 @@ impl RenderBox  {
             // TODO: items for background, border, outline
             @GenericBox(_) => {
             },
+            // the inline-block's flow is positioned at this box's origin
+            @InlineBlockBox(_, flow) => {
+                for FlowTree.each_child(flow) |child| {
+                    flow.build_display_list_for_child(builder, child, dirty,
+                                                      &abs_box_bounds.origin, list);
+                }
+            },
             @ImageBox(_, ref i) => {
                 match i.get_image() {
                     Some(image) => {
@@ -553,10 +671,17 @@ impl RenderBox  {
     }
 
     // Converts this node's ComputedStyle to a text alignment used in the inline layout code.
+    // A node with no element to take a style from, such as a detached text node, gets the
+    // initial value, 'left'.
     fn text_align(@self) -> CSSTextAlign {
-        do self.with_style_of_nearest_element |my_style| {
-            my_style.text_align()
+        let mut node = self.d().node;
+        while !node.is_element() {
+            match NodeTree.get_parent(&node) {
+                Some(move parent) => node = move parent,
+                None => return CSSTextAlignLeft
+            }
         }
+        node.style().text_align()
     }
 }
 
@@ -581,7 +706,8 @@ impl RenderBox : BoxedDebugMethods {
             @GenericBox(*) => ~"GenericBox",
             @ImageBox(*) => ~"ImageBox",
             @TextBox(_,d) => fmt!("TextBox(text=%s)", str::substr(d.run.text, d.range.begin(), d.range.length())),
-            @UnscannedTextBox(_, ref s) => fmt!("UnscannedTextBox(%s)", *s)
+            @UnscannedTextBox(_, ref s) => fmt!("UnscannedTextBox(%s)", *s),
+            @InlineBlockBox(_, flow) => fmt!("InlineBlockBox(f%d)", flow.d().id)
         };
 
         fmt!("box b%?: %?", self.d().id, repr)
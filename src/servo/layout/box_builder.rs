@@ -5,6 +5,7 @@ use dom::node::{Comment, Doctype, Element, Text, Node, LayoutData};
 use layout::box::*;
 use layout::block::BlockFlowData;
 use layout::context::LayoutContext;
+use layout::float::FloatFlowData;
 use layout::flow::*;
 use layout::inline::InlineFlowData;
 use layout::root::RootFlowData;
@@ -308,6 +309,12 @@ impl LayoutTreeBuilder {
                 do node.aux |data| { data.flow = Some(*child_flow) }
             }
         }
+
+        // the children of a block container are either all block-level or
+        // all inline-level
+        if is_block_container(this_ctx.default_collector.flow) {
+            self.normalize_children(this_ctx.default_collector.flow);
+        }
     }
 
     // Fixup any irregularities such as:
@@ -367,6 +374,97 @@ impl LayoutTreeBuilder {
         }
     }
 
+    /** Wraps each run of consecutive inline-level children of `parent` in
+    an anonymous block flow, if `parent` has block-level children too, so
+    that all of its children are block-level (CSS 2.1 Section 9.2.1.1). */
+    fn normalize_children(parent: @FlowContext) {
+        let mut children = ~[];
+        for FlowTree.each_child(parent) |child| {
+            children.push(child);
+        }
+
+        let has_inline = children.any(|child| is_inline_level(*child));
+        let has_block = children.any(|child| !is_inline_level(*child));
+        if !(has_inline && has_block) {
+            return;
+        }
+
+        for children.each |child| {
+            tree::remove_child(&FlowTree, parent, *child);
+        }
+        let mut anonymous_block: Option<@FlowContext> = None;
+        for children.each |child| {
+            if is_inline_level(*child) {
+                if anonymous_block.is_none() {
+                    let block = self.make_flow(Flow_Block);
+                    debug!("LayoutTreeBuilder: wrapping inline flows of f%d in anonymous block f%d",
+                           parent.d().id, block.d().id);
+                    FlowTree.add_child(parent, block);
+                    anonymous_block = Some(block);
+                }
+                FlowTree.add_child(anonymous_block.get(), *child);
+            } else {
+                anonymous_block = None;
+                FlowTree.add_child(parent, *child);
+            }
+        }
+    }
+
+    /** Adds the boxes of the '::before' and '::after' pseudo-elements of
+    each block under `root` (CSS 2.1 Section 12.1), as inline flows at the
+    start and end of the block's children. The boxes are marked with their
+    pseudo-element, so they aren't taken for boxes of the element itself.
+    Inline elements don't get generated content yet. */
+    fn generate_content(root: @FlowContext) {
+        let mut flows = ~[];
+        for tree::dfs(&FlowTree, &root) |flow| {
+            flows.push(*flow);
+            true
+        }
+
+        for flows.each |flow| {
+            // TODO: the pseudo-elements of inline elements belong among the
+            // boxes of their inline flow
+            match **flow {
+                BlockFlow(*) | RootFlow(*) => {}
+                _ => loop
+            }
+            let (before, after) = match flow.d().style {
+                Some(style) => (copy style.before_content, copy style.after_content),
+                None => loop
+            };
+            // generated boxes take their style from the originating element,
+            // so they keep its node
+            let node = match flow.d().node {
+                Some(node) => node,
+                None => loop
+            };
+
+            do before.iter |text| {
+                debug!("LayoutTreeBuilder: generating ::before content of f%d", flow.d().id);
+                add_first_child(*flow, self.make_generated_content_flow(node, BeforePseudo, copy *text));
+            }
+            do after.iter |text| {
+                debug!("LayoutTreeBuilder: generating ::after content of f%d", flow.d().id);
+                FlowTree.add_child(*flow, self.make_generated_content_flow(node, AfterPseudo, copy *text));
+            }
+            if before.is_some() || after.is_some() {
+                self.normalize_children(*flow);
+            }
+        }
+    }
+
+    priv fn make_generated_content_flow(node: Node, pseudo: PseudoElement,
+                                        text: ~str) -> @FlowContext {
+        let flow = self.make_flow(Flow_Inline);
+        let data = RenderBoxData {
+            pseudo: Some(pseudo),
+            .. RenderBoxData(node, flow, self.next_box_id())
+        };
+        flow.inline().boxes.push(@UnscannedTextBox(move data, move text));
+        flow
+    }
+
     fn fixup_split_inline(_foo: @FlowContext) {
         // TODO: finish me. 
         fail ~"TODO: handle case where an inline is split by a block"
@@ -381,6 +479,7 @@ impl LayoutTreeBuilder {
 
         self.root_flow = Some(new_flow);
         self.construct_recursively(layout_ctx, root, &root_ctx);
+        self.generate_content(new_flow);
         return Ok(new_flow)
     }
 
@@ -389,7 +488,7 @@ impl LayoutTreeBuilder {
         let ret = match ty {
             Flow_Absolute    => @AbsoluteFlow(move data),
             Flow_Block       => @BlockFlow(move data, BlockFlowData()),
-            Flow_Float       => @FloatFlow(move data),
+            Flow_Float       => @FloatFlow(move data, FloatFlowData()),
             Flow_InlineBlock => @InlineBlockFlow(move data),
             Flow_Inline      => @InlineFlow(move data, InlineFlowData()),
             Flow_Root        => @RootFlow(move data, RootFlowData()),
@@ -468,3 +567,132 @@ impl LayoutTreeBuilder {
         }
     }
 }
+
+priv fn is_inline_level(flow: @FlowContext) -> bool {
+    match *flow {
+        InlineFlow(*) | InlineBlockFlow(*) => true,
+        _ => false
+    }
+}
+
+priv fn is_block_container(flow: @FlowContext) -> bool {
+    match *flow {
+        AbsoluteFlow(*) | BlockFlow(*) | FloatFlow(*) | InlineBlockFlow(*) | RootFlow(*) => true,
+        _ => false
+    }
+}
+
+priv fn add_first_child(parent: @FlowContext, child: @FlowContext) {
+    let mut children = ~[];
+    for FlowTree.each_child(parent) |sibling| {
+        children.push(sibling);
+    }
+    for children.each |sibling| {
+        tree::remove_child(&FlowTree, parent, *sibling);
+    }
+    FlowTree.add_child(parent, child);
+    for children.each |sibling| {
+        FlowTree.add_child(parent, *sibling);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dom::node::NodeScope;
+    use geom::size::Size2D;
+    use gfx::display_list::CornerRadii;
+    use gfx::geometry::Au;
+    use layout::aux::LayoutAuxMethods;
+    use layout::context::test_layout_context;
+    use layout::test_builder;
+
+    #[test]
+    fn inline_runs_among_blocks_are_wrapped_in_anonymous_blocks() {
+        let root = test_builder::root().block().up().inline_boxes(1).inline_boxes(1).block().build();
+        LayoutTreeBuilder::new().normalize_children(root);
+
+        let mut children = ~[];
+        for FlowTree.each_child(root) |child| {
+            children.push(child);
+        }
+        assert children.len() == 3;
+        assert children[1].starts_block_flow() && children[1].d().node.is_none();
+
+        let mut wrapped = 0;
+        for FlowTree.each_child(children[1]) |child| {
+            assert child.starts_inline_flow();
+            wrapped += 1;
+        }
+        assert wrapped == 2;
+    }
+
+    #[test]
+    fn constructed_block_wraps_inline_content_among_blocks() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let scope = NodeScope();
+        let html = scope.new_node(Element(ElementData(~"html", ~HTMLDivElement)));
+        let body = scope.new_node(Element(ElementData(~"body", ~HTMLDivElement)));
+        scope.add_child(html, body);
+        scope.add_child(body, scope.new_node(Element(ElementData(~"div", ~HTMLDivElement))));
+        scope.add_child(body, scope.new_node(Text(~"a")));
+        scope.add_child(body, scope.new_node(Text(~"b")));
+        scope.add_child(body, scope.new_node(Element(ElementData(~"div", ~HTMLDivElement))));
+        let refs = DVec();
+        html.initialize_style_for_subtree(&refs);
+
+        let root = LayoutTreeBuilder::new().construct_trees(&ctx, html).get();
+        let body_flow = tree::first_child(&FlowTree, &root).get();
+        let mut children = ~[];
+        for FlowTree.each_child(body_flow) |child| {
+            children.push(child);
+        }
+        assert children.len() == 3;
+        assert children[0].starts_block_flow() && children[2].starts_block_flow();
+        assert children[1].starts_block_flow() && children[1].d().node.is_none();
+
+        let inline = tree::first_child(&FlowTree, &children[1]).get();
+        assert inline.starts_inline_flow() && tree::next_sibling(&FlowTree, &inline).is_none();
+        assert inline.inline().boxes.len() == 2;
+    }
+
+    #[test]
+    fn before_content_becomes_leading_box_of_block() {
+        let builder = test_builder::root().block().inline_boxes(1);
+        let original = builder.last_flow();
+        let node = builder.new_node();
+        let root = builder.build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().node = Some(node);
+        block.set_style(@ComputedStyle {
+            width: Auto,
+            height: AutoHeight,
+            box_sizing: ContentBox,
+            padding: BoxOffsets::zero(),
+            border: BoxOffsets::zero(),
+            background_color: None,
+            border_radius: CornerRadii::zero(),
+            before_content: Some(~"x"),
+            after_content: None
+        });
+        LayoutTreeBuilder::new().generate_content(root);
+
+        let generated = tree::first_child(&FlowTree, &block).get();
+        assert generated.starts_inline_flow();
+        assert generated.inline().boxes.len() == 1;
+        match generated.inline().boxes[0] {
+            @UnscannedTextBox(ref d, ref text) => {
+                assert *text == ~"x";
+                assert d.node == node;
+                match d.pseudo {
+                    Some(BeforePseudo) => {}
+                    _ => fail ~"expected a ::before box"
+                }
+            }
+            _ => fail ~"expected a text box"
+        }
+        assert core::managed::ptr_eq(tree::next_sibling(&FlowTree, &generated).get(), original);
+        assert core::managed::ptr_eq(tree::last_child(&FlowTree, &block).get(), original);
+        // the generated box isn't one of the element's own boxes
+        assert root.computed_box_for_node(node).is_none();
+    }
+}
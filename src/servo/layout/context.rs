@@ -1,7 +1,12 @@
 use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::display_list::DisplayList;
 use gfx::font_context::FontContext;
 use gfx::geometry::Au;
 use gfx::resource::local_image_cache::LocalImageCache;
+use layout::flow::FlowContext;
+use layout::inline::LineBreaker;
+use std::arc::{ARC, clone};
 use std::net::url::Url;
 
 /* Represents layout task context. */
@@ -10,5 +15,76 @@ pub struct LayoutContext {
     font_ctx: @FontContext,
     image_cache: @LocalImageCache,
     doc_url: Url,
-    screen_size: Rect<Au>
+    screen_size: Rect<Au>,
+    // where inline flows may break lines
+    line_breaker: @LineBreaker,
+    reflow_cache: @ReflowCache,
+    // the distance between tab stops in preformatted text, in spaces ('tab-size')
+    tab_size: uint,
+    // the factor that authored lengths are scaled by, for zooming the page;
+    // unlike the device pixel ratio, it changes the layout
+    zoom: float
+}
+
+/* The display list built by the last reflow, keyed by the content hash
+   of the flow tree and the context's layout parameters (see
+   `FlowContext::content_hash`) and by the viewport, so that laying out
+   an unchanged tree at the same size again can be skipped. The laid out
+   tree is kept with it, for queries. The list is shared rather than
+   managed, so that it can be sent to the renderer. */
+pub struct ReflowCache {
+    priv mut entry: Option<(u64, Rect<Au>, @FlowContext, ARC<DisplayList>)>,
+    // the number of reflows skipped because of a hit
+    mut skipped: uint
+}
+
+pub fn ReflowCache() -> ReflowCache {
+    ReflowCache {
+        entry: None,
+        skipped: 0
+    }
+}
+
+pub impl ReflowCache {
+    fn get(&self, hash: u64, viewport: &Rect<Au>) -> Option<(@FlowContext, ARC<DisplayList>)> {
+        match self.entry {
+            Some((entry_hash, ref entry_viewport, root, ref list)) if entry_hash == hash
+                && *entry_viewport == *viewport => {
+                self.skipped += 1;
+                Some((root, clone(list)))
+            }
+            _ => None
+        }
+    }
+
+    fn put(&self, hash: u64, viewport: &Rect<Au>, root: @FlowContext, list: &ARC<DisplayList>) {
+        self.entry = Some((hash, copy *viewport, root, clone(list)));
+    }
+
+    /* Forgets the cached list, e.g. because something that the content
+       hash doesn't cover, such as a loaded image, has changed. */
+    fn mark_dirty(&self) {
+        self.entry = None;
+    }
+}
+
+#[cfg(test)]
+pub fn test_layout_context(screen_size: Size2D<Au>) -> LayoutContext {
+    use azure::azure_hl::CairoBackend;
+    use geom::point::Point2D;
+    use gfx::resource::image_cache_task::ImageCacheTask;
+    use gfx::resource::resource_task::ResourceTask;
+    use gfx::util::url::make_url;
+    use layout::inline::SpaceLineBreaker;
+
+    LayoutContext {
+        font_ctx: @FontContext::new(CairoBackend, false),
+        image_cache: @LocalImageCache(ImageCacheTask(ResourceTask())),
+        doc_url: make_url(~"http://example.com/", None),
+        screen_size: Rect(Point2D(Au(0), Au(0)), screen_size),
+        line_breaker: @SpaceLineBreaker as @LineBreaker,
+        reflow_cache: @ReflowCache(),
+        tab_size: 8,
+        zoom: 1f
+    }
 }
@@ -9,14 +9,17 @@ use either::{Left, Right};
 use geom::point::Point2D;
 use geom::rect::Rect;
 use geom::size::Size2D;
+use layout::block::build_own_display_items;
 use layout::box::{RenderBox, TextBox};
 use layout::context::LayoutContext;
-use layout::flow::FlowContext;
+use layout::flow::{FlowContext, FlowTree, origin_in_tree};
 use layout::text::TextBoxData;
+use util::actor::ActorRef;
 use util::tree;
 use vec::push;
 
-use gfx::display_list::DisplayList;
+use gfx::display_list::{DisplayItem, DisplayList, PopScrollLayer, PopTranslate};
+use gfx::display_list::{PushScrollLayer, PushTranslate};
 use gfx::geometry::Au;
 
 /** A builder object that manages display list builder should mainly
@@ -30,9 +33,19 @@ pub struct DisplayListBuilder {
     ctx:  &LayoutContext,
 }
 
+/** The display items of one subtree of the flow tree, in the coordinates
+ of the flow that `build_display_list_chunked` was called on. */
+pub struct DisplayListChunk {
+    // the id of the flow at the top of the subtree
+    flow_id: int,
+    list: DisplayList
+}
 
 trait FlowDisplayListBuilderMethods {
     fn build_display_list(@self, a: &DisplayListBuilder, b: &Rect<Au>, c: &mut DisplayList);
+    fn build_display_list_chunked(@self, a: &DisplayListBuilder, b: &Rect<Au>,
+                                  c: &ActorRef<DisplayListChunk>) -> uint;
+    fn build_display_list_tiled(@self, a: &DisplayListBuilder, b: &[Rect<Au>], c: &mut DisplayList);
 
     fn build_display_list_for_child(@self, a: &DisplayListBuilder, b: @FlowContext,
                                     c: &Rect<Au>, d: &Point2D<Au>, e: &mut DisplayList);
@@ -42,7 +55,97 @@ impl FlowContext: FlowDisplayListBuilderMethods {
     fn build_display_list(@self, builder: &DisplayListBuilder, dirty: &Rect<Au>,
                           list: &mut DisplayList) {
         let zero = gfx::geometry::zero_point();
+        let (below, above) = stacked_flows(self);
+        for below.each |flow| {
+            build_display_list_for_stacked(self, builder, *flow, dirty, list);
+        }
         self.build_display_list_recurse(builder, dirty, &zero, list);
+        for above.each |flow| {
+            build_display_list_for_stacked(self, builder, *flow, dirty, list);
+        }
+    }
+
+    /** Like `build_display_list`, but for several dirty rects (e.g. tiles)
+     at once: an item is included if it intersects any of them. The tree is
+     traversed once, with the union of the tiles as the dirty rect, and the
+     items are culled against each tile afterwards. */
+    fn build_display_list_tiled(@self, builder: &DisplayListBuilder, dirty_tiles: &[Rect<Au>],
+                                list: &mut DisplayList) {
+        if dirty_tiles.is_empty() { return; }
+
+        let mut bounds = copy dirty_tiles[0];
+        for dirty_tiles.each |tile| {
+            bounds = bounds.union(tile);
+        }
+        let mut all_items = DisplayList::new();
+        self.build_display_list(builder, &bounds, &mut all_items);
+        cull_to_tiles(move all_items, dirty_tiles, list);
+    }
+
+    /** Like `build_display_list`, but sends the items to `sink` as they are
+     built, so that painting can start before the whole list is done: one
+     chunk for this flow's own box, then one for each child subtree, with
+     one for each flow painted apart from tree order before or after them.
+     A scroll container's layer is opened in its own chunk and closed in
+     one more after its children. Concatenated, the chunks are what
+     `build_display_list` builds. Returns the number of chunks sent. Only
+     block and root flows can be split this way. */
+    fn build_display_list_chunked(@self, builder: &DisplayListBuilder, dirty: &Rect<Au>,
+                                  sink: &ActorRef<DisplayListChunk>) -> uint {
+        let zero = gfx::geometry::zero_point();
+        let (below, above) = stacked_flows(self);
+        let mut chunks = 0;
+
+        for below.each |flow| {
+            let mut stacked_list = DisplayList::new();
+            build_display_list_for_stacked(self, builder, *flow, dirty, &mut stacked_list);
+            sink.send(DisplayListChunk { flow_id: flow.d().id, list: move stacked_list });
+            chunks += 1;
+        }
+
+        // as in build_display_list_recurse and build_display_list_root, an
+        // undisplayed flow, or a root in an empty viewport, paints nothing
+        // in tree order
+        let size = self.d().position.size;
+        let empty_root = self.starts_root_flow() && (size.width == Au(0) || size.height == Au(0));
+        if self.d().is_displayed && !empty_root {
+            let scrolls = self.is_scroll_container();
+            let mut own_list = DisplayList::new();
+            if scrolls {
+                let clip = Rect(copy zero, copy size);
+                let content = self.overflow_rect();
+                own_list.append_item(~DisplayItem::new_PushScrollLayer(&clip, &content));
+            }
+            build_own_display_items(self, builder, dirty, &zero, &mut own_list);
+            sink.send(DisplayListChunk { flow_id: self.d().id, list: move own_list });
+            chunks += 1;
+
+            for FlowTree.each_child(self) |child| {
+                // as in build_display_list_block, stacked children are painted apart
+                if child.effective_z_index() != 0 {
+                    loop;
+                }
+                let mut child_list = DisplayList::new();
+                self.build_display_list_for_child(builder, child, dirty, &zero, &mut child_list);
+                sink.send(DisplayListChunk { flow_id: child.d().id, list: move child_list });
+                chunks += 1;
+            }
+
+            if scrolls {
+                let mut pop_list = DisplayList::new();
+                pop_list.append_item(~DisplayItem::new_PopScrollLayer());
+                sink.send(DisplayListChunk { flow_id: self.d().id, list: move pop_list });
+                chunks += 1;
+            }
+        }
+
+        for above.each |flow| {
+            let mut stacked_list = DisplayList::new();
+            build_display_list_for_stacked(self, builder, *flow, dirty, &mut stacked_list);
+            sink.send(DisplayListChunk { flow_id: flow.d().id, list: move stacked_list });
+            chunks += 1;
+        }
+        chunks
     }
 
     fn build_display_list_for_child(@self,
@@ -51,7 +154,6 @@ impl FlowContext: FlowDisplayListBuilderMethods {
                                     dirty: &Rect<Au>, offset: &Point2D<Au>,
                                     list: &mut DisplayList) {
 
-        // adjust the dirty rect to child flow context coordinates
         let abs_flow_bounds = child_flow.d().position.translate(offset);
         let adj_offset = offset.add(&child_flow.d().position.origin);
 
@@ -62,9 +164,372 @@ impl FlowContext: FlowDisplayListBuilderMethods {
 
         if dirty.intersects(&abs_flow_bounds) {
             debug!("build_display_list_for_child: intersected. recursing into child flow...");
-            child_flow.build_display_list_recurse(builder, dirty, &adj_offset, list);
+            // The child flow's items are built in its own coordinate system, and
+            // positioned by a single translation instead of offsetting every item.
+            // So, adjust the dirty rect to child flow context coordinates.
+            let child_dirty = dirty.translate(&Point2D(-adj_offset.x, -adj_offset.y));
+            let zero = gfx::geometry::zero_point();
+            list.append_item(~DisplayItem::new_PushTranslate(&adj_offset));
+            child_flow.build_display_list_recurse(builder, &child_dirty, &zero, list);
+            list.append_item(~DisplayItem::new_PopTranslate());
         } else {
             debug!("build_display_list_for_child: Did not intersect...");
         }
     }
 }
+
+/* The descendants of `root` that aren't painted in tree order, in the
+   order that they are painted in: positioned flows with a negative
+   'z-index' beneath the rest of the tree, and those with a positive one
+   above it. Flows inside a 'display: none' subtree aren't painted at all. */
+priv fn stacked_flows(root: @FlowContext) -> (~[@FlowContext], ~[@FlowContext]) {
+    let mut below = ~[];
+    let mut above = ~[];
+    for root.compute_stacking_order().each |entry| {
+        let (flow, z_index) = *entry;
+        if z_index == 0 || core::managed::ptr_eq(flow, root) || !is_displayed_in(flow, root) {
+            loop;
+        }
+        if z_index < 0 { below.push(flow) } else { above.push(flow) }
+    }
+    (move below, move above)
+}
+
+/* Whether `flow` and each of its ancestors up to `root` are displayed. */
+priv fn is_displayed_in(flow: @FlowContext, root: @FlowContext) -> bool {
+    let mut cur = flow;
+    loop {
+        if !cur.d().is_displayed {
+            return false;
+        }
+        if core::managed::ptr_eq(cur, root) {
+            return true;
+        }
+        cur = match tree::parent(&FlowTree, &cur) {
+            Some(parent) => parent,
+            None => return true
+        };
+    }
+}
+
+/* Builds the items of `flow`, a descendant of `root` that isn't painted
+   in tree order, in `root`'s coordinates. */
+priv fn build_display_list_for_stacked(root: @FlowContext, builder: &DisplayListBuilder,
+                                       flow: @FlowContext, dirty: &Rect<Au>,
+                                       list: &mut DisplayList) {
+    let parent = tree::parent(&FlowTree, &flow).get();
+    let parent_origin = origin_in_tree(parent, root);
+    root.build_display_list_for_child(builder, flow, dirty, &parent_origin, list);
+}
+
+/* Appends the items of `items` that intersect any of `tiles` to `list`.
+   Translation and layer markers are always kept. */
+priv fn cull_to_tiles(items: DisplayList, tiles: &[Rect<Au>], list: &mut DisplayList) {
+    let mut offset = gfx::geometry::zero_point();
+    let mut saved_offsets = ~[];
+    for vec::consume(move items.list) |_i, item| {
+        let keep = match *item {
+            PushTranslate(ref translation) => {
+                saved_offsets.push(copy offset);
+                offset = offset.add(translation);
+                true
+            }
+            PopTranslate => {
+                offset = saved_offsets.pop();
+                true
+            }
+            PushScrollLayer(*) | PopScrollLayer => true,
+            _ => {
+                let bounds = item.d().bounds.translate(&offset);
+                tiles.any(|tile| tile.intersects(&bounds))
+            }
+        };
+        if keep {
+            list.append_item(move item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gfx::color::rgb;
+    use gfx::display_list::{CornerRadii, SolidColor};
+    use layout::flow::{Auto, AutoHeight, BoxOffsets, ComputedStyle, ContentBox, Hidden};
+    use layout::flow::{HeightAssigned, OverflowScroll, RelativePosition};
+    use layout::context::test_layout_context;
+    use layout::test_builder;
+    use std::cell::Cell;
+    use util::actor::{Actor, spawn};
+
+    // The tests set flow positions by hand, instead of running the layout passes.
+    fn mark_laid_out(root: @FlowContext) {
+        do tree::dfs(&FlowTree, &root) |flow| {
+            flow.d().phase = HeightAssigned;
+            true
+        };
+    }
+
+    #[test]
+    fn nested_flows_emit_translate_markers() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().block().build();
+        let outer = tree::first_child(&FlowTree, &root).get();
+        let inner = tree::first_child(&FlowTree, &outer).get();
+        root.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(800), Au::from_px(600)));
+        outer.d().position = Rect(Point2D(Au::from_px(10), Au::from_px(20)),
+                                  Size2D(Au::from_px(100), Au::from_px(100)));
+        inner.d().position = Rect(Point2D(Au::from_px(5), Au::from_px(5)),
+                                  Size2D(Au::from_px(50), Au::from_px(50)));
+        mark_laid_out(root);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        root.build_display_list(&builder, &copy root.d().position, &mut list);
+
+        assert list.list.len() == 4;
+        match *list.list[0] {
+            PushTranslate(offset) => assert offset == Point2D(Au::from_px(10), Au::from_px(20)),
+            _ => fail ~"expected a translation for the outer flow"
+        }
+        match *list.list[1] {
+            PushTranslate(offset) => assert offset == Point2D(Au::from_px(5), Au::from_px(5)),
+            _ => fail ~"expected a translation for the inner flow"
+        }
+        match (&*list.list[2], &*list.list[3]) {
+            (&PopTranslate, &PopTranslate) => {}
+            _ => fail ~"expected translations to be popped"
+        }
+    }
+
+    #[test]
+    fn scrollable_flow_emits_layer_markers_around_children() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().block().build();
+        let outer = tree::first_child(&FlowTree, &root).get();
+        let inner = tree::first_child(&FlowTree, &outer).get();
+        root.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(800), Au::from_px(600)));
+        outer.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(100), Au::from_px(100)));
+        inner.d().position = Rect(Point2D(Au(0), Au::from_px(150)),
+                                  Size2D(Au::from_px(50), Au::from_px(50)));
+        outer.d().overflow = OverflowScroll;
+        mark_laid_out(root);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        root.build_display_list(&builder, &copy root.d().position, &mut list);
+
+        // outer's translation, the layer, inner's translation
+        assert list.list.len() == 6;
+        match *list.list[1] {
+            PushScrollLayer(clip, content) => {
+                assert clip == Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(100), Au::from_px(100)));
+                assert content == Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(100), Au::from_px(200)));
+            }
+            _ => fail ~"expected a scroll layer for the outer flow"
+        }
+        match (&*list.list[2], &*list.list[3], &*list.list[4]) {
+            (&PushTranslate(_), &PopTranslate, &PopScrollLayer) => {}
+            _ => fail ~"expected the inner flow's items inside the scroll layer"
+        }
+    }
+
+    #[test]
+    fn positioned_flows_are_painted_in_z_index_order() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().up().block().build();
+        let first = tree::first_child(&FlowTree, &root).get();
+        let second = tree::last_child(&FlowTree, &root).get();
+        root.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(800), Au::from_px(600)));
+        first.d().position = square(0, 0);
+        second.d().position = square(0, 100);
+        first.d().position_scheme = RelativePosition;
+        first.d().z_index = 2;
+        second.d().position_scheme = RelativePosition;
+        second.d().z_index = 1;
+        mark_laid_out(root);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        root.build_display_list(&builder, &copy root.d().position, &mut list);
+
+        // the flow with the higher 'z-index' is painted last
+        assert list.list.len() == 4;
+        match (&*list.list[0], &*list.list[2]) {
+            (&PushTranslate(lower), &PushTranslate(higher)) => {
+                assert lower == Point2D(Au(0), Au::from_px(100));
+                assert higher == Point2D(Au(0), Au(0));
+            }
+            _ => fail ~"expected a translation for each flow"
+        }
+    }
+
+    #[test]
+    fn positioned_flow_in_undisplayed_subtree_is_not_painted() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().block().build();
+        let hidden = tree::first_child(&FlowTree, &root).get();
+        let positioned = tree::first_child(&FlowTree, &hidden).get();
+        root.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(800), Au::from_px(600)));
+        hidden.d().position = square(0, 0);
+        positioned.d().position = square(0, 0);
+        hidden.d().is_displayed = false;
+        positioned.d().position_scheme = RelativePosition;
+        positioned.d().z_index = 1;
+        mark_laid_out(root);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        root.build_display_list(&builder, &copy root.d().position, &mut list);
+
+        assert list.list.is_empty();
+    }
+
+    // the flow id of the chunk that stops a ChunkCollector
+    const STOP_COLLECTING: int = -1;
+
+    struct ChunkCollector {
+        chunks: Chan<DisplayListChunk>
+    }
+
+    impl ChunkCollector: Actor<DisplayListChunk> {
+        fn handle(&self, chunk: DisplayListChunk) -> bool {
+            if chunk.flow_id == STOP_COLLECTING {
+                return false;
+            }
+            self.chunks.send(move chunk);
+            return true;
+        }
+    }
+
+    fn stop_collecting(sink: &ActorRef<DisplayListChunk>) {
+        sink.send(DisplayListChunk { flow_id: STOP_COLLECTING, list: DisplayList::new() });
+    }
+
+    #[test]
+    fn chunked_build_sends_one_chunk_per_subtree() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().up().block().up().block().build();
+        root.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(800), Au::from_px(600)));
+
+        let (port, chan) = pipes::stream();
+        let chan = Cell(move chan);
+        let sink = do spawn |move chan| { ChunkCollector { chunks: chan.take() } };
+        mark_laid_out(root);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let chunks = root.build_display_list_chunked(&builder, &copy root.d().position, &sink);
+
+        assert chunks == 4;
+        let flow_ids = do vec::from_fn(chunks) |_i| { port.recv().flow_id };
+        stop_collecting(&sink);
+        assert flow_ids == ~[0, 1, 2, 3];
+    }
+
+    #[test]
+    fn chunked_build_paints_stacked_flows_in_z_index_order() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().up().block().up().block().build();
+        root.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(800), Au::from_px(600)));
+        let first = tree::first_child(&FlowTree, &root).get();
+        let last = tree::last_child(&FlowTree, &root).get();
+        first.d().position_scheme = RelativePosition;
+        first.d().z_index = 1;
+        last.d().position_scheme = RelativePosition;
+        last.d().z_index = -1;
+
+        let (port, chan) = pipes::stream();
+        let chan = Cell(move chan);
+        let sink = do spawn |move chan| { ChunkCollector { chunks: chan.take() } };
+        mark_laid_out(root);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let chunks = root.build_display_list_chunked(&builder, &copy root.d().position, &sink);
+
+        assert chunks == 4;
+        let flow_ids = do vec::from_fn(chunks) |_i| { port.recv().flow_id };
+        stop_collecting(&sink);
+        assert flow_ids == ~[3, 0, 2, 1];
+    }
+
+    #[test]
+    fn chunks_concatenate_to_the_whole_list() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().block().up().block().up().block().build();
+        let scroller = tree::first_child(&FlowTree, &root).get();
+        let inner = tree::first_child(&FlowTree, &scroller).get();
+        let hidden = tree::next_sibling(&FlowTree, &inner).get();
+        let stacked = tree::last_child(&FlowTree, &scroller).get();
+        for [scroller, inner, hidden, stacked].each |flow| {
+            flow.set_style(@ComputedStyle {
+                width: Auto,
+                height: AutoHeight,
+                box_sizing: ContentBox,
+                padding: BoxOffsets::zero(),
+                border: BoxOffsets::zero(),
+                background_color: Some(rgb(255, 0, 0)),
+                border_radius: CornerRadii::zero(),
+                before_content: None,
+                after_content: None
+            });
+        }
+        scroller.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(100), Au::from_px(100)));
+        inner.d().position = square(0, 0);
+        hidden.d().position = square(0, 50);
+        stacked.d().position = square(0, 150);
+        scroller.d().overflow = OverflowScroll;
+        hidden.d().visibility = Hidden;
+        stacked.d().position_scheme = RelativePosition;
+        stacked.d().z_index = 1;
+        mark_laid_out(root);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let dirty = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(800), Au::from_px(600)));
+        let mut whole = DisplayList::new();
+        scroller.build_display_list(&builder, &dirty, &mut whole);
+
+        let (port, chan) = pipes::stream();
+        let chan = Cell(move chan);
+        let sink = do spawn |move chan| { ChunkCollector { chunks: chan.take() } };
+        let chunks = scroller.build_display_list_chunked(&builder, &dirty, &sink);
+
+        // the scroller's own items and the layer's closing are chunks of their own
+        assert chunks == 5;
+        let mut joined = ~[];
+        for uint::range(0, chunks) |_i| {
+            let chunk = port.recv();
+            for chunk.list.list.each |item| { joined.push(fmt!("%?", *item)) }
+        }
+        stop_collecting(&sink);
+        let expected = do whole.list.map |item| { fmt!("%?", *item) };
+        assert joined == expected;
+    }
+
+    fn square(x: int, y: int) -> Rect<Au> {
+        Rect(Point2D(Au::from_px(x), Au::from_px(y)), Size2D(Au::from_px(10), Au::from_px(10)))
+    }
+
+    #[test]
+    fn culling_keeps_items_in_any_tile() {
+        let mut items = DisplayList::new();
+        items.append_item(~DisplayItem::new_SolidColor(&square(0, 0), rgb(0, 0, 0)));
+        items.append_item(~DisplayItem::new_PushTranslate(&Point2D(Au::from_px(500), Au(0))));
+        items.append_item(~DisplayItem::new_SolidColor(&square(0, 0), rgb(0, 0, 0)));
+        items.append_item(~DisplayItem::new_PopTranslate());
+        items.append_item(~DisplayItem::new_SolidColor(&square(250, 250), rgb(0, 0, 0)));
+
+        let tiles = [Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(100), Au::from_px(100))),
+                     Rect(Point2D(Au::from_px(450), Au(0)), Size2D(Au::from_px(100), Au::from_px(100)))];
+        let mut list = DisplayList::new();
+        cull_to_tiles(move items, tiles, &mut list);
+
+        // the item between the tiles is culled.
+        assert list.list.len() == 4;
+        match (&*list.list[0], &*list.list[2]) {
+            (&SolidColor(ref first, _), &SolidColor(ref second, _)) => {
+                assert first.bounds == square(0, 0);
+                assert second.bounds == square(0, 0);
+            }
+            _ => fail ~"expected an item from each tile"
+        }
+    }
+}
@@ -0,0 +1,182 @@
+/** Floats, and the float lists that their containing blocks keep
+    track of them in, for the purpose of clearance (CSS 2.1, 9.5). */
+
+use core::dvec::DVec;
+use geom::rect::Rect;
+use gfx::geometry::Au;
+use layout::block::stack_child_flows;
+use layout::context::LayoutContext;
+use layout::flow::{FlowContext, FloatFlow};
+
+/* Which side of the containing block a float is pushed to ('float'). */
+pub enum FloatSide {
+    FloatLeft,
+    FloatRight
+}
+
+/* Which preceding floats a block must be placed below ('clear'). */
+pub enum Clear {
+    ClearNone,
+    ClearLeft,
+    ClearRight,
+    ClearBoth
+}
+
+pub struct FloatFlowData {
+    // TODO: this should be read from the flow's CSS style
+    mut side: FloatSide
+}
+
+pub fn FloatFlowData() -> FloatFlowData {
+    FloatFlowData {
+        side: FloatLeft
+    }
+}
+
+struct PlacedFloat {
+    side: FloatSide,
+    // position, relative to the containing block's content
+    bounds: Rect<Au>
+}
+
+/* The floats placed so far in a block formatting context, in order. */
+pub struct FloatList {
+    priv floats: DVec<PlacedFloat>
+}
+
+pub fn FloatList() -> FloatList {
+    FloatList {
+        floats: DVec()
+    }
+}
+
+pub impl FloatList {
+    fn add(&self, side: FloatSide, bounds: &Rect<Au>) {
+        self.floats.push(PlacedFloat { side: side, bounds: copy *bounds });
+    }
+
+    fn reset(&self) {
+        self.floats.set(~[]);
+    }
+
+    pure fn len(&self) -> uint {
+        self.floats.len()
+    }
+
+    /** The lowest y that a block with the given 'clear' may start at:
+        the bottom of the lowest float on the cleared side(s). */
+    pure fn clearance(&self, clear: Clear) -> Au {
+        let mut bottom = Au(0);
+        for self.floats.each |f| {
+            let cleared = match (clear, f.side) {
+                (ClearBoth, _) | (ClearLeft, FloatLeft) | (ClearRight, FloatRight) => true,
+                _ => false
+            };
+            if cleared {
+                bottom = Au::max(bottom, f.bounds.origin.y + f.bounds.size.height);
+            }
+        }
+        bottom
+    }
+
+    /** The part of the span from `left` to `right` that isn't covered by
+        the floats overlapping the band from `top` to `top + height`. */
+    pure fn free_span(&self, top: Au, height: Au, left: Au, right: Au) -> (Au, Au) {
+        let mut free_left = left;
+        let mut free_right = right;
+        for self.floats.each |f| {
+            if !f.overlaps(top, height) {
+                loop;
+            }
+            match f.side {
+                FloatLeft => free_left = Au::max(free_left, f.bounds.origin.x + f.bounds.size.width),
+                FloatRight => free_right = Au::min(free_right, f.bounds.origin.x)
+            }
+        }
+        (free_left, free_right)
+    }
+
+    /** The nearest bottom edge of the floats overlapping the band from
+        `top` to `top + height`: the first y below `top` at which the free
+        span may be wider. */
+    pure fn next_float_bottom(&self, top: Au, height: Au) -> Option<Au> {
+        let mut next = None;
+        for self.floats.each |f| {
+            if f.overlaps(top, height) {
+                let bottom = f.bounds.origin.y + f.bounds.size.height;
+                next = Some(next.map_default(bottom, |b| Au::min(*b, bottom)));
+            }
+        }
+        next
+    }
+}
+
+impl PlacedFloat {
+    pure fn overlaps(&self, top: Au, height: Au) -> bool {
+        let float_top = self.bounds.origin.y;
+        let float_bottom = float_top + self.bounds.size.height;
+        // an empty band still overlaps the floats it starts inside of
+        (float_top < top + height || float_top <= top) && float_bottom > top
+    }
+}
+
+trait FloatLayout {
+    pure fn starts_float_flow() -> bool;
+
+    fn assign_height_float(@self, ctx: &LayoutContext);
+}
+
+impl FlowContext : FloatLayout {
+    pure fn starts_float_flow() -> bool {
+        match self {
+            FloatFlow(*) => true,
+            _ => false
+        }
+    }
+
+    /* A float is as tall as its declared height, or else as tall as
+       its stacked child contexts. */
+    fn assign_height_float(@self, ctx: &LayoutContext) {
+        assert self.starts_float_flow();
+
+        let content_height = stack_child_flows(self, ctx.zoom);
+        self.d().position.size.height = self.used_height(ctx, content_height);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geom::point::Point2D;
+    use geom::size::Size2D;
+
+    fn rect(y: int, height: int) -> Rect<Au> {
+        Rect(Point2D(Au(0), Au::from_px(y)), Size2D(Au::from_px(100), Au::from_px(height)))
+    }
+
+    #[test]
+    fn clearance_only_counts_floats_on_cleared_side() {
+        let floats = FloatList();
+        floats.add(FloatLeft, &rect(0, 50));
+        floats.add(FloatRight, &rect(10, 70));
+
+        assert floats.clearance(ClearNone) == Au(0);
+        assert floats.clearance(ClearLeft) == Au::from_px(50);
+        assert floats.clearance(ClearRight) == Au::from_px(80);
+        assert floats.clearance(ClearBoth) == Au::from_px(80);
+    }
+
+    #[test]
+    fn free_span_excludes_overlapping_floats() {
+        let floats = FloatList();
+        floats.add(FloatLeft, &rect(0, 50));
+        floats.add(FloatRight, &Rect(Point2D(Au::from_px(300), Au(0)),
+                                     Size2D(Au::from_px(100), Au::from_px(20))));
+
+        let (left, right) = floats.free_span(Au(0), Au::from_px(10), Au(0), Au::from_px(400));
+        assert left == Au::from_px(100) && right == Au::from_px(300);
+        let (left, right) = floats.free_span(Au::from_px(30), Au::from_px(10), Au(0), Au::from_px(400));
+        assert left == Au::from_px(100) && right == Au::from_px(400);
+        assert floats.next_float_bottom(Au(0), Au::from_px(10)) == Some(Au::from_px(20));
+        assert floats.next_float_bottom(Au::from_px(50), Au::from_px(10)) == None;
+    }
+}
@@ -1,9 +1,10 @@
 use dom::node::Node;
-use layout::block::BlockFlowData;
-use layout::box::RenderBox;
+use layout::block::{BlockFlowData, widest_children};
+use layout::box::{GenericBox, ImageBox, InlineBlockBox, RenderBox, TextBox, UnscannedTextBox};
 use layout::context::LayoutContext;
 use layout::debug::BoxedDebugMethods;
 use layout::display_list_builder::DisplayListBuilder;
+use layout::float::{Clear, ClearNone, FloatFlowData, FloatList};
 use layout::inline::{InlineFlowData, NodeRange};
 use layout::root::RootFlowData;
 use util::tree;
@@ -11,8 +12,12 @@ use util::tree;
 use core::dvec::DVec;
 use geom::rect::Rect;
 use geom::point::Point2D;
-use gfx::display_list::DisplayList;
+use geom::size::Size2D;
+use gfx::color::Color;
+use gfx::display_list::{CornerRadii, DisplayItem, DisplayList};
+use std::map::HashMap;
 use gfx::geometry::Au;
+use gfx::util::range::Range;
 
 /** Servo's experimental layout system builds a tree of FlowContexts
 and RenderBoxes, and figures out positions and display attributes of
@@ -46,7 +51,7 @@ context, such as linebox structures or float lists */
 pub enum FlowContext {
     AbsoluteFlow(FlowData), 
     BlockFlow(FlowData, BlockFlowData),
-    FloatFlow(FlowData),
+    FloatFlow(FlowData, FloatFlowData),
     InlineBlockFlow(FlowData),
     InlineFlow(FlowData, InlineFlowData),
     RootFlow(FlowData, RootFlowData),
@@ -78,6 +83,41 @@ struct FlowData {
     mut min_width: Au,
     mut pref_width: Au,
     mut position: Rect<Au>,
+    // the last layout pass that this flow has been through
+    mut phase: LayoutPhase,
+
+    /* box model inputs. TODO: these should be read from the flow's
+       CSS style, once flows have access to it. */
+    mut width: LengthOrAuto,
+    // 'min-width' and 'max-width'; 'auto' for no constraint ('none')
+    mut min_width_constraint: LengthOrAuto,
+    mut max_width_constraint: LengthOrAuto,
+    mut height: DeclaredHeight,
+    // share of the parent block's width, relative to the other weighted
+    // children; None to take the whole width
+    mut width_weight: Option<int>,
+    mut box_sizing: BoxSizing,
+    mut padding: BoxOffsets,
+    mut border: BoxOffsets,
+    mut margin_left: LengthOrAuto,
+    mut margin_right: LengthOrAuto,
+    mut clear: Clear,
+    mut overflow: Overflow,
+    mut position_scheme: PositionScheme,
+    // the stack level among positioned flows ('z-index'); ignored for
+    // static flows
+    mut z_index: int,
+    // false for flows of 'display: none' elements, which every pass skips
+    mut is_displayed: bool,
+    // whether the flow paints its own boxes; hidden flows still take up
+    // space, and their children may be visible
+    mut visibility: Visibility,
+    // the computed style that the box model inputs above were read from
+    mut style: Option<@ComputedStyle>,
+
+    /* floats placed among this flow's children, if it is their
+       containing block */
+    floats: FloatList,
 }
 
 fn FlowData(id: int) -> FlowData {
@@ -88,16 +128,244 @@ fn FlowData(id: int) -> FlowData {
 
         min_width: Au(0),
         pref_width: Au(0),
-        position: Au::zero_rect()
+        position: Au::zero_rect(),
+        phase: NotLaidOut,
+
+        width: Auto,
+        min_width_constraint: Auto,
+        max_width_constraint: Auto,
+        height: AutoHeight,
+        width_weight: None,
+        box_sizing: ContentBox,
+        padding: BoxOffsets::zero(),
+        border: BoxOffsets::zero(),
+        margin_left: Length(Au(0)),
+        margin_right: Length(Au(0)),
+        clear: ClearNone,
+        overflow: OverflowVisible,
+        position_scheme: StaticPosition,
+        z_index: 0,
+        is_displayed: true,
+        visibility: Visible,
+        style: None,
+
+        floats: FloatList()
+    }
+}
+
+/* The layout passes, in the order that they must run on a flow. */
+pub enum LayoutPhase {
+    NotLaidOut,
+    WidthsBubbled,
+    WidthsAssigned,
+    HeightAssigned
+}
+
+/* Fails if the last pass that `flow` has been through isn't the
+   `required` one. Running `pass` on it anyway would silently use stale or
+   missing results: a flow that was laid out before must have its widths
+   bubbled again before they are assigned. Only checked in debug builds. */
+#[cfg(debug)]
+priv fn check_phase(flow: &FlowContext, required: LayoutPhase, pass: &str) {
+    if (flow.d().phase as uint) != (required as uint) {
+        fail fmt!("%s of f%d without %? first: the flow is %?",
+                  pass, flow.d().id, required, flow.d().phase);
+    }
+}
+
+#[cfg(ndebug)]
+priv fn check_phase(_flow: &FlowContext, _required: LayoutPhase, _pass: &str) {}
+
+/* Describes what is wrong with the geometry that `pass` gave `flow`, if
+   it is absurd: a negative size, or an edge out of the range of `Au`.
+   Arithmetic that overflowed in the pass leaves such wrapped values. */
+priv fn geometry_error(flow: &FlowContext, pass: &str) -> Option<~str> {
+    let position = &flow.d().position;
+    let right = position.origin.x.checked_add(position.size.width);
+    let bottom = position.origin.y.checked_add(position.size.height);
+    if position.size.width < Au(0) || position.size.height < Au(0)
+        || right.is_none() || bottom.is_none() {
+        Some(fmt!("%s of f%d overflowed: position is %?", pass, flow.d().id, *position))
+    } else {
+        None
+    }
+}
+
+/* Fails if `pass` gave `flow` absurd geometry, naming the flow, rather than
+   letting wrapped coordinates spread to the rest of the tree. Only checked
+   in debug builds. */
+#[cfg(debug)]
+priv fn check_geometry(flow: &FlowContext, pass: &str) {
+    match geometry_error(flow, pass) {
+        Some(move error) => fail error,
+        None => {}
     }
 }
 
+#[cfg(ndebug)]
+priv fn check_geometry(_flow: &FlowContext, _pass: &str) {}
+
+/* How a declared width maps onto the content width
+   (CSS3 UI, 'box-sizing' property). */
+pub enum BoxSizing {
+    // the declared width is the width of the content box
+    ContentBox,
+    // the declared width includes padding and border
+    BorderBox
+}
+
+/* A declared 'width' or horizontal margin. Percentages are of the
+   containing block's width (CSS 2.1, 10.2 and 8.3). */
+pub enum LengthOrAuto {
+    Length(Au),
+    Percent(float),
+    Auto
+}
+
+pub impl LengthOrAuto {
+    /* The used length, given the containing block's width; None for 'auto'. */
+    pure fn resolve(&self, containing_width: Au) -> Option<Au> {
+        match *self {
+            Length(length) => Some(length),
+            Percent(percent) => Some(containing_width.scale_by(percent / 100f)),
+            Auto => None
+        }
+    }
+
+    /* This length as it is used at `zoom`. Percentages are of lengths
+       that are already zoomed, so they stay the same. */
+    pure fn zoomed(&self, zoom: float) -> LengthOrAuto {
+        match *self {
+            Length(length) => Length(length.scale_by(zoom)),
+            Percent(percent) => Percent(percent),
+            Auto => Auto
+        }
+    }
+
+    /* The length, if it doesn't depend on the containing block. */
+    pure fn definite_length(&self) -> Option<Au> {
+        match *self {
+            Length(length) => Some(length),
+            Percent(*) | Auto => None
+        }
+    }
+}
+
+/* A declared 'height'. Percentages are of the containing block's height,
+   and behave as 'auto' if that height isn't definite (CSS 2.1, 10.5). */
+pub enum DeclaredHeight {
+    AutoHeight,
+    FixedHeight(Au),
+    PercentHeight(float)
+}
+
+/* A copy of `d` with the given id, outside of any tree. Floats are
+   recomputed by layout, so they aren't copied. */
+priv fn clone_flow_data(d: &FlowData, id: int) -> FlowData {
+    FlowData {
+        node: d.node,
+        tree: tree::empty(),
+        id: id,
+
+        min_width: d.min_width,
+        pref_width: d.pref_width,
+        position: copy d.position,
+        phase: d.phase,
+
+        width: d.width,
+        min_width_constraint: d.min_width_constraint,
+        max_width_constraint: d.max_width_constraint,
+        height: d.height,
+        width_weight: d.width_weight,
+        box_sizing: d.box_sizing,
+        padding: copy d.padding,
+        border: copy d.border,
+        margin_left: d.margin_left,
+        margin_right: d.margin_right,
+        clear: d.clear,
+        overflow: d.overflow,
+        position_scheme: d.position_scheme,
+        z_index: d.z_index,
+        is_displayed: d.is_displayed,
+        visibility: d.visibility,
+        style: d.style,
+
+        floats: FloatList()
+    }
+}
+
+/* How a flow is positioned ('position'). */
+pub enum PositionScheme {
+    StaticPosition,
+    RelativePosition,
+    AbsolutePosition,
+    FixedPosition
+}
+
+/* What happens to content that doesn't fit in the flow ('overflow'). */
+pub enum Overflow {
+    OverflowVisible,
+    OverflowHidden,
+    OverflowScroll,
+    OverflowAuto
+}
+
+/* Whether a flow's own boxes are painted ('visibility'). */
+pub enum Visibility {
+    Visible,
+    Hidden
+}
+
+/* Widths of one box-model edge (padding, border, margin) on each side. */
+pub struct BoxOffsets {
+    mut top: Au,
+    mut right: Au,
+    mut bottom: Au,
+    mut left: Au
+}
+
+pub impl BoxOffsets {
+    static pure fn zero() -> BoxOffsets {
+        BoxOffsets { top: Au(0), right: Au(0), bottom: Au(0), left: Au(0) }
+    }
+
+    /* These widths as they are used at `zoom`. */
+    pure fn zoomed(&self, zoom: float) -> BoxOffsets {
+        BoxOffsets {
+            top: self.top.scale_by(zoom),
+            right: self.right.scale_by(zoom),
+            bottom: self.bottom.scale_by(zoom),
+            left: self.left.scale_by(zoom)
+        }
+    }
+
+    pure fn horizontal(&self) -> Au { self.left + self.right }
+    pure fn vertical(&self) -> Au { self.top + self.bottom }
+}
+
+/* The computed values of the properties that layout reads. TODO: this
+   should be the CSS engine's computed style, once layout can hold on
+   to it. */
+pub struct ComputedStyle {
+    width: LengthOrAuto,
+    height: DeclaredHeight,
+    box_sizing: BoxSizing,
+    padding: BoxOffsets,
+    border: BoxOffsets,
+    background_color: Option<Color>,
+    border_radius: CornerRadii,
+    // the text of the flow's '::before' and '::after' pseudo-elements
+    // ('content'); only string content is supported
+    before_content: Option<~str>,
+    after_content: Option<~str>
+}
+
 impl FlowContext  {
     pure fn d(&self) -> &self/FlowData {
         match *self {
             AbsoluteFlow(ref d)    => d,
             BlockFlow(ref d, _)    => d,
-            FloatFlow(ref d)       => d,
+            FloatFlow(ref d, _)    => d,
             InlineBlockFlow(ref d) => d,
             InlineFlow(ref d, _)   => d,
             RootFlow(ref d, _)     => d,
@@ -119,6 +387,13 @@ impl FlowContext  {
         }
     }
 
+    pure fn float_data(&self) -> &self/FloatFlowData {
+        match *self {
+            FloatFlow(_, ref f) => f,
+            _ => fail fmt!("Tried to access float data of non-float: f%d", self.d().id)
+        }
+    }
+
     pure fn root(&self) -> &self/RootFlowData {
         match *self {
             RootFlow(_, ref r) => r,
@@ -126,36 +401,168 @@ impl FlowContext  {
         }
     }
 
+    /// The name of this flow's variant, e.g. "BlockFlow", for logging.
+    pure fn type_name(&self) -> &static/str {
+        match *self {
+            AbsoluteFlow(*)    => "AbsoluteFlow",
+            BlockFlow(*)       => "BlockFlow",
+            FloatFlow(*)       => "FloatFlow",
+            InlineBlockFlow(*) => "InlineBlockFlow",
+            InlineFlow(*)      => "InlineFlow",
+            RootFlow(*)        => "RootFlow",
+            TableFlow(*)       => "TableFlow"
+        }
+    }
+
     fn bubble_widths(@self, ctx: &LayoutContext) {
+        if !self.d().is_displayed {
+            self.d().min_width = Au(0);
+            self.d().pref_width = Au(0);
+            self.d().phase = WidthsBubbled;
+            return;
+        }
         match self {
             @BlockFlow(*)  => self.bubble_widths_block(ctx),
+            @AbsoluteFlow(*) | @FloatFlow(*) | @InlineBlockFlow(*) => {
+                self.bubble_widths_shrink_to_fit(ctx)
+            }
             @InlineFlow(*) => self.bubble_widths_inline(ctx),
             @RootFlow(*)   => self.bubble_widths_root(ctx),
             _ => fail fmt!("Tried to bubble_widths of flow: f%d", self.d().id)
         }
+        self.d().phase = WidthsBubbled;
+    }
+
+    /** Computes the (min, pref) widths of this flow's subtree, as
+        `bubble_widths` would, but without storing them in any flow.
+        Text runs in inline flows must already have been scanned. */
+    fn intrinsic_widths(@self, ctx: &LayoutContext) -> (Au, Au) {
+        if !self.d().is_displayed {
+            return (Au(0), Au(0));
+        }
+        match self {
+            @BlockFlow(*) | @RootFlow(*) => {
+                let mut child_widths = ~[];
+                for FlowTree.each_child(self) |child| {
+                    child_widths.push(child.intrinsic_widths(ctx));
+                }
+                self.intrinsic_widths_block(ctx, child_widths)
+            },
+            @AbsoluteFlow(*) | @FloatFlow(*) | @InlineBlockFlow(*) => {
+                let mut child_widths = ~[];
+                for FlowTree.each_child(self) |child| {
+                    child_widths.push(child.intrinsic_widths(ctx));
+                }
+                widest_children(child_widths)
+            },
+            @InlineFlow(*) => self.intrinsic_widths_inline(ctx),
+            _ => fail fmt!("Tried to compute intrinsic_widths of flow: f%d", self.d().id)
+        }
     }
 
     fn assign_widths(@self, ctx: &LayoutContext) {
+        check_phase(self, WidthsBubbled, "assign_widths");
+        if !self.d().is_displayed {
+            self.d().position.size.width = Au(0);
+            self.d().phase = WidthsAssigned;
+            return;
+        }
         match self {
             @BlockFlow(*)  => self.assign_widths_block(ctx),
+            @AbsoluteFlow(*) | @FloatFlow(*) | @InlineBlockFlow(*) => {
+                self.assign_widths_shrink_to_fit(ctx)
+            }
             @InlineFlow(*) => self.assign_widths_inline(ctx),
             @RootFlow(*)   => self.assign_widths_root(ctx),
             _ => fail fmt!("Tried to assign_widths of flow: f%d", self.d().id)
         }
+        check_geometry(self, "assign_widths");
+        self.d().phase = WidthsAssigned;
     }
 
     fn assign_height(@self, ctx: &LayoutContext) {
+        check_phase(self, WidthsAssigned, "assign_height");
+        if !self.d().is_displayed {
+            self.d().position.size.height = Au(0);
+            self.d().phase = HeightAssigned;
+            return;
+        }
         match self {
-            @BlockFlow(*)  => self.assign_height_block(ctx),
-            @InlineFlow(*) => self.assign_height_inline(ctx),
-            @RootFlow(*)   => self.assign_height_root(ctx),
+            @AbsoluteFlow(*)    => self.assign_height_absolute(ctx),
+            @BlockFlow(*)       => self.assign_height_block(ctx),
+            @FloatFlow(*)       => self.assign_height_float(ctx),
+            @InlineBlockFlow(*) => self.assign_height_inline_block(ctx),
+            @InlineFlow(*)      => self.assign_height_inline(ctx),
+            @RootFlow(*)        => self.assign_height_root(ctx),
             _ => fail fmt!("Tried to assign_height of flow: f%d", self.d().id)
         }
+        check_geometry(self, "assign_height");
+        self.d().phase = HeightAssigned;
+    }
+
+    /** The height of this flow's containing block, if it is definite,
+        i.e. known without laying out the containing block's content. */
+    fn containing_block_height(@self, ctx: &LayoutContext) -> Option<Au> {
+        match tree::parent(&FlowTree, &self) {
+            Some(parent) => parent.definite_height(ctx),
+            None => None
+        }
+    }
+
+    /** This flow's height, if it is definite. The initial containing
+        block, i.e. the root flow, is as tall as the viewport. */
+    fn definite_height(@self, ctx: &LayoutContext) -> Option<Au> {
+        match self.d().height {
+            FixedHeight(height) => Some(height.scale_by(ctx.zoom)),
+            PercentHeight(percent) => do self.containing_block_height(ctx).map |cb_height| {
+                cb_height.scale_by(percent / 100f)
+            },
+            AutoHeight => match self {
+                @RootFlow(*) => Some(self.root().viewport.get_default(ctx.screen_size).size.height),
+                _ => None
+            }
+        }
+    }
+
+    /** The height of this flow once its content is `content_height` tall. */
+    fn used_height(@self, ctx: &LayoutContext, content_height: Au) -> Au {
+        let height = match self.d().height {
+            AutoHeight => content_height,
+            _ => self.definite_height(ctx).get_default(content_height)
+        };
+        // used heights are never negative (CSS 2.1, 10.5)
+        Au::max(height, Au(0))
+    }
+
+    /** Keeps a reference to `style`, and reads the box model inputs from
+        it, so that the width and height passes don't resolve the style
+        again. */
+    fn set_style(@self, style: @ComputedStyle) {
+        let d = self.d();
+        d.width = style.width;
+        d.height = style.height;
+        d.box_sizing = style.box_sizing;
+        d.padding = copy style.padding;
+        d.border = copy style.border;
+        d.style = Some(style);
     }
 
     fn build_display_list_recurse(@self, builder: &DisplayListBuilder, dirty: &Rect<Au>,
                                   offset: &Point2D<Au>, list: &mut DisplayList) {
         debug!("FlowContext::build_display_list at %?: %s", self.d().position, self.debug_str());
+        check_phase(self, HeightAssigned, "build_display_list");
+        if !self.d().is_displayed {
+            return;
+        }
+
+        // The content of a scrollable flow goes in its own layer, so that the
+        // renderer can scroll it without rebuilding the display list.
+        let scrolls = self.is_scroll_container();
+        if scrolls {
+            let clip = Rect(copy *offset, copy self.d().position.size);
+            let content = self.overflow_rect().translate(offset);
+            list.append_item(~DisplayItem::new_PushScrollLayer(&clip, &content));
+        }
 
         match self {
             @RootFlow(*) => self.build_display_list_root(builder, dirty, offset, list),
@@ -163,6 +570,107 @@ impl FlowContext  {
             @InlineFlow(*) => self.build_display_list_inline(builder, dirty, offset, list),
             _ => fail fmt!("Tried to build_display_list_recurse of flow: %?", self)
         }
+
+        if scrolls {
+            list.append_item(~DisplayItem::new_PopScrollLayer());
+        }
+    }
+
+    /** The flow that this flow is positioned and sized relative to
+        (CSS 2.1, 10.1): the viewport, i.e. the root flow, for fixed flows;
+        the nearest positioned ancestor (or the root) for absolute flows;
+        and the parent for other flows. The root is its own containing block. */
+    fn containing_block(@self) -> @FlowContext {
+        let mut ancestor = match tree::parent(&FlowTree, &self) {
+            Some(parent) => parent,
+            None => return self
+        };
+        loop {
+            let found = match (self.d().position_scheme, ancestor.d().position_scheme) {
+                (StaticPosition, _) | (RelativePosition, _) => true,
+                (AbsolutePosition, StaticPosition) => false,
+                (AbsolutePosition, _) => true,
+                (FixedPosition, _) => false
+            };
+            if found {
+                return ancestor;
+            }
+            match tree::parent(&FlowTree, &ancestor) {
+                Some(parent) => ancestor = parent,
+                None => return ancestor
+            }
+        }
+    }
+
+    /// Whether this flow scrolls its content ('overflow: scroll' or 'auto').
+    pure fn is_scroll_container(@self) -> bool {
+        match self.d().overflow {
+            OverflowScroll | OverflowAuto => true,
+            OverflowVisible | OverflowHidden => false
+        }
+    }
+
+    /** The nearest ancestor that scrolls its content, which scrolling this
+        flow into view or a wheel event over it should scroll. The root,
+        i.e. the viewport, scrolls if no other ancestor does. None for the
+        root itself. */
+    fn nearest_scroll_container(@self) -> Option<@FlowContext> {
+        let mut ancestor = match tree::parent(&FlowTree, &self) {
+            Some(parent) => parent,
+            None => return None
+        };
+        loop {
+            if ancestor.is_scroll_container() {
+                return Some(ancestor);
+            }
+            match tree::parent(&FlowTree, &ancestor) {
+                Some(parent) => ancestor = parent,
+                None => return Some(ancestor)
+            }
+        }
+    }
+
+    /** Copies this flow's subtree, so that it can be laid out differently
+        without disturbing this one. The copies get fresh ids, larger than
+        any id in this subtree. Render boxes are shared, not copied. */
+    fn deep_clone(@self) -> @FlowContext {
+        let mut next_id = 0;
+        do tree::dfs(&FlowTree, &self) |flow| {
+            next_id = int::max(next_id, flow.d().id + 1);
+            true
+        };
+        self.deep_clone_with_ids(&mut next_id)
+    }
+
+    priv fn deep_clone_with_ids(@self, next_id: &mut int) -> @FlowContext {
+        let data = clone_flow_data(self.d(), *next_id);
+        *next_id += 1;
+
+        let clone = match *self {
+            AbsoluteFlow(*) => @AbsoluteFlow(move data),
+            BlockFlow(_, ref b) => @BlockFlow(move data, BlockFlowData { box: b.box,
+                                                                       marker: b.marker }),
+            FloatFlow(_, ref f) => @FloatFlow(move data, FloatFlowData { side: f.side }),
+            InlineBlockFlow(*) => @InlineBlockFlow(move data),
+            InlineFlow(_, ref i) => @InlineFlow(move data, i.clone()),
+            RootFlow(_, ref r) => @RootFlow(move data, RootFlowData { box: r.box,
+                                                                     viewport: copy r.viewport }),
+            TableFlow(*) => @TableFlow(move data)
+        };
+        for FlowTree.each_child(self) |child| {
+            FlowTree.add_child(clone, child.deep_clone_with_ids(next_id));
+        }
+        clone
+    }
+
+    /** The area covered by this flow and its child flows, relative to
+        this flow's origin. Only meaningful after layout. */
+    fn overflow_rect(@self) -> Rect<Au> {
+        let mut rect = Rect(Au::zero_point(), copy self.d().position.size);
+        for FlowTree.each_child(self) |child| {
+            rect = rect.union(&child.d().position);
+        }
+        rect
     }
 
     // Actual methods that do not require much flow-specific logic
@@ -179,7 +687,7 @@ impl FlowContext  {
     pure fn foldl_boxes_for_node<B: Copy>(node: Node, seed: B, 
                                           cb: pure fn&(a: B,@RenderBox) -> B) -> B {
         do self.foldl_all_boxes(seed) |acc, box| {
-            if box.d().node == node { cb(acc, box) }
+            if box.d().is_box_of(node) { cb(acc, box) }
             else { acc }
         }
     }
@@ -196,9 +704,836 @@ impl FlowContext  {
     pure fn iter_boxes_for_node<T>(node: Node,
                                    cb: pure fn&(@RenderBox) -> T) {
         do self.iter_all_boxes |box| {
-            if box.d().node == node { cb(box); }
+            if box.d().is_box_of(node) { cb(box); }
+        }
+    }
+
+    /** Dumps the flow tree with each flow's computed min/pref widths and
+        position, one flow per line. Only meaningful after layout. */
+    fn dump_layout(@self) -> ~str {
+        self.dump_layout_indent(0u)
+    }
+
+    priv fn dump_layout_indent(@self, indent: uint) -> ~str {
+        let mut s = ~"";
+        for uint::range(0u, indent) |_i| {
+            s += ~"    ";
+        }
+
+        let d = self.d();
+        s += fmt!("f%d: min_width=%dpx pref_width=%dpx position=(%dpx, %dpx, %dpx, %dpx)\n",
+                  d.id, d.min_width.to_px(), d.pref_width.to_px(),
+                  d.position.origin.x.to_px(), d.position.origin.y.to_px(),
+                  d.position.size.width.to_px(), d.position.size.height.to_px());
+
+        for FlowTree.each_child(self) |child| {
+            s += child.dump_layout_indent(indent + 1u);
         }
+        move s
     }
+
+    /** Serializes the laid-out flow tree tersely, for comparing against
+        expected output in tests: one `id:type:min,pref:x,y,w,h` line per
+        flow, in pixels, indented by two spaces per level. */
+    fn serialize_layout(@self) -> ~str {
+        let mut s = ~"";
+        self.serialize_layout_indent(0u, &mut s);
+        move s
+    }
+
+    priv fn serialize_layout_indent(@self, depth: uint, s: &mut ~str) {
+        for uint::range(0u, depth) |_i| {
+            *s += ~"  ";
+        }
+        let d = self.d();
+        *s += fmt!("%d:%s:%d,%d:%d,%d,%d,%d\n", d.id, self.type_name(),
+                   d.min_width.to_px(), d.pref_width.to_px(),
+                   d.position.origin.x.to_px(), d.position.origin.y.to_px(),
+                   d.position.size.width.to_px(), d.position.size.height.to_px());
+        for FlowTree.each_child(self) |child| {
+            child.serialize_layout_indent(depth + 1u, s);
+        }
+    }
+
+    /** A hash of this flow subtree's structure, boxes and layout inputs,
+        and of the parameters of `ctx` that change layout: the things that
+        layout results depend on, other than the viewport. Positions
+        computed by layout are left out, so the hash is the same before
+        and after a reflow. */
+    fn content_hash(@self, ctx: &LayoutContext) -> u64 {
+        let hasher = ContentHasher();
+        hasher.mix_float(ctx.zoom);
+        hasher.mix_int(ctx.tab_size as int);
+        hasher.mix_str(ctx.line_breaker.name());
+        self.content_key(&hasher);
+        hasher.hash
+    }
+
+    priv fn content_key(@self, hasher: &ContentHasher) {
+        let d = self.d();
+        hasher.mix_int(d.id);
+        hasher.mix_str(self.type_name());
+        hasher.mix_length(&d.width);
+        hasher.mix_length(&d.min_width_constraint);
+        hasher.mix_length(&d.max_width_constraint);
+        hasher.mix_height(&d.height);
+        match d.width_weight {
+            Some(weight) => { hasher.mix_bool(true); hasher.mix_int(weight); }
+            None => hasher.mix_bool(false)
+        }
+        hasher.mix_int(d.box_sizing as int);
+        hasher.mix_offsets(&d.padding);
+        hasher.mix_offsets(&d.border);
+        hasher.mix_length(&d.margin_left);
+        hasher.mix_length(&d.margin_right);
+        hasher.mix_int(d.clear as int);
+        hasher.mix_int(d.overflow as int);
+        hasher.mix_int(d.position_scheme as int);
+        hasher.mix_int(d.z_index);
+        hasher.mix_bool(d.is_displayed);
+        hasher.mix_int(d.visibility as int);
+        do d.style.iter |style| {
+            match style.background_color {
+                Some(color) => {
+                    hasher.mix_bool(true);
+                    hasher.mix_float(color.r as float);
+                    hasher.mix_float(color.g as float);
+                    hasher.mix_float(color.b as float);
+                    hasher.mix_float(color.a as float);
+                }
+                None => hasher.mix_bool(false)
+            }
+            hasher.mix_au(style.border_radius.top_left);
+            hasher.mix_au(style.border_radius.top_right);
+            hasher.mix_au(style.border_radius.bottom_right);
+            hasher.mix_au(style.border_radius.bottom_left);
+        }
+
+        match *self {
+            RootFlow(_, ref r) => {
+                hasher.mix_box(r.box);
+                match r.viewport {
+                    Some(viewport) => {
+                        hasher.mix_bool(true);
+                        hasher.mix_au(viewport.origin.x);
+                        hasher.mix_au(viewport.origin.y);
+                        hasher.mix_au(viewport.size.width);
+                        hasher.mix_au(viewport.size.height);
+                    }
+                    None => hasher.mix_bool(false)
+                }
+            }
+            BlockFlow(_, ref b) => {
+                hasher.mix_box(b.box);
+                hasher.mix_box(b.marker);
+            }
+            FloatFlow(_, ref f) => hasher.mix_int(f.side as int),
+            InlineFlow(_, ref i) => {
+                hasher.mix_int(i.boxes.len() as int);
+                for i.boxes.each |box| {
+                    hasher.mix_box(Some(*box));
+                }
+                match i.text_align {
+                    Some(align) => { hasher.mix_bool(true); hasher.mix_int(align as int); }
+                    None => hasher.mix_bool(false)
+                }
+                hasher.mix_option_au(i.line_height);
+                hasher.mix_au(i.text_indent);
+                hasher.mix_int(i.white_space as int);
+                hasher.mix_int(i.text_overflow as int);
+            }
+            AbsoluteFlow(*) | InlineBlockFlow(*) | TableFlow(*) => {}
+        }
+
+        // the subtree's end is marked, so that the same flows in another
+        // shape hash differently
+        for FlowTree.each_child(self) |child| {
+            child.content_key(hasher);
+        }
+        hasher.mix_int(-1);
+    }
+
+    /// Whether this flow is the first of its parent's children.
+    fn is_first_child(@self) -> bool {
+        tree::parent(&FlowTree, &self).is_some() && tree::prev_sibling(&FlowTree, &self).is_none()
+    }
+
+    /// Whether this flow is the last of its parent's children.
+    fn is_last_child(@self) -> bool {
+        tree::parent(&FlowTree, &self).is_some() && tree::next_sibling(&FlowTree, &self).is_none()
+    }
+
+    /** Removes this flow from its parent's children, if it has a parent,
+        and returns it as the root of its own subtree, to be added to
+        another parent. */
+    fn detach(@self) -> @FlowContext {
+        match tree::parent(&FlowTree, &self) {
+            Some(parent) => tree::remove_child(&FlowTree, parent, self),
+            None => {}
+        }
+        self
+    }
+
+    /** A block flow that takes this inline flow's place in the tree, for
+        when the 'display' of its element changes. It keeps this flow's
+        data, id included, its parent, siblings and children; this flow is
+        left outside of any tree, so callers must use the returned flow.
+        The block's box is the first of the inline's boxes for the flow's
+        own node, if any. The other boxes, e.g. those of the element's
+        descendants, go in an anonymous inline flow that becomes the
+        block's only child. A block flow is returned as it is. */
+    fn become_block(@self) -> @FlowContext {
+        let (block, content) = match *self {
+            BlockFlow(*) => return self,
+            InlineFlow(ref d, ref i) => {
+                let boxes = i.boxes.get();
+                let own = vec::position(boxes, |box| d.node.map_default(false, |node| box.d().is_box_of(*node)));
+                let box = own.map(|i| boxes[*i]);
+                let block = @BlockFlow(clone_flow_data(d, d.id), BlockFlowData { box: box, marker: None });
+                do box.iter |box| { box.d().ctx = block }
+
+                let other_boxes = DVec();
+                for boxes.eachi |j, box| {
+                    if Some(j) != own { other_boxes.push(*box) }
+                }
+                let content = if other_boxes.is_empty() {
+                    None
+                } else {
+                    let mut data = InlineFlowData();
+                    data.elems = i.elems.clone();
+                    data.elems.repair_for_box_changes(&i.boxes, &other_boxes);
+                    data.boxes = move other_boxes;
+                    data.text_align = i.text_align;
+                    data.line_height = i.line_height;
+                    data.text_indent = i.text_indent;
+                    data.white_space = i.white_space;
+                    data.text_overflow = i.text_overflow;
+                    let content = @InlineFlow(FlowData(unused_flow_id(self)), move data);
+                    for content.inline().boxes.each |box| { box.d().ctx = content }
+                    Some(content)
+                };
+                (block, content)
+            }
+            _ => fail fmt!("Can't turn %s into a block flow", self.debug_str())
+        };
+        self.replace_in_tree(block);
+        do content.iter |content| { FlowTree.add_child(block, *content) }
+        block
+    }
+
+    /** Like `become_block`, but the other way around: the inline flow holds
+        the block's box, if it has one, followed by the boxes of the
+        block's inline children, which are dropped, and it takes its
+        alignment and white space handling from the first of them. List
+        item markers are dropped. Fails if the block has a child that
+        isn't inline. An inline flow is returned as it is. */
+    fn become_inline(@self) -> @FlowContext {
+        let inline = match *self {
+            InlineFlow(*) => return self,
+            BlockFlow(ref d, ref b) => {
+                let mut data = InlineFlowData();
+                do b.box.iter |box| { data.boxes.push(*box) }
+
+                let mut children = ~[];
+                for FlowTree.each_child(self) |child| { children.push(child) }
+                for children.eachi |n, child| {
+                    let child_data = match *child {
+                        @InlineFlow(_, ref child_data) => child_data,
+                        _ => fail fmt!("Can't turn %s into an inline flow: its child %s isn't inline",
+                                       self.debug_str(), child.debug_str())
+                    };
+                    if n == 0 {
+                        data.text_align = child_data.text_align;
+                        data.line_height = child_data.line_height;
+                        data.text_indent = child_data.text_indent;
+                        data.white_space = child_data.white_space;
+                        data.text_overflow = child_data.text_overflow;
+                    }
+                    // the child's node ranges, after the boxes before them
+                    let offset = data.boxes.len();
+                    for child_data.elems.eachi_mut |_, nr| {
+                        data.elems.add_mapping(nr.node, &Range::new(nr.range.begin() + offset,
+                                                                   nr.range.length()));
+                        true
+                    }
+                    for child_data.boxes.each |box| { data.boxes.push(*box) }
+                    child.detach();
+                }
+                @InlineFlow(clone_flow_data(d, d.id), move data)
+            }
+            _ => fail fmt!("Can't turn %s into an inline flow", self.debug_str())
+        };
+        for inline.inline().boxes.each |box| { box.d().ctx = inline }
+        self.replace_in_tree(inline);
+        inline
+    }
+
+    /* Gives `replacement` this flow's parent, siblings and children, and
+       leaves this flow outside of any tree. */
+    priv fn replace_in_tree(@self, replacement: @FlowContext) {
+        let links = &self.d().tree;
+        let new_links = &replacement.d().tree;
+        new_links.parent = links.parent;
+        new_links.first_child = links.first_child;
+        new_links.last_child = links.last_child;
+        new_links.prev_sibling = links.prev_sibling;
+        new_links.next_sibling = links.next_sibling;
+
+        do links.parent.iter |parent| {
+            let parent_links = &parent.d().tree;
+            if parent_links.first_child.map_default(false, |f| core::managed::ptr_eq(*f, self)) {
+                parent_links.first_child = Some(replacement);
+            }
+            if parent_links.last_child.map_default(false, |f| core::managed::ptr_eq(*f, self)) {
+                parent_links.last_child = Some(replacement);
+            }
+        }
+        do links.prev_sibling.iter |prev| { prev.d().tree.next_sibling = Some(replacement) }
+        do links.next_sibling.iter |next| { next.d().tree.prev_sibling = Some(replacement) }
+        for FlowTree.each_child(replacement) |child| {
+            child.d().tree.parent = Some(replacement);
+        }
+
+        links.parent = None;
+        links.first_child = None;
+        links.last_child = None;
+        links.prev_sibling = None;
+        links.next_sibling = None;
+    }
+
+    /** The boxes on the first line of an inline flow, for '::first-line'
+        styling. None for other flows, and for inline flows that haven't
+        been broken into lines yet. The node is the flow's own node if it
+        has one, else that of the line's first box. */
+    fn first_line_range(@self) -> Option<NodeRange> {
+        match self {
+            @InlineFlow(*) if self.inline().lines.len() > 0 => {
+                let line = self.inline().lines[0];
+                let node = match self.d().node {
+                    Some(node) => node,
+                    None => self.inline().boxes[line.begin()].d().node
+                };
+                Some(NodeRange::new(node, &const line))
+            },
+            _ => None
+        }
+    }
+
+    /** The distance from the top of this flow to the baseline of its first
+        line box, for aligning table cells by their baselines: that of an
+        inline flow's first line, or else that of the first child flow that
+        has one. None if there is no line box. Only meaningful after layout. */
+    fn first_baseline(@self) -> Option<Au> {
+        match self {
+            @InlineFlow(*) => {
+                if self.inline().lines.len() == 0 || self.inline().box_metrics.len() == 0 {
+                    return None;
+                }
+                // every box on the line sits on the same baseline
+                let first = self.inline().lines[0].begin();
+                Some(self.inline().boxes[first].d().position.origin.y +
+                     self.inline().box_metrics[first].ascent)
+            },
+            _ => {
+                for FlowTree.each_child(self) |child| {
+                    match child.first_baseline() {
+                        Some(baseline) => return Some(child.d().position.origin.y + baseline),
+                        None => {}
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /** The flows in this flow's subtree that were made for `node`, in
+        tree order. A node may have several flows, e.g. when a block is
+        split around an inline. */
+    fn find_flows_for_node(@self, node: Node) -> ~[@FlowContext] {
+        let mut flows = ~[];
+        do tree::dfs(&FlowTree, &self) |flow| {
+            if flow.d().node == Some(node) {
+                flows.push(*flow);
+            }
+            true
+        };
+        move flows
+    }
+
+    /** The border box of the first box made for `node` in this flow's
+        subtree, relative to this flow's origin; called on the root, that
+        is the box's absolute position. Only meaningful after layout. */
+    fn computed_box_for_node(@self, node: Node) -> Option<Rect<Au>> {
+        let mut found = None;
+        do tree::dfs(&FlowTree, &self) |flow| {
+            let boxes = match **flow {
+                RootFlow(_, ref r) => r.box.map_default(~[], |box| ~[*box]),
+                BlockFlow(_, ref b) => b.box.map_default(~[], |box| ~[*box]),
+                InlineFlow(_, ref i) => i.boxes.get(),
+                _ => ~[]
+            };
+            for boxes.each |box| {
+                if found.is_none() && box.d().is_box_of(node) {
+                    found = Some(box.border_box().translate(&origin_in_tree(*flow, self)));
+                }
+            }
+            found.is_none()
+        };
+        found
+    }
+
+    /** The area that this flow's subtree paints into, relative to the root
+        of the flow tree: the union of the border boxes of the render boxes
+        of its flows, and of the backgrounds of blocks, including those
+        that overflow their parents. Flows that paint nothing are left out,
+        so a subtree that paints nothing gives an empty rect at its origin.
+        Only meaningful after layout. */
+    fn paint_bounds(@self) -> Rect<Au> {
+        let mut top = self;
+        loop {
+            match tree::parent(&FlowTree, &top) {
+                Some(parent) => top = parent,
+                None => break
+            }
+        }
+        let origin = origin_in_tree(self, top);
+        let mut bounds = None;
+        self.add_paint_bounds(&origin, &mut bounds);
+        bounds.get_default(Rect(origin, Size2D(Au(0), Au(0))))
+    }
+
+    priv fn add_paint_bounds(@self, origin: &Point2D<Au>, bounds: &mut Option<Rect<Au>>) {
+        if !self.d().is_displayed {
+            return;
+        }
+        let visible = match self.d().visibility {
+            Visible => true,
+            Hidden => false
+        };
+        let mut painted = ~[];
+        if visible {
+            do self.d().style.iter |style| {
+                if style.background_color.is_some() {
+                    painted.push(Rect(copy *origin, copy self.d().position.size));
+                }
+            }
+            let boxes = match *self {
+                RootFlow(_, ref r) => r.box.map_default(~[], |box| ~[*box]),
+                BlockFlow(_, ref b) => b.box.map_default(~[], |box| ~[*box]),
+                InlineFlow(_, ref i) => i.boxes.get(),
+                _ => ~[]
+            };
+            for boxes.each |box| {
+                painted.push(box.border_box().translate(origin));
+            }
+        }
+        for painted.each |rect| {
+            *bounds = Some(match *bounds {
+                Some(ref so_far) => so_far.union(rect),
+                None => copy *rect
+            });
+        }
+
+        for FlowTree.each_child(self) |child| {
+            child.add_paint_bounds(&origin.add(&child.d().position.origin), bounds);
+        }
+    }
+
+    /** The 'z-index' that this flow is painted with: positioned flows have
+        their declared one, and the others are at level 0. */
+    pure fn effective_z_index(&self) -> int {
+        match self.d().position_scheme {
+            StaticPosition => 0,
+            _ => self.d().z_index
+        }
+    }
+
+    /** The flows of this flow's subtree with their effective 'z-index',
+        in the order that they are painted in: by 'z-index', and flows at
+        the same level in tree order. */
+    fn compute_stacking_order(@self) -> ~[(@FlowContext, int)] {
+        let mut flows = ~[];
+        do tree::dfs(&FlowTree, &self) |flow| {
+            flows.push((*flow, flow.effective_z_index()));
+            true
+        };
+        // merge sort is stable, so tree order breaks ties
+        do std::sort::merge_sort(flows) |a, b| {
+            match (a, b) {
+                (&(_, z_a), &(_, z_b)) => z_a <= z_b
+            }
+        }
+    }
+
+    /** Calls `f` on each flow in this flow's subtree that has no child
+        flows, in tree order, until `f` returns false. */
+    fn each_leaf(@self, f: fn(@FlowContext) -> bool) {
+        do tree::dfs(&FlowTree, &self) |flow| {
+            !tree::is_leaf(&FlowTree, flow) || f(*flow)
+        };
+    }
+
+    /// The number of render boxes held by this flow itself.
+    fn box_count(@self) -> uint {
+        match self {
+            @InlineFlow(*) => self.inline().boxes.len(),
+            @BlockFlow(*) => self.block().box.map_default(0u, |_b| 1u),
+            @RootFlow(*) => self.root().box.map_default(0u, |_b| 1u),
+            _ => 0u
+        }
+    }
+
+    /** Estimates the memory used by this flow's subtree: each flow with its
+        variant data, and the render boxes that the flows hold. DOM nodes,
+        styles and text runs are shared with other structures, and aren't
+        counted. */
+    fn estimated_bytes(@self) -> uint {
+        let mut bytes = 0u;
+        do tree::dfs(&FlowTree, &self) |flow| {
+            bytes += sys::size_of::<FlowContext>();
+            bytes += flow.box_count() * (sys::size_of::<@RenderBox>() + sys::size_of::<RenderBox>());
+            true
+        };
+        bytes
+    }
+
+    /** Records the position of every flow in this flow's subtree, keyed by
+        flow id, so that it can be compared after a reflow. */
+    fn snapshot_positions(@self) -> HashMap<int, Rect<Au>> {
+        let positions = HashMap();
+        do tree::dfs(&FlowTree, &self) |flow| {
+            positions.insert(flow.d().id, copy flow.d().position);
+            true
+        };
+        positions
+    }
+
+    /** Records the parent of every flow in this flow's subtree, by flow
+        id, so that the tree can be compared after an edit with
+        `tree_edit_stats`. */
+    fn snapshot_parents(@self) -> HashMap<int, Option<int>> {
+        let parents = HashMap();
+        do tree::dfs(&FlowTree, &self) |flow| {
+            let parent = tree::parent(&FlowTree, flow).map(|parent| parent.d().id);
+            parents.insert(flow.d().id, parent);
+            true
+        };
+        parents
+    }
+
+    /** Checks the invariants of the flow tree rooted at this flow, and
+        returns the first violation found. Widths are checked too, so
+        this is only meaningful after `bubble_widths`. */
+    fn validate(@self) -> Result<(), ~str> {
+        if tree::parent(&FlowTree, &self).is_some() {
+            return Err(fmt!("f%d is not the root of its flow tree", self.d().id));
+        }
+
+        let mut roots = 0u;
+        let mut violation = None;
+        do tree::dfs(&FlowTree, &self) |flow| {
+            match *flow {
+                @RootFlow(*) => roots += 1u,
+                _ => {}
+            }
+            violation = flow.check_invariants();
+            violation.is_none()
+        };
+
+        match move violation {
+            Some(move msg) => Err(move msg),
+            None if roots != 1u => Err(fmt!("flow tree has %u root flows", roots)),
+            None => Ok(())
+        }
+    }
+
+    priv fn check_invariants(@self) -> Option<~str> {
+        for FlowTree.each_child(self) |child| {
+            match tree::parent(&FlowTree, &child) {
+                Some(parent) if core::managed::ptr_eq(parent, self) => {},
+                _ => return Some(fmt!("f%d's parent is not f%d", child.d().id, self.d().id))
+            }
+        }
+
+        let has_box = match self {
+            @BlockFlow(*) => self.block().box.is_some(),
+            @RootFlow(*) => self.root().box.is_some(),
+            _ => false
+        };
+        if has_box && self.d().node.is_none() {
+            return Some(fmt!("f%d has a box but no node", self.d().id));
+        }
+
+        if self.d().min_width < Au(0) || self.d().pref_width < Au(0) {
+            return Some(fmt!("f%d has negative widths: min_width=%dpx pref_width=%dpx",
+                             self.d().id, self.d().min_width.to_px(), self.d().pref_width.to_px()));
+        }
+        None
+    }
+}
+
+/* Accumulates a content hash (see `FlowContext::content_hash`), as 64-bit
+   FNV-1a, without building a string of what it hashes. */
+struct ContentHasher {
+    mut hash: u64
+}
+
+fn ContentHasher() -> ContentHasher {
+    ContentHasher { hash: 0xcbf29ce484222325u64 }
+}
+
+impl ContentHasher {
+    fn mix_byte(&self, byte: u8) {
+        self.hash = (self.hash ^ (byte as u64)) * 0x100000001b3u64;
+    }
+
+    fn mix_int(&self, value: int) {
+        let value = value as u64;
+        for uint::range(0, 8) |i| {
+            self.mix_byte((value >> ((i * 8) as u64)) as u8);
+        }
+    }
+
+    fn mix_bool(&self, value: bool) {
+        self.mix_byte(if value { 1 } else { 0 });
+    }
+
+    fn mix_au(&self, value: Au) {
+        self.mix_int(*value as int);
+    }
+
+    fn mix_option_au(&self, value: Option<Au>) {
+        match value {
+            Some(value) => { self.mix_bool(true); self.mix_au(value); }
+            None => self.mix_bool(false)
+        }
+    }
+
+    // to a millionth, which is finer than any length in Au
+    fn mix_float(&self, value: float) {
+        self.mix_int((value * 1000000f) as int);
+    }
+
+    fn mix_str(&self, s: &str) {
+        self.mix_int(s.len() as int);
+        for str::each(s) |byte| {
+            self.mix_byte(byte);
+            true
+        }
+    }
+
+    fn mix_length(&self, length: &LengthOrAuto) {
+        match *length {
+            Length(length) => { self.mix_byte(1); self.mix_au(length); }
+            Percent(percent) => { self.mix_byte(2); self.mix_float(percent); }
+            Auto => self.mix_byte(0)
+        }
+    }
+
+    fn mix_height(&self, height: &DeclaredHeight) {
+        match *height {
+            FixedHeight(height) => { self.mix_byte(1); self.mix_au(height); }
+            PercentHeight(percent) => { self.mix_byte(2); self.mix_float(percent); }
+            AutoHeight => self.mix_byte(0)
+        }
+    }
+
+    fn mix_offsets(&self, offsets: &BoxOffsets) {
+        self.mix_au(offsets.top);
+        self.mix_au(offsets.right);
+        self.mix_au(offsets.bottom);
+        self.mix_au(offsets.left);
+    }
+
+    fn mix_size(&self, size: &Option<Size2D<Au>>) {
+        match *size {
+            Some(ref size) => {
+                self.mix_bool(true);
+                self.mix_au(size.width);
+                self.mix_au(size.height);
+            }
+            None => self.mix_bool(false)
+        }
+    }
+
+    // the box's kind, content and sizing inputs
+    fn mix_box(&self, box: Option<@RenderBox>) {
+        let box = match box {
+            Some(box) => box,
+            None => return self.mix_bool(false)
+        };
+        self.mix_bool(true);
+        let d = box.d();
+        self.mix_int(d.id);
+        self.mix_size(&d.intrinsic_size);
+        match d.intrinsic_ratio {
+            Some(ratio) => { self.mix_bool(true); self.mix_float(ratio); }
+            None => self.mix_bool(false)
+        }
+        self.mix_option_au(d.declared_width);
+        self.mix_option_au(d.declared_height);
+        match box {
+            @GenericBox(*) => self.mix_byte(0),
+            @ImageBox(*) => self.mix_byte(1),
+            @TextBox(_, ref text) => {
+                self.mix_byte(2);
+                self.mix_int(text.range.length() as int);
+                for text.range.eachi |i| {
+                    self.mix_byte(text.run.text[i]);
+                }
+            }
+            @UnscannedTextBox(_, ref text) => {
+                self.mix_byte(3);
+                self.mix_str(*text);
+            }
+            // the inline-block's flow isn't a child of the inline flow
+            @InlineBlockBox(_, flow) => {
+                self.mix_byte(4);
+                flow.content_key(self);
+            }
+        }
+    }
+}
+
+/** Lists the flows whose positions differ between two snapshots taken by
+    `snapshot_positions`, as (id, before, after), in order of flow id.
+    Flows that are only in one of the snapshots are not listed. */
+pub fn diff_positions(before: &HashMap<int, Rect<Au>>,
+                      after: &HashMap<int, Rect<Au>>) -> ~[(int, Rect<Au>, Rect<Au>)] {
+    let mut changes = ~[];
+    for before.each |id, old_position| {
+        match after.find(id) {
+            Some(new_position) if new_position != old_position => {
+                changes.push((id, old_position, new_position));
+            }
+            _ => {}
+        }
+    }
+    do std::sort::merge_sort(changes) |a, b| {
+        match (a, b) {
+            (&(id_a, _, _), &(id_b, _, _)) => id_a <= id_b
+        }
+    }
+}
+
+/* How two flow trees differ, matching flows by id. */
+pub struct EditStats {
+    // flows only in the tree after the edit
+    insertions: uint,
+    // flows only in the tree before the edit
+    deletions: uint,
+    // flows in both trees whose parent changed; each one moves its
+    // whole subtree, so the flows under it aren't counted
+    moves: uint
+}
+
+/** Compares the tree before an edit, as recorded by `snapshot_parents`,
+    with the tree after it, e.g. to check that an incremental pass only
+    rebuilt the flows that it had to. Edits usually change the tree in
+    place, so the tree before can't be walked once they are done. */
+pub fn tree_edit_stats(before_parents: &HashMap<int, Option<int>>,
+                       after: @FlowContext) -> EditStats {
+    let after_parents = after.snapshot_parents();
+
+    let mut stats = EditStats { insertions: 0, deletions: 0, moves: 0 };
+    for before_parents.each |id, old_parent| {
+        match after_parents.find(id) {
+            Some(new_parent) if new_parent != old_parent => stats.moves += 1,
+            Some(_) => {}
+            None => stats.deletions += 1
+        }
+    }
+    for after_parents.each_key |id| {
+        if !before_parents.contains_key(id) {
+            stats.insertions += 1;
+        }
+    }
+    stats
+}
+
+/** The areas to repaint after a reflow: for each flow that moved or
+    was resized since `before` was taken by `snapshot_positions`, the
+    union of its old and new positions, with overlapping areas merged.
+    Areas are relative to `after_tree`, and assume that a flow's parent
+    is where it was before; if it isn't, the parent's own area covers it. */
+pub fn dirty_regions(before: &HashMap<int, Rect<Au>>, after_tree: @FlowContext) -> ~[Rect<Au>] {
+    let flows = HashMap();
+    do tree::dfs(&FlowTree, &after_tree) |flow| {
+        flows.insert(flow.d().id, *flow);
+        true
+    };
+
+    let mut regions = ~[];
+    for diff_positions(before, &after_tree.snapshot_positions()).each |change| {
+        match *change {
+            (id, ref old_position, ref new_position) => {
+                let parent_origin = match tree::parent(&FlowTree, &flows.get(id)) {
+                    Some(parent) => origin_in_tree(parent, after_tree),
+                    None => Au::zero_point()
+                };
+                regions.push(old_position.union(new_position).translate(&parent_origin));
+            }
+        }
+    }
+    coalesce_rects(regions)
+}
+
+/* The origin of `flow`, relative to its ancestor `top`. */
+pub fn origin_in_tree(flow: @FlowContext, top: @FlowContext) -> Point2D<Au> {
+    let mut origin = Au::zero_point();
+    let mut cur = flow;
+    while !core::managed::ptr_eq(cur, top) {
+        origin = origin.add(&cur.d().position.origin);
+        cur = match tree::parent(&FlowTree, &cur) {
+            Some(parent) => parent,
+            None => break
+        };
+    }
+    origin
+}
+
+/* An id that no flow in the tree of `flow` has, for a flow made outside
+   of the box builder. */
+priv fn unused_flow_id(flow: @FlowContext) -> int {
+    let mut root = flow;
+    loop {
+        match tree::parent(&FlowTree, &root) {
+            Some(parent) => root = parent,
+            None => break
+        }
+    }
+    let mut max_id = flow.d().id;
+    do tree::dfs(&FlowTree, &root) |f| {
+        max_id = int::max(max_id, f.d().id);
+        true
+    };
+    max_id + 1
+}
+
+/* Merges overlapping rects until no two of them overlap. */
+priv fn coalesce_rects(rects: ~[Rect<Au>]) -> ~[Rect<Au>] {
+    let mut merged: ~[Rect<Au>] = ~[];
+    for rects.each |rect| {
+        let mut rect = copy *rect;
+        // a merged rect may now overlap rects that it didn't before, so
+        // keep merging until it's disjoint from the rest.
+        loop {
+            let mut rest = ~[];
+            let mut grew = false;
+            for merged.each |other| {
+                if rect.intersects(other) {
+                    rect = rect.union(other);
+                    grew = true;
+                } else {
+                    rest.push(copy *other);
+                }
+            }
+            merged = move rest;
+            if !grew { break; }
+        }
+        merged.push(move rect);
+    }
+    move merged
 }
 
 /* The tree holding FlowContexts */
@@ -276,9 +1611,638 @@ impl FlowContext : BoxedDebugMethods {
                     None => ~"RootFlow",
                 }
             },
-            _ => ~"(Unknown flow)"
+            _ => str::from_slice(self.type_name())
         };
             
         fmt!("f%? %?", self.d().id, repr)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use layout::block::BlockFlowData;
+    use layout::context::test_layout_context;
+    use layout::test_builder;
+    use layout::traverse::*;
+    use geom::size::Size2D;
+    use gfx::color::rgb;
+    use gfx::util::range::Range;
+    use newcss::values::CSSTextAlignRight;
+
+    #[test]
+    fn dump_layout_shows_computed_dimensions() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        let child = @BlockFlow(FlowData(1), BlockFlowData());
+        FlowTree.add_child(parent, child);
+        parent.d().position.size.width = Au::from_px(300);
+        parent.d().padding.left = Au::from_px(10);
+
+        parent.bubble_widths(&ctx);
+        parent.assign_widths(&ctx);
+        parent.assign_height(&ctx);
+
+        let dump = parent.dump_layout();
+        assert str::contains(dump, "f0: min_width=0px pref_width=0px position=(0px, 0px, 300px, 0px)\n");
+        assert str::contains(dump, "    f1: min_width=0px pref_width=0px position=(10px, 0px, 290px, 0px)\n");
+    }
+
+    #[test]
+    fn serialize_layout_is_one_terse_line_per_flow() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let parent = @BlockFlow(FlowData(0), BlockFlowData());
+        let child = @BlockFlow(FlowData(1), BlockFlowData());
+        let grandchild = @BlockFlow(FlowData(2), BlockFlowData());
+        FlowTree.add_child(parent, child);
+        FlowTree.add_child(child, grandchild);
+        parent.d().position.size.width = Au::from_px(300);
+        parent.d().padding.left = Au::from_px(10);
+        grandchild.d().height = FixedHeight(Au::from_px(20));
+
+        do parent.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do parent.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do parent.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert parent.serialize_layout() == ~"0:BlockFlow:0,0:0,0,300,20\n" +
+                                            ~"  1:BlockFlow:0,0:10,0,290,20\n" +
+                                            ~"    2:BlockFlow:0,0:0,0,290,20\n";
+    }
+
+    #[test]
+    fn validate_accepts_built_tree() {
+        let root = test_builder::root().block().inline_boxes(1).build();
+        assert root.validate().is_ok();
+    }
+
+    #[test]
+    fn validate_rejects_dangling_parent() {
+        let root = test_builder::root().block().build();
+        let child = tree::first_child(&FlowTree, &root).get();
+        child.d().tree.parent = Some(@BlockFlow(FlowData(9), BlockFlowData()));
+
+        match root.validate() {
+            Err(msg) => assert msg == ~"f1's parent is not f0",
+            Ok(()) => fail ~"expected the dangling parent to be reported"
+        }
+    }
+
+    #[test]
+    fn intrinsic_widths_match_bubbled_widths_without_storing_them() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().inline_boxes(2).build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().min_width = Au::from_px(7);
+
+        let widths = root.intrinsic_widths(&ctx);
+        assert block.d().min_width == Au::from_px(7);
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        assert widths == (root.d().min_width, root.d().pref_width);
+    }
+
+    #[test]
+    fn diff_positions_reports_only_moved_flows() {
+        let root = test_builder::root().block().up().block().build();
+        let moved = tree::last_child(&FlowTree, &root).get();
+        let before = root.snapshot_positions();
+
+        moved.d().position.origin.y = Au::from_px(40);
+        let after = root.snapshot_positions();
+
+        let changes = diff_positions(&before, &after);
+        assert changes.len() == 1;
+        match changes[0] {
+            (id, old_position, new_position) => {
+                assert id == moved.d().id;
+                assert old_position.origin.y == Au(0);
+                assert new_position.origin.y == Au::from_px(40);
+            }
+        }
+    }
+
+    #[test]
+    fn adding_one_child_is_one_insertion() {
+        let root = test_builder::root().block().up().block().build();
+        let before = root.snapshot_parents();
+        let block = tree::last_child(&FlowTree, &root).get();
+        FlowTree.add_child(block, @BlockFlow(FlowData(3), BlockFlowData()));
+
+        let stats = tree_edit_stats(&before, root);
+        assert stats.insertions == 1;
+        assert stats.deletions == 0;
+        assert stats.moves == 0;
+    }
+
+    #[test]
+    fn edit_in_place_counts_moved_and_removed_flows() {
+        // the first block holds a block, which holds an inline flow
+        let root = test_builder::root().block().block().inline_boxes(1).up().up().block().build();
+        let first = tree::first_child(&FlowTree, &root).get();
+        let moved = tree::first_child(&FlowTree, &first).get();
+        let last = tree::last_child(&FlowTree, &root).get();
+        let before = root.snapshot_parents();
+
+        tree::remove_child(&FlowTree, first, moved);
+        FlowTree.add_child(last, moved);
+        tree::remove_child(&FlowTree, root, first);
+
+        let stats = tree_edit_stats(&before, root);
+        assert stats.insertions == 0;
+        assert stats.deletions == 1;
+        // the inline flow moves along with its parent
+        assert stats.moves == 1;
+    }
+
+    #[test]
+    fn estimated_bytes_grows_with_children() {
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        let before = root.estimated_bytes();
+
+        FlowTree.add_child(block, @BlockFlow(FlowData(2), BlockFlowData()));
+        let with_block = root.estimated_bytes();
+        assert with_block > before;
+
+        FlowTree.add_child(block, @InlineFlow(FlowData(3), InlineFlowData()));
+        assert root.estimated_bytes() > with_block;
+    }
+
+    #[test]
+    fn first_and_last_children_among_three_siblings() {
+        let root = test_builder::root().block().up().block().up().block().build();
+        let first = tree::first_child(&FlowTree, &root).get();
+        let middle = tree::next_sibling(&FlowTree, &first).get();
+        let last = tree::last_child(&FlowTree, &root).get();
+
+        assert first.is_first_child() && !first.is_last_child();
+        assert !middle.is_first_child() && !middle.is_last_child();
+        assert !last.is_first_child() && last.is_last_child();
+        assert !root.is_first_child() && !root.is_last_child();
+    }
+
+    #[test]
+    fn inline_becomes_block_in_its_place() {
+        let builder = test_builder::root().block().up().inline_boxes(2);
+        let inline = builder.last_flow();
+        let node = inline.inline().boxes[1].d().node;
+        inline.d().node = Some(node);
+        let root = builder.block().build();
+        let first = tree::first_child(&FlowTree, &root).get();
+        let last = tree::last_child(&FlowTree, &root).get();
+
+        let other_box = inline.inline().boxes[0];
+        let own_box = inline.inline().boxes[1];
+
+        let block = inline.become_block();
+        assert block.type_name() == "BlockFlow";
+        assert block.d().id == inline.d().id;
+        assert block.d().node == Some(node);
+        assert core::managed::ptr_eq(block.block().box.get(), own_box);
+        assert core::managed::ptr_eq(own_box.d().ctx, block);
+
+        // the box of another node moved into an anonymous inline child
+        let content = tree::first_child(&FlowTree, &block).get();
+        assert tree::next_sibling(&FlowTree, &content).is_none();
+        assert content.starts_inline_flow() && content.d().node.is_none();
+        assert content.d().id == 4;
+        assert content.inline().boxes.len() == 1;
+        assert core::managed::ptr_eq(content.inline().boxes[0], other_box);
+        assert core::managed::ptr_eq(other_box.d().ctx, content);
+
+        assert core::managed::ptr_eq(tree::parent(&FlowTree, &block).get(), root);
+        assert core::managed::ptr_eq(tree::next_sibling(&FlowTree, &first).get(), block);
+        assert core::managed::ptr_eq(tree::prev_sibling(&FlowTree, &last).get(), block);
+        assert core::managed::ptr_eq(tree::prev_sibling(&FlowTree, &block).get(), first);
+        assert core::managed::ptr_eq(tree::next_sibling(&FlowTree, &block).get(), last);
+        assert tree::parent(&FlowTree, &inline).is_none();
+
+        // and back again, keeping the block's box
+        let inline = block.become_inline();
+        assert inline.inline().boxes.len() == 1;
+        assert core::managed::ptr_eq(own_box.d().ctx, inline);
+        assert core::managed::ptr_eq(tree::next_sibling(&FlowTree, &first).get(), inline);
+    }
+
+    #[test]
+    fn become_inline_takes_the_boxes_of_inline_children() {
+        let builder = test_builder::root().block().inline_boxes(2);
+        let child = builder.last_flow();
+        child.inline().text_align = Some(CSSTextAlignRight);
+        let node = child.inline().boxes[1].d().node;
+        child.inline().elems.add_mapping(node, &Range::new(1, 1));
+        let root = builder.build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        let boxes = child.inline().boxes.get();
+
+        let inline = block.become_inline();
+        assert inline.d().id == block.d().id;
+        assert inline.inline().text_align == Some(CSSTextAlignRight);
+        assert inline.inline().boxes.len() == 2;
+        for boxes.eachi |i, box| {
+            assert core::managed::ptr_eq(inline.inline().boxes[i], *box);
+            assert core::managed::ptr_eq(box.d().ctx, inline);
+        }
+        let mut ranges = ~[];
+        for inline.inline().elems.eachi_mut |_, nr| {
+            ranges.push((nr.range.begin(), nr.range.length()));
+            true
+        }
+        assert ranges == ~[(1, 1)];
+
+        // the child is dropped, rather than left under an inline flow
+        assert tree::first_child(&FlowTree, &inline).is_none();
+        assert tree::parent(&FlowTree, &child).is_none();
+        assert core::managed::ptr_eq(tree::first_child(&FlowTree, &root).get(), inline);
+    }
+
+    #[test]
+    #[should_fail]
+    fn become_inline_fails_for_block_with_block_child() {
+        let root = test_builder::root().block().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.become_inline();
+    }
+
+    #[test]
+    fn detach_removes_middle_child_from_its_parent() {
+        let root = test_builder::root().block().up().block().up().block().build();
+        let first = tree::first_child(&FlowTree, &root).get();
+        let middle = tree::next_sibling(&FlowTree, &first).get();
+        let last = tree::last_child(&FlowTree, &root).get();
+
+        let detached = middle.detach();
+        assert core::managed::ptr_eq(detached, middle);
+        assert tree::parent(&FlowTree, &detached).is_none();
+        assert tree::prev_sibling(&FlowTree, &detached).is_none();
+        assert tree::next_sibling(&FlowTree, &detached).is_none();
+
+        let mut remaining = ~[];
+        for FlowTree.each_child(root) |child| { remaining.push(child.d().id); }
+        assert remaining == ~[first.d().id, last.d().id];
+    }
+
+    #[test]
+    fn absolute_flow_is_contained_by_nearest_positioned_ancestor() {
+        let root = test_builder::root().block().block().block().build();
+        let outer = tree::first_child(&FlowTree, &root).get();
+        let middle = tree::first_child(&FlowTree, &outer).get();
+        let inner = tree::first_child(&FlowTree, &middle).get();
+        outer.d().position_scheme = RelativePosition;
+        inner.d().position_scheme = AbsolutePosition;
+
+        assert core::managed::ptr_eq(inner.containing_block(), outer);
+        assert core::managed::ptr_eq(middle.containing_block(), outer);
+    }
+
+    #[test]
+    fn fixed_flow_is_contained_by_viewport() {
+        let root = test_builder::root().block().block().build();
+        let outer = tree::first_child(&FlowTree, &root).get();
+        let inner = tree::first_child(&FlowTree, &outer).get();
+        outer.d().position_scheme = RelativePosition;
+        inner.d().position_scheme = FixedPosition;
+
+        assert core::managed::ptr_eq(inner.containing_block(), root);
+        assert core::managed::ptr_eq(root.containing_block(), root);
+    }
+
+    #[test]
+    fn nested_flow_finds_nearest_scrolling_ancestor() {
+        let root = test_builder::root().block().block().block().block().build();
+        let outer = tree::first_child(&FlowTree, &root).get();
+        let scroller = tree::first_child(&FlowTree, &outer).get();
+        let middle = tree::first_child(&FlowTree, &scroller).get();
+        let inner = tree::first_child(&FlowTree, &middle).get();
+        outer.d().overflow = OverflowScroll;
+        scroller.d().overflow = OverflowAuto;
+        inner.d().overflow = OverflowScroll;
+
+        assert core::managed::ptr_eq(inner.nearest_scroll_container().get(), scroller);
+        assert core::managed::ptr_eq(scroller.nearest_scroll_container().get(), outer);
+    }
+
+    #[test]
+    fn nearest_scroll_container_falls_back_to_root() {
+        let root = test_builder::root().block().block().build();
+        let outer = tree::first_child(&FlowTree, &root).get();
+        let inner = tree::first_child(&FlowTree, &outer).get();
+        outer.d().overflow = OverflowHidden;
+
+        assert core::managed::ptr_eq(inner.nearest_scroll_container().get(), root);
+        assert root.nearest_scroll_container().is_none();
+    }
+
+    #[test]
+    fn deep_clone_is_independent_of_original() {
+        let root = test_builder::root().block().inline_boxes(2).build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        let clone = root.deep_clone();
+        let block_clone = tree::first_child(&FlowTree, &clone).get();
+        let inline_clone = tree::first_child(&FlowTree, &block_clone).get();
+
+        assert clone.d().id == 3 && block_clone.d().id == 4 && inline_clone.d().id == 5;
+        assert core::managed::ptr_eq(tree::parent(&FlowTree, &inline_clone).get(), block_clone);
+        assert inline_clone.inline().boxes.len() == 2;
+        assert clone.validate().is_ok();
+
+        block_clone.d().position.origin.y = Au::from_px(10);
+        assert block.d().position.origin.y == Au(0);
+    }
+
+    #[test]
+    fn dirty_regions_cover_old_and_new_positions() {
+        let root = test_builder::root().block().up().block().build();
+        let moved = tree::last_child(&FlowTree, &root).get();
+        moved.d().position = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(50), Au::from_px(50)));
+        let before = root.snapshot_positions();
+
+        moved.d().position.origin.y = Au::from_px(100);
+
+        let regions = dirty_regions(&before, root);
+        assert regions == ~[Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(50), Au::from_px(150)))];
+    }
+
+    #[test]
+    fn first_baseline_of_inline_flow_is_that_of_its_first_line() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = test_builder::root().inline_boxes(2).last_flow();
+        assert flow.first_baseline().is_none();
+
+        flow.d().position.size.width = Au::from_px(300);
+        flow.bubble_widths(&ctx);
+        flow.assign_widths(&ctx);
+        flow.assign_height(&ctx);
+
+        // generic boxes are 30px tall, and sit on the baseline
+        assert flow.first_baseline() == Some(Au::from_px(30));
+    }
+
+    #[test]
+    fn first_baseline_of_block_is_that_of_its_first_child_with_one() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().up().block().inline_boxes(1).build();
+        let empty = tree::first_child(&FlowTree, &root).get();
+        let block = tree::last_child(&FlowTree, &root).get();
+        empty.d().height = FixedHeight(Au::from_px(10));
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert block.first_baseline() == Some(Au::from_px(30));
+        assert root.first_baseline() == Some(Au::from_px(40));
+    }
+
+    #[test]
+    fn find_flows_for_node_finds_the_flow_made_for_it() {
+        let root = test_builder::root().block().inline_boxes(1).up().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        let inline = tree::first_child(&FlowTree, &block).get();
+        let node = inline.inline().boxes[0].d().node;
+        block.d().node = Some(node);
+
+        let flows = root.find_flows_for_node(node);
+        assert flows.len() == 1 && core::managed::ptr_eq(flows[0], block);
+    }
+
+    #[test]
+    fn computed_box_for_node_is_in_absolute_coordinates() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let builder = test_builder::root().block().inline_boxes(2);
+        builder.up().block().inline_boxes(1);
+        let inline = builder.last_flow();
+        let root = builder.build();
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        let block = tree::parent(&FlowTree, &inline).get();
+        let box = inline.inline().boxes[0];
+        let expected = box.d().position.translate(&block.d().position.origin.add(&inline.d().position.origin));
+        // below the first block's line of 30px tall boxes
+        assert expected == Rect(Point2D(Au(0), Au::from_px(30)), Size2D(Au::from_px(45), Au::from_px(30)));
+        assert root.computed_box_for_node(box.d().node) == Some(expected);
+        assert root.computed_box_for_node(builder.new_node()).is_none();
+    }
+
+    #[test]
+    fn find_flows_for_node_finds_every_part_of_a_split_node() {
+        let root = test_builder::root().block().inline_boxes(1).up().block().build();
+        let first = tree::first_child(&FlowTree, &root).get();
+        let second = tree::last_child(&FlowTree, &root).get();
+        let inline = tree::first_child(&FlowTree, &first).get();
+        let node = inline.inline().boxes[0].d().node;
+        first.d().node = Some(node);
+        second.d().node = Some(node);
+
+        let flows = root.find_flows_for_node(node);
+        assert flows.len() == 2;
+        assert core::managed::ptr_eq(flows[0], first) && core::managed::ptr_eq(flows[1], second);
+    }
+
+    #[test]
+    fn each_leaf_visits_only_flows_without_children() {
+        let root = test_builder::root().block().inline_boxes(1).inline_boxes(1)
+                                       .up().block().inline_boxes(1).build();
+        let mut ids = ~[];
+        for root.each_leaf |flow| {
+            assert flow.starts_inline_flow();
+            ids.push(flow.d().id);
+        }
+        assert ids == ~[2, 3, 5];
+    }
+
+    #[test]
+    fn overflowing_child_enlarges_paint_bounds() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let builder = test_builder::root().block().inline_boxes(2);
+        let inline = builder.last_flow();
+        let root = builder.build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.set_style(@ComputedStyle {
+            width: Length(Au::from_px(100)),
+            height: FixedHeight(Au::from_px(50)),
+            box_sizing: ContentBox,
+            padding: BoxOffsets::zero(),
+            border: BoxOffsets::zero(),
+            background_color: Some(rgb(0, 0, 255)),
+            border_radius: CornerRadii::zero(),
+            before_content: None,
+            after_content: None
+        });
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+        let within = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(100), Au::from_px(50)));
+        assert block.paint_bounds() == within;
+
+        // the two 45px boxes now reach past the block's right edge
+        inline.d().position.origin.x = Au::from_px(80);
+        let beyond = Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(170), Au::from_px(50)));
+        assert block.paint_bounds() == beyond;
+        assert inline.paint_bounds() == Rect(Point2D(Au::from_px(80), Au(0)),
+                                             Size2D(Au::from_px(90), Au::from_px(30)));
+    }
+
+    #[test]
+    fn style_round_trips_through_flow_data() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let style = @ComputedStyle {
+            width: Length(Au::from_px(200)),
+            height: FixedHeight(Au::from_px(50)),
+            box_sizing: ContentBox,
+            padding: BoxOffsets::zero(),
+            border: BoxOffsets::zero(),
+            background_color: None,
+            border_radius: CornerRadii::zero(),
+            before_content: None,
+            after_content: None
+        };
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        assert block.d().style.is_none();
+
+        block.set_style(style);
+        assert core::managed::ptr_eq(block.d().style.get(), style);
+        assert core::managed::ptr_eq(block.deep_clone().d().style.get(), style);
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+        assert block.d().position.size == Size2D(Au::from_px(200), Au::from_px(50));
+    }
+
+    #[test]
+    fn type_name_names_each_variant() {
+        assert AbsoluteFlow(FlowData(0)).type_name() == "AbsoluteFlow";
+        assert BlockFlow(FlowData(0), BlockFlowData()).type_name() == "BlockFlow";
+        assert FloatFlow(FlowData(0), FloatFlowData()).type_name() == "FloatFlow";
+        assert InlineBlockFlow(FlowData(0)).type_name() == "InlineBlockFlow";
+        assert InlineFlow(FlowData(0), InlineFlowData()).type_name() == "InlineFlow";
+        assert RootFlow(FlowData(0), RootFlowData()).type_name() == "RootFlow";
+        assert TableFlow(FlowData(0)).type_name() == "TableFlow";
+    }
+
+    #[test]
+    fn hidden_flow_takes_no_space_and_paints_nothing() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().inline_boxes(2).up().block().build();
+        let hidden = tree::first_child(&FlowTree, &root).get();
+        let visible = tree::last_child(&FlowTree, &root).get();
+        hidden.d().is_displayed = false;
+        hidden.d().height = FixedHeight(Au::from_px(50));
+        visible.d().height = FixedHeight(Au::from_px(30));
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert root.d().pref_width == Au(0);
+        assert hidden.d().position.size == Size2D(Au(0), Au(0));
+        assert visible.d().position.origin.y == Au(0);
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        root.build_display_list_recurse(&builder, &copy root.d().position,
+                                        &Au::zero_point(), &mut list);
+        assert list.list.is_empty();
+    }
+
+    #[test]
+    fn stacking_order_sorts_positioned_flows_by_z_index() {
+        let root = test_builder::root().block().up().block().up().block().build();
+        let first = tree::first_child(&FlowTree, &root).get();
+        let last = tree::last_child(&FlowTree, &root).get();
+        first.d().position_scheme = RelativePosition;
+        first.d().z_index = 2;
+        last.d().position_scheme = AbsolutePosition;
+        last.d().z_index = 1;
+
+        let order = do root.compute_stacking_order().map |entry| {
+            match *entry { (flow, z_index) => (flow.d().id, z_index) }
+        };
+        assert order == ~[(0, 0), (2, 0), (3, 1), (1, 2)];
+    }
+
+    #[test]
+    fn z_index_of_static_flow_is_ignored() {
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().z_index = 5;
+        assert block.effective_z_index() == 0;
+    }
+
+    #[test]
+    fn geometry_error_names_the_overflowing_flow() {
+        let flow = @BlockFlow(FlowData(7), BlockFlowData());
+        flow.d().position.size = Size2D(Au::from_px(100), Au::from_px(10));
+        assert geometry_error(flow, "assign_widths").is_none();
+
+        flow.d().position.origin.x = Au(i32::max_value - 10);
+        let error = geometry_error(flow, "assign_widths").get();
+        assert str::contains(error, "assign_widths of f7");
+    }
+
+    #[test]
+    #[should_fail]
+    #[cfg(debug)]
+    fn overflowing_width_fails_assign_widths() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+        block.d().width = Length(Au(i32::max_value));
+        block.d().padding.left = Au::from_px(10);
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+    }
+
+    #[test]
+    #[should_fail]
+    #[cfg(debug)]
+    fn assign_widths_before_bubble_widths_fails() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        root.assign_widths(&ctx);
+    }
+
+    #[test]
+    #[should_fail]
+    #[cfg(debug)]
+    fn assign_height_before_assign_widths_fails() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+    }
+
+    #[test]
+    #[should_fail]
+    #[cfg(debug)]
+    fn reflow_that_skips_bubble_widths_fails() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        // the widths bubbled by the first reflow may be stale
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+    }
+
+    #[test]
+    #[should_fail]
+    #[cfg(debug)]
+    fn build_display_list_before_assign_height_fails() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+
+        let builder = DisplayListBuilder { ctx: &ctx };
+        let mut list = DisplayList::new();
+        root.build_display_list_recurse(&builder, &copy root.d().position,
+                                        &Au::zero_point(), &mut list);
+    }
+}
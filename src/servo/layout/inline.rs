@@ -1,19 +1,23 @@
 use dom::node::Node;
 use layout::box::*;
 use layout::context::LayoutContext;
-use layout::flow::{FlowContext, InlineFlow};
-use layout::text::TextBoxData;
+use layout::flow::{FlowContext, FlowTree, Hidden, InlineFlow, OverflowVisible, Visible};
+use layout::text::{TextBoxData, adapt_textbox_with_range};
+use layout::traverse::FlowContextTraversals;
 use util::tree;
 
 use core::dlist::DList;
+use core::dvec;
 use core::dvec::DVec;
 use core::num::Num;
 use geom::{Point2D, Rect, Size2D};
 use gfx::font::FontStyle;
 use gfx::geometry::Au;
+use gfx::text::text_run::TextRun;
 use gfx::text::util::*;
 use gfx::util::range::Range;
-use newcss::values::{CSSTextAlignCenter, CSSTextAlignJustify, CSSTextAlignLeft, CSSTextAlignRight};
+use newcss::values::{CSSTextAlign, CSSTextAlignCenter, CSSTextAlignJustify, CSSTextAlignLeft};
+use newcss::values::CSSTextAlignRight;
 use newcss::units::{BoxAuto, BoxLength, Px};
 use std::arc;
 
@@ -59,6 +63,10 @@ impl ElementMapping {
         ElementMapping { entries: DVec() }
     }
 
+    fn clone(&self) -> ElementMapping {
+        ElementMapping { entries: dvec::from_vec(self.entries.get()) }
+    }
+
     fn add_mapping(node: Node, range: &const Range) {
         self.entries.push(NodeRange::new(node, range))
     }
@@ -235,33 +243,35 @@ priv impl TextRunScanner {
                 let old_box = in_boxes[self.clump.begin()];
                 let text = old_box.raw_text();
                 let font_style = old_box.font_style();
-                // TODO(Issue #115): use actual CSS 'white-space' property of relevant style.
-                let compression = CompressWhitespaceNewline;
+                let compression = compression_for(flow.inline().white_space);
                 let transformed_text = transform_text(text, compression);
+                let transformed_text = match flow.inline().white_space {
+                    WhiteSpacePre | WhiteSpacePreWrap => {
+                        let mut expanded = expand_tabs([move transformed_text], ctx.tab_size);
+                        expanded.pop()
+                    }
+                    WhiteSpaceNormal => move transformed_text
+                };
                 // TODO(Issue #177): text run creation must account for text-renderability by fontgroup fonts.
                 // this is probably achieved by creating fontgroup above, and then letting FontGroup decide
                 // which Font to stick into the TextRun.
                 let fontgroup = ctx.font_ctx.get_resolved_font_for_style(&font_style);
                 let run = @fontgroup.create_textrun(move transformed_text);
                 debug!("TextRunScanner: pushing single text box in range: %?", self.clump);
-                let new_box = layout::text::adapt_textbox_with_range(old_box.d(),
-                                                                     run,
-                                                                     &const Range::new(0, run.char_len()));
-                out_boxes.push(new_box);
+                push_text_boxes(flow, old_box.d(), run, &const Range::new(0, run.char_len()), out_boxes);
             },
             (false, true) => {
-                // TODO(Issue #115): use actual CSS 'white-space' property of relevant style.
-                let compression = CompressWhitespaceNewline;
-
-                // first, transform/compress text of all the nodes
-                let transformed_strs : ~[~str] = vec::from_fn(self.clump.length(), |i| {
-                    // TODO(Issue #113): we shoud be passing compression context
-                    // between calls to transform_text, so that boxes
-                    // starting/ending with whitespace &c can be
-                    // compressed correctly w.r.t. the TextRun.
-                    let idx = i + self.clump.begin();
-                    transform_text(in_boxes[idx].raw_text(), compression)
+                let compression = compression_for(flow.inline().white_space);
+
+                // first, transform/compress text of all the nodes, as one run
+                let raw_strs : ~[~str] = vec::from_fn(self.clump.length(), |i| {
+                    in_boxes[i + self.clump.begin()].raw_text()
                 });
+                let transformed_strs = transform_clump_text(raw_strs, compression);
+                let transformed_strs = match flow.inline().white_space {
+                    WhiteSpacePre | WhiteSpacePreWrap => expand_tabs(transformed_strs, ctx.tab_size),
+                    WhiteSpaceNormal => move transformed_strs
+                };
 
                 // next, concatenate all of the transformed strings together, saving the new char indices
                 let mut run_str : ~str = ~"";
@@ -291,8 +301,7 @@ priv impl TextRunScanner {
                               in_boxes[i].debug_str());
                         loop
                     }
-                    let new_box = layout::text::adapt_textbox_with_range(in_boxes[i].d(), run, range);
-                    out_boxes.push(new_box);
+                    push_text_boxes(flow, in_boxes[i].d(), run, range, out_boxes);
                 }
             }
         } /* /match */
@@ -319,12 +328,164 @@ priv impl TextRunScanner {
     } /* /fn flush_clump_to_list */
 }
 
+/// How text in an inline flow with the given 'white-space' is compressed.
+priv fn compression_for(white_space: WhiteSpace) -> CompressionMode {
+    match white_space {
+        WhiteSpaceNormal => CompressWhitespaceNewline,
+        WhiteSpacePre | WhiteSpacePreWrap => CompressNone
+    }
+}
+
+/// Whether newlines in text with the given 'white-space' end lines.
+priv pure fn preserves_newlines(white_space: WhiteSpace) -> bool {
+    match white_space {
+        WhiteSpaceNormal => false,
+        WhiteSpacePre | WhiteSpacePreWrap => true
+    }
+}
+
+/// Whether lines of text with the given 'white-space' wrap where they are full.
+priv pure fn soft_wraps(white_space: WhiteSpace) -> bool {
+    match white_space {
+        WhiteSpaceNormal | WhiteSpacePreWrap => true,
+        WhiteSpacePre => false
+    }
+}
+
+/**
+Pushes the text boxes for `range` of `run`. Where newlines are preserved,
+the range is cut after each newline, so that the line scanner can end a
+line after every box that ends in one.
+*/
+priv fn push_text_boxes(flow: @FlowContext, data: &RenderBoxData, run: @TextRun,
+                        range: &const Range, out_boxes: &DVec<@RenderBox>) {
+    if !preserves_newlines(flow.inline().white_space) {
+        out_boxes.push(layout::text::adapt_textbox_with_range(data, run, range));
+        return;
+    }
+    for newline_split_ranges(run.text, range).each |piece| {
+        out_boxes.push(layout::text::adapt_textbox_with_range(data, run, piece));
+    }
+}
+
+/// Cuts `range` of the characters of `text` after each newline.
+priv fn newline_split_ranges(text: &str, range: &const Range) -> ~[Range] {
+    let chars = str::chars(text);
+    let mut pieces = ~[];
+    let mut piece = Range::new(range.begin(), 0);
+    for range.eachi |i| {
+        piece.extend_by(1);
+        if chars[i] == '\n' {
+            pieces.push(piece);
+            piece = Range::new(i + 1, 0);
+        }
+    }
+    if piece.length() > 0 {
+        pieces.push(piece);
+    }
+    move pieces
+}
+
+/**
+Transforms the texts of a clump of boxes that will share one text run. The
+texts are compressed as if they were one string, so that whitespace at the
+end of one box and the start of the next collapses to a single space; the
+result holds the transformed text of each box.
+*/
+priv fn transform_clump_text(texts: &[~str], compression: CompressionMode) -> ~[~str] {
+    let mut in_whitespace = false;
+    do texts.map |text| {
+        let (transformed, out_whitespace) = transform_text_in_context(*text, compression,
+                                                                      in_whitespace);
+        in_whitespace = out_whitespace;
+        move transformed
+    }
+}
+
+/**
+Replaces each tab in the texts of a clump of preformatted boxes with the
+spaces up to the next tab stop; stops are `tab_size` columns apart. Columns
+are counted from the last newline, across the texts, as they are all on
+one run. Text measurement then gives tabs their width. A `tab_size` of 0
+makes tabs take no room at all, so they are dropped.
+*/
+priv fn expand_tabs(texts: &[~str], tab_size: uint) -> ~[~str] {
+    let mut column = 0;
+    do texts.map |text| {
+        let mut expanded = ~"";
+        for str::each_char(*text) |c| {
+            match c {
+                '\t' if tab_size == 0 => {}
+                '\t' => {
+                    let spaces = tab_size - column % tab_size;
+                    for uint::range(0, spaces) |_i| { str::push_char(&mut expanded, ' '); }
+                    column += spaces;
+                }
+                '\n' => {
+                    str::push_char(&mut expanded, c);
+                    column = 0;
+                }
+                _ => {
+                    str::push_char(&mut expanded, c);
+                    column += 1;
+                }
+            }
+        }
+        move expanded
+    }
+}
+
+/** Decides where an inline flow's lines may and must break. Scripts
+    differ in where they allow breaks. Both lists are in increasing order. */
+pub trait LineBreaker {
+    /// Indices of the boxes in `boxes` after which a full line may end.
+    fn break_opportunities(&self, boxes: &[@RenderBox]) -> ~[uint];
+    /// Indices of the boxes in `boxes` that must end a line.
+    fn forced_breaks(&self, boxes: &[@RenderBox]) -> ~[uint];
+    /// Tells breakers with different rules apart, for the reflow cache.
+    fn name(&self) -> &static/str;
+}
+
+/** The default breaker. It allows a break after any box and forces none,
+    so lines only break where they're full: between boxes, or at spaces
+    inside text boxes, which are split to fit (see
+    `RenderBox::split_to_width`). */
+pub struct SpaceLineBreaker;
+
+impl SpaceLineBreaker : LineBreaker {
+    fn break_opportunities(&self, boxes: &[@RenderBox]) -> ~[uint] {
+        vec::from_fn(boxes.len(), |i| i)
+    }
+
+    fn forced_breaks(&self, _boxes: &[@RenderBox]) -> ~[uint] {
+        ~[]
+    }
+
+    fn name(&self) -> &static/str { "SpaceLineBreaker" }
+}
+
+// whether the increasing list `indices` holds `index`
+priv pure fn sorted_contains(indices: &[uint], index: uint) -> bool {
+    let mut low = 0u;
+    let mut high = indices.len();
+    while low < high {
+        let mid = (low + high) / 2;
+        if indices[mid] == index { return true; }
+        if indices[mid] < index { low = mid + 1; } else { high = mid; }
+    }
+    false
+}
+
 struct LineboxScanner {
     flow: @FlowContext,
     new_boxes: DVec<@RenderBox>,
     work_list: DList<@RenderBox>,
     pending_line: {mut range: Range, mut width: Au},
     line_spans: DVec<Range>,
+    // the index in the flow's box list of the box being appended, and of
+    // the box each box on the pending line came from
+    mut current_source: uint,
+    line_sources: DVec<uint>,
 }
 
 fn LineboxScanner(inline: @FlowContext) -> LineboxScanner {
@@ -335,7 +496,9 @@ fn LineboxScanner(inline: @FlowContext) -> LineboxScanner {
         new_boxes: DVec(),
         work_list: DList(),
         pending_line: {mut range: Range::empty(), mut width: Au(0)},
-        line_spans: DVec()
+        line_spans: DVec(),
+        current_source: 0,
+        line_sources: DVec()
     }
 }
 
@@ -350,43 +513,88 @@ impl LineboxScanner {
     priv fn reset_linebox() {
         self.pending_line.range.reset(0,0);
         self.pending_line.width = Au(0);
+        self.line_sources.set(~[]);
     }
 
     pub fn scan_for_lines(ctx: &LayoutContext) {
         self.reset_scanner();
         
         let boxes = &self.flow.inline().boxes;
+        let opportunities = ctx.line_breaker.break_opportunities(boxes.get());
+        let forced_breaks = ctx.line_breaker.forced_breaks(boxes.get());
+        let mut next_forced = 0u;
+        let keep_newlines = preserves_newlines(self.flow.inline().white_space);
         let mut i = 0u;
 
         loop {
-            // acquire the next box to lay out from work list or box list
-            let cur_box = match self.work_list.pop() {
+            // acquire the next box to lay out from work list or box list.
+            // Boxes on the work list are the remainders of box i - 1.
+            let (cur_box, is_remainder) = match self.work_list.pop() {
                 Some(box) => { 
                     debug!("LineboxScanner: Working with box from work list: b%d", box.d().id);
-                    box
+                    (box, true)
                 },
                 None => { 
                     if i == boxes.len() { break; }
                     let box = boxes[i]; i += 1;
                     debug!("LineboxScanner: Working with box from box list: b%d", box.d().id);
-                    box
+                    (box, false)
                 }
             };
+            self.current_source = i - 1;
 
-            let box_was_appended = self.try_append_to_line(ctx, cur_box);
+            let mut box_was_appended = self.try_append_to_line(ctx, cur_box);
             if !box_was_appended {
-                debug!("LineboxScanner: Box wasn't appended, because line %u was full.",
-                       self.line_spans.len());
-                self.flush_current_line();
-            } else {
-                debug!("LineboxScanner: appended a box to line %u", self.line_spans.len());
+                // The box is back on the work list, and the line isn't empty. A box
+                // whose first part is on the line may end it where it was split;
+                // otherwise the line may only end after a box the breaker allows.
+                if is_remainder || sorted_contains(opportunities, i - 2) {
+                    debug!("LineboxScanner: Box wasn't appended, because line %u was full.",
+                           self.line_spans.len());
+                    self.flush_current_line(ctx, false);
+                } else {
+                    match self.rewind_to_last_break(opportunities) {
+                        Some(source) => {
+                            debug!("LineboxScanner: Line %u was full, so ending it at its last break.",
+                                   self.line_spans.len());
+                            self.work_list.pop();
+                            i = source;
+                            while next_forced > 0 && forced_breaks[next_forced - 1] >= i {
+                                next_forced -= 1;
+                            }
+                            self.flush_current_line(ctx, false);
+                        }
+                        None => {
+                            // TODO(Issue #224): signal that horizontal overflow happened?
+                            debug!("LineboxScanner: Line %u has no break, so overflowing.",
+                                   self.line_spans.len());
+                            self.work_list.pop();
+                            self.push_box_to_line(cur_box);
+                            box_was_appended = true;
+                        }
+                    }
+                }
+            }
+
+            if box_was_appended && self.work_list.is_empty() {
+                debug!("LineboxScanner: appended box %u to line %u", i - 1, self.line_spans.len());
+                while next_forced < forced_breaks.len() && forced_breaks[next_forced] < i - 1 {
+                    next_forced += 1;
+                }
+                if next_forced < forced_breaks.len() && forced_breaks[next_forced] == i - 1 {
+                    debug!("LineboxScanner: Line breaker ended line %u.", self.line_spans.len());
+                    self.flush_current_line(ctx, false);
+                } else if keep_newlines && boxes[i - 1].ends_with_newline() {
+                    debug!("LineboxScanner: Preserved newline ended line %u.", self.line_spans.len());
+                    self.flush_current_line(ctx, false);
+                }
             }
         }
 
         if self.pending_line.range.length() > 0 {
             debug!("LineboxScanner: Partially full linebox %u left at end of scanning.",
                    self.line_spans.len());
-            self.flush_current_line();
+            self.flush_current_line(ctx, true);
         }
 
         self.flow.inline().elems.repair_for_box_changes(&self.flow.inline().boxes, &self.new_boxes);
@@ -407,12 +615,18 @@ impl LineboxScanner {
         };
     }
 
-    priv fn flush_current_line() {
+    priv fn flush_current_line(ctx: &LayoutContext, is_last_line: bool) {
         debug!("LineboxScanner: Flushing line %u: %?",
                self.line_spans.len(), self.pending_line);
+        let indent = self.line_indent();
+        let available_width = self.flow.d().position.size.width - indent;
+        if self.pending_line.width > available_width && self.truncates_with_ellipsis() {
+            self.truncate_line_with_ellipsis(ctx, available_width);
+        }
+
         // set box horizontal offsets
         let line_range = self.pending_line.range;
-        let mut offset_x = Au(0);
+        let mut offset_x = indent;
         // TODO(Issue #199): interpretation of CSS 'direction' will change how boxes are positioned.
         debug!("LineboxScanner: Setting horizontal offsets for boxes in line %u range: %?",
                self.line_spans.len(), line_range);
@@ -421,7 +635,9 @@ impl LineboxScanner {
         // TODO(Issue #222): use 'text-align' property from InlineFlow's 
         // block container, not from the style of the first box child.
         let linebox_align;
-        if self.pending_line.range.begin() < self.new_boxes.len() {
+        if self.flow.inline().text_align.is_some() {
+            linebox_align = self.flow.inline().text_align.get();
+        } else if self.pending_line.range.begin() < self.new_boxes.len() {
             let first_box = self.new_boxes[self.pending_line.range.begin()];
             linebox_align = first_box.text_align();
         } else {
@@ -429,19 +645,33 @@ impl LineboxScanner {
             linebox_align = CSSTextAlignLeft;
         }
 
-        let slack_width = self.flow.d().position.size.width - self.pending_line.width;
+        let slack_width = self.flow.d().position.size.width - indent - self.pending_line.width;
         match linebox_align {
-            // So sorry, but justified text is more complicated than shuffling linebox coordinates.
-            // TODO(Issue #213): implement `text-align: justify`
-            CSSTextAlignLeft | CSSTextAlignJustify => {
+            CSSTextAlignLeft => {
                 for line_range.eachi |i| {
                     let box_data = &self.new_boxes[i].d();
                     box_data.position.origin.x = offset_x;
                     offset_x += box_data.position.size.width;
                 }
             },
+            // Slack is distributed evenly between the boxes of the line, except
+            // on the last line, which is left-aligned.
+            // TODO(Issue #213): justify between words within text boxes, too.
+            CSSTextAlignJustify => {
+                let gap_count = line_range.length() - 1;
+                let gap_width = if is_last_line || gap_count == 0 || slack_width < Au(0) {
+                    Au(0)
+                } else {
+                    slack_width / Au(gap_count as i32)
+                };
+                for line_range.eachi |i| {
+                    let box_data = &self.new_boxes[i].d();
+                    box_data.position.origin.x = offset_x;
+                    offset_x += box_data.position.size.width + gap_width;
+                }
+            },
             CSSTextAlignCenter => {
-                offset_x = slack_width.scale_by(0.5f);
+                offset_x = indent + slack_width.scale_by(0.5f);
                 for line_range.eachi |i| {
                     let box_data = &self.new_boxes[i].d();
                     box_data.position.origin.x = offset_x;
@@ -449,7 +679,7 @@ impl LineboxScanner {
                 }
             },
             CSSTextAlignRight => {
-                offset_x = slack_width;
+                offset_x = indent + slack_width;
                 for line_range.eachi |i| {
                     let box_data = &self.new_boxes[i].d();
                     box_data.position.origin.x = offset_x;
@@ -463,9 +693,107 @@ impl LineboxScanner {
         self.reset_linebox();
     }
 
+    /* Takes the boxes after the pending line's last allowed break back off
+       the line, returning the index in the flow's box list of the first of
+       them, or None if the line has no allowed break. Only whole boxes are
+       taken back, since a box is only split where it ends a line. */
+    priv fn rewind_to_last_break(opportunities: &[uint]) -> Option<uint> {
+        let line_range = self.pending_line.range;
+        let mut k = self.line_sources.len() - 1;
+        while k > 0 {
+            let source = self.line_sources[k - 1];
+            if self.line_sources[k] != source && sorted_contains(opportunities, source) {
+                let first_taken = self.line_sources[k];
+                while self.line_sources.len() > k {
+                    let box = self.new_boxes.pop();
+                    self.line_sources.pop();
+                    self.pending_line.width -= box.d().position.size.width;
+                }
+                self.pending_line.range.reset(line_range.begin(), k);
+                return Some(first_taken);
+            }
+            k -= 1;
+        }
+        None
+    }
+
+    // 'text-overflow' only applies when the flow's block container clips
+    priv fn truncates_with_ellipsis() -> bool {
+        match self.flow.inline().text_overflow {
+            TextOverflowClip => return false,
+            TextOverflowEllipsis => {}
+        }
+        match tree::parent(&FlowTree, &self.flow) {
+            Some(container) => match container.d().overflow {
+                OverflowVisible => false,
+                _ => true
+            },
+            None => false
+        }
+    }
+
+    /* Cuts the pending line short, so that it ends with an ellipsis and
+       fits in `available_width` (CSS3 UI, 'text-overflow'). The first box
+       that doesn't fit is cut between characters if it is text, and
+       dropped otherwise, along with the boxes after it. */
+    priv fn truncate_line_with_ellipsis(ctx: &LayoutContext, available_width: Au) {
+        let line_range = self.pending_line.range;
+        let last_box = self.new_boxes[line_range.end() - 1];
+        let ellipsis = make_ellipsis_box(ctx, last_box);
+        let room = available_width - ellipsis.d().position.size.width;
+        debug!("LineboxScanner: Truncating line %u to %? before an ellipsis",
+               self.line_spans.len(), room);
+
+        let mut kept = ~[];
+        let mut width = Au(0);
+        for line_range.eachi |i| {
+            let box = self.new_boxes[i];
+            let box_width = box.d().position.size.width;
+            if width + box_width <= room {
+                kept.push(box);
+                width += box_width;
+                loop;
+            }
+            match box {
+                @TextBox(_, ref data) => {
+                    let mut chars = data.range.length();
+                    while chars > 0 {
+                        let range = Range::new(data.range.begin(), chars);
+                        if width + data.run.metrics_for_range(&const range).advance_width <= room {
+                            break;
+                        }
+                        chars -= 1;
+                    }
+                    if chars > 0 {
+                        let part = adapt_textbox_with_range(box.d(), data.run,
+                                                           &const Range::new(data.range.begin(), chars));
+                        width += part.d().position.size.width;
+                        kept.push(part);
+                    }
+                }
+                _ => {}
+            }
+            break;
+        }
+        width += ellipsis.d().position.size.width;
+        kept.push(ellipsis);
+
+        do self.new_boxes.swap |boxes| {
+            let mut boxes = move boxes;
+            while boxes.len() > line_range.begin() {
+                boxes.pop();
+            }
+            boxes.push_all(kept);
+            move boxes
+        };
+        self.pending_line.range.reset(line_range.begin(), kept.len());
+        self.pending_line.width = width;
+    }
+
     // return value: whether any box was appended.
     priv fn try_append_to_line(ctx: &LayoutContext, in_box: @RenderBox) -> bool {
-        let remaining_width = self.flow.d().position.size.width - self.pending_line.width;
+        let remaining_width = self.flow.d().position.size.width - self.line_indent()
+            - self.pending_line.width;
         let in_box_width = in_box.d().position.size.width;
         let line_is_empty: bool = self.pending_line.range.length() == 0;
 
@@ -478,6 +806,14 @@ impl LineboxScanner {
             return true;
         }
 
+        if !soft_wraps(self.flow.inline().white_space) {
+            // TODO(Issue #224): signal that horizontal overflow happened?
+            debug!("LineboxScanner: case=box doesn't fit, but line %u doesn't wrap, so overflowing.",
+                   self.line_spans.len());
+            self.push_box_to_line(in_box);
+            return true;
+        }
+
         if !in_box.can_split() {
             // force it onto the line anyway, if its otherwise empty
             // TODO(Issue #224): signal that horizontal overflow happened?
@@ -487,7 +823,8 @@ impl LineboxScanner {
                 self.push_box_to_line(in_box);
                 return true;
             } else {
-                debug!("LineboxScanner: Case=box can't split, not appending.");
+                debug!("LineboxScanner: Case=box can't split, not appending and deferring box.");
+                self.work_list.push_head(in_box);
                 return false;
             }
         }
@@ -543,6 +880,11 @@ impl LineboxScanner {
         }
     }
 
+    // 'text-indent' only shifts the first line
+    priv fn line_indent() -> Au {
+        if self.line_spans.len() == 0 { self.flow.inline().text_indent } else { Au(0) }
+    }
+
     // unconditional push
     priv fn push_box_to_line(box: @RenderBox) {
         debug!("LineboxScanner: Pushing box b%d to line %u", box.d().id, self.line_spans.len());
@@ -554,9 +896,22 @@ impl LineboxScanner {
         self.pending_line.range.extend_by(1);
         self.pending_line.width += box.d().position.size.width;
         self.new_boxes.push(box);
+        self.line_sources.push(self.current_source);
     }
 }
 
+/* A text box of "\u2026", in the font of `like`, or else of its style. */
+priv fn make_ellipsis_box(ctx: &LayoutContext, like: @RenderBox) -> @RenderBox {
+    let run = match like {
+        @TextBox(_, ref data) => @TextRun::new(data.run.font, ~"\u2026"),
+        _ => {
+            let fontgroup = ctx.font_ctx.get_resolved_font_for_style(&like.font_style());
+            @fontgroup.create_textrun(~"\u2026")
+        }
+    };
+    adapt_textbox_with_range(like.d(), run, &const Range::new(0, run.char_len()))
+}
+
 pub struct InlineFlowData {
     // A vec of all inline render boxes. Several boxes may
     // correspond to one Node/Element.
@@ -567,7 +922,43 @@ pub struct InlineFlowData {
     // vec of ranges into boxes that represent elements. These ranges
     // must be well-nested, and are only related to the content of
     // boxes (not lines). Ranges are only kept for non-leaf elements.
-    elems: ElementMapping
+    elems: ElementMapping,
+    // alignment of boxes within each line. If None, the 'text-align'
+    // style of the first box on each line is used.
+    mut text_align: Option<CSSTextAlign>,
+    // height of each line box. If None, each line box is as tall as
+    // the natural height of its boxes.
+    // TODO(Issue #226): get CSS 'line-height' from the containing block's style.
+    mut line_height: Option<Au>,
+    // how far the first line's content is shifted to the right, out of
+    // the width that it has ('text-indent').
+    // TODO: get CSS 'text-indent' from the containing block's style.
+    mut text_indent: Au,
+    // vertical metrics of each box in `boxes`, computed by assign_height.
+    box_metrics: DVec<BoxVerticalMetrics>,
+    // TODO(Issue #115): get CSS 'white-space' from the containing block's style.
+    mut white_space: WhiteSpace,
+    // what lines that overflow a clipping container end with
+    // TODO: get CSS 'text-overflow' from the containing block's style.
+    mut text_overflow: TextOverflow
+}
+
+/// The CSS 'white-space' property, as far as it is supported.
+pub enum WhiteSpace {
+    // whitespace collapses, and lines wrap
+    WhiteSpaceNormal,
+    // whitespace is preserved, and lines end at newlines
+    WhiteSpacePre,
+    // whitespace is preserved, lines end at newlines, and lines also wrap
+    WhiteSpacePreWrap
+}
+
+/// The CSS 'text-overflow' property.
+pub enum TextOverflow {
+    // overflowing content is clipped
+    TextOverflowClip,
+    // overflowing content is cut short, and an ellipsis shows where
+    TextOverflowEllipsis
 }
 
 pub fn InlineFlowData() -> InlineFlowData {
@@ -575,15 +966,68 @@ pub fn InlineFlowData() -> InlineFlowData {
         boxes: DVec(),
         lines: DVec(),
         elems: ElementMapping::new(),
+        text_align: None,
+        line_height: None,
+        text_indent: Au(0),
+        box_metrics: DVec(),
+        white_space: WhiteSpaceNormal,
+        text_overflow: TextOverflowClip
+    }
+}
+
+impl InlineFlowData {
+    /// A copy of this data that shares its render boxes.
+    fn clone(&self) -> InlineFlowData {
+        InlineFlowData {
+            boxes: dvec::from_vec(self.boxes.get()),
+            lines: dvec::from_vec(self.lines.get()),
+            elems: self.elems.clone(),
+            text_align: self.text_align,
+            line_height: self.line_height,
+            text_indent: self.text_indent,
+            box_metrics: dvec::from_vec(self.box_metrics.get()),
+            white_space: self.white_space,
+            text_overflow: self.text_overflow
+        }
     }
 }
 
+/// The extent of a box above and below its baseline.
+pub struct BoxVerticalMetrics {
+    ascent: Au,
+    descent: Au
+}
+
+/**
+Aligns the boxes of one line on a common baseline. Returns the offset of
+each box's top from the top of the line box, and the line box's height.
+Any difference between `line_height` and the natural height of the boxes
+is split evenly above and below them, as half-leading.
+*/
+priv fn place_boxes_on_baseline(metrics: &[BoxVerticalMetrics],
+                                line_height: Option<Au>) -> (~[Au], Au) {
+    let mut max_ascent = Au(0);
+    let mut max_descent = Au(0);
+    for metrics.each |m| {
+        max_ascent = Au::max(max_ascent, m.ascent);
+        max_descent = Au::max(max_descent, m.descent);
+    }
+
+    let natural_height = max_ascent + max_descent;
+    let linebox_height = line_height.get_default(natural_height);
+    let baseline = (linebox_height - natural_height).scale_by(0.5f) + max_ascent;
+
+    (metrics.map(|m| baseline - m.ascent), linebox_height)
+}
+
 trait InlineLayout {
     pure fn starts_inline_flow() -> bool;
 
     fn bubble_widths_inline(@self, ctx: &LayoutContext);
+    fn intrinsic_widths_inline(@self, ctx: &LayoutContext) -> (Au, Au);
     fn assign_widths_inline(@self, ctx: &LayoutContext);
     fn assign_height_inline(@self, ctx: &LayoutContext);
+    fn selection_rects(@self, node: Node, start: uint, end: uint) -> ~[Rect<Au>];
     fn build_display_list_inline(@self, a: &DisplayListBuilder, b: &Rect<Au>, c: &Point2D<Au>,
                                  d: &mut DisplayList);
 }
@@ -597,6 +1041,25 @@ impl FlowContext : InlineLayout {
         let mut scanner = TextRunScanner::new();
         scanner.scan_for_runs(ctx, self);
 
+        // an inline-block's flow isn't a child in the flow tree, so the
+        // traversal never reaches it; bubble its widths before measuring it.
+        for self.inline().boxes.each |box| {
+            match *box {
+                @InlineBlockBox(_, flow) => do flow.traverse_postorder |f| { f.bubble_widths(ctx) },
+                _ => {}
+            }
+        }
+
+        let (min_width, pref_width) = self.intrinsic_widths_inline(ctx);
+        self.d().min_width = min_width;
+        self.d().pref_width = pref_width;
+    }
+
+    /* Measures the (min, pref) widths of the flow's boxes. Text runs
+       must already have been scanned. */
+    fn intrinsic_widths_inline(@self, ctx: &LayoutContext) -> (Au, Au) {
+        assert self.starts_inline_flow();
+
         let mut min_width = Au(0);
         let mut pref_width = Au(0);
 
@@ -606,8 +1069,7 @@ impl FlowContext : InlineLayout {
             pref_width = Au::max(pref_width, box.get_pref_width(ctx));
         }
 
-        self.d().min_width = min_width;
-        self.d().pref_width = pref_width;
+        (min_width, pref_width)
     }
 
     /* Recursively (top-down) determines the actual width of child
@@ -621,13 +1083,19 @@ impl FlowContext : InlineLayout {
         // over the box list, and/or put into RenderBox.
         for self.inline().boxes.each |box| {
             box.d().position.size.width = match *box {
-                @ImageBox(_, ref img) => {
-                    Au::from_px(img.get_size().get_default(Size2D(0,0)).width)
-                }
+                @ImageBox(*) => box.replaced_size().width,
                 @TextBox(*) => { /* text boxes are initialized with dimensions */
                                    box.d().position.size.width
                 },
-                // TODO(Issue #225): different cases for 'inline-block', other replaced content
+                // an inline-block is as wide as declared, or else shrinks to fit
+                // its content within the line's width. The box is atomic, so
+                // the line breaker never looks inside its flow.
+                @InlineBlockBox(_, flow) => {
+                    flow.d().position.size.width = self.d().position.size.width;
+                    do flow.traverse_preorder |f| { f.assign_widths(ctx) }
+                    flow.d().position.size.width
+                }
+                // TODO(Issue #225): other replaced content
                 @GenericBox(*) => Au::from_px(45), 
                 _ => fail fmt!("Tried to assign width to unknown Box variant: %?", box)
             };
@@ -636,82 +1104,65 @@ impl FlowContext : InlineLayout {
         let mut scanner = LineboxScanner(self);
         scanner.scan_for_lines(ctx);
    
-        /* There are no child contexts, so stop here. */
-
-        // TODO(Issue #225): once there are 'inline-block' elements, this won't be
-        // true.  In that case, set the InlineBlockBox's width to the
-        // shrink-to-fit width, perform inline flow, and set the block
-        // flow context's width as the assigned width of the
-        // 'inline-block' box that created this flow before recursing.
+        /* There are no child contexts, so stop here. Inline-blocks laid
+           out their own flows above. */
     }
 
-    fn assign_height_inline(@self, _ctx: &LayoutContext) {
-        // TODO(Issue #226): get CSS 'line-height' property from
-        // containing block's style to determine minimum linebox height.
-        // TODO(Issue #226): get CSS 'line-height' property from each non-replaced
-        // inline element to determine its height for computing linebox height.
-        let line_height = Au::from_px(20);
+    fn assign_height_inline(@self, ctx: &LayoutContext) {
         let mut cur_y = Au(0);
+        let boxes = &self.inline().boxes;
+        self.inline().box_metrics.set(~[]);
 
         for self.inline().lines.eachi |i, line_span| {
             debug!("assign_height_inline: processing line %u with box span: %?", i, line_span);
-            // coords relative to left baseline
-            let mut linebox_bounding_box = Au::zero_rect();
-            let boxes = &self.inline().boxes;
+            let mut line_metrics = ~[];
             for line_span.eachi |box_i| {
                 let cur_box = boxes[box_i];
 
                 // compute box height.
                 cur_box.d().position.size.height = match cur_box {
-                    @ImageBox(_, ref img) => Au::from_px(img.size().height),
+                    @ImageBox(*) => cur_box.replaced_size().height,
                     @TextBox(*) => { /* text boxes are initialized with dimensions */
                         cur_box.d().position.size.height
                     },
-                    // TODO(Issue #225): different cases for 'inline-block', other replaced content
+                    // the inline-block's own formatting context decides its height
+                    @InlineBlockBox(_, flow) => {
+                        do flow.traverse_postorder |f| { f.assign_height(ctx) }
+                        flow.d().position.size.height
+                    }
+                    // TODO(Issue #225): other replaced content
                     @GenericBox(*) => Au::from_px(30),
                     _ => fail fmt!("Tried to assign height to unknown Box variant: %s", cur_box.debug_str())
                 };
 
-                // compute bounding rect, with left baseline as origin.
-                // so, linebox height is a matter of lining up ideal baselines,
-                // and then using the union of all these rects.
-                let bounding_box = match cur_box {
-                    // adjust to baseline coords
-                    // TODO(Issue #227): use left/right margins, border, padding for nonreplaced content,
-                    // and also use top/bottom  margins, border, padding for replaced or inline-block content.
-                    // TODO(Issue #225): use height, width for 'inline-block', other replaced content
-                    @ImageBox(*) | @GenericBox(*) => {
-                        let box_bounds = cur_box.d().position;
-                        box_bounds.translate(&Point2D(Au(0), -cur_box.d().position.size.height))
+                // compute the box's extent above and below its baseline.
+                // TODO(Issue #227): use top/bottom margins, border, padding for replaced or
+                // inline-block content.
+                let metrics = match cur_box {
+                    @TextBox(_, data) => BoxVerticalMetrics {
+                        ascent: data.run.font.metrics.ascent,
+                        descent: data.run.font.metrics.descent
                     },
-                    // adjust bounding box metric to box's horizontal offset
-                    // TODO: we can use font metrics directly instead of re-measuring for the bounding box.
-                    @TextBox(_, data) => { 
-                        let text_bounds = data.run.metrics_for_range(&const data.range).bounding_box;
-                        text_bounds.translate(&Point2D(cur_box.d().position.origin.x, Au(0)))
-                    },
-                    _ => fail fmt!("Tried to compute bounding box of unknown Box variant: %s", cur_box.debug_str())
+                    // replaced and generic content sits on the baseline.
+                    _ => BoxVerticalMetrics {
+                        ascent: cur_box.d().position.size.height,
+                        descent: Au(0)
+                    }
                 };
-                debug!("assign_height_inline: bounding box for box b%d = %?", cur_box.d().id, bounding_box);
-                linebox_bounding_box = linebox_bounding_box.union(&bounding_box);
-                debug!("assign_height_inline: linebox bounding box = %?", linebox_bounding_box);
+                debug!("assign_height_inline: metrics for box b%d = %?", cur_box.d().id, metrics);
+                line_metrics.push(metrics);
             }
-            let linebox_height = linebox_bounding_box.size.height;
-            let baseline_offset = -linebox_bounding_box.origin.y;
-            // now go back and adjust y coordinates to match determined baseline
+
+            // TODO(Issue #226): use 'vertical-align' to offset boxes from the baseline.
+            let (box_offsets, linebox_height) = place_boxes_on_baseline(line_metrics,
+                                                                        self.inline().line_height);
             for line_span.eachi |box_i| {
-                let cur_box = boxes[box_i];
-                // TODO(Issue #226): this is completely wrong. Need to use element's 
-                // 'line-height' when calculating linebox height. Then, go back over 
-                // and set y offsets according to 'vertical-align' property of containing block.
-                let halfleading = match cur_box {
-                    @TextBox(_, data) => { (data.run.font.metrics.em_size - line_height).scale_by(0.5f) },
-                    _ => { Au(0) }
-                };
-                cur_box.d().position.origin.y = cur_y + halfleading + (baseline_offset - cur_box.d().position.size.height);
+                let offset_i = box_i - line_span.begin();
+                boxes[box_i].d().position.origin.y = cur_y + box_offsets[offset_i];
             }
-            
-            cur_y += Au::max(line_height, linebox_height);
+            for line_metrics.each |metrics| { self.inline().box_metrics.push(*metrics); }
+
+            cur_y += linebox_height;
         } // /lines.each |line_span|
 
         self.d().position.size.height = cur_y;
@@ -722,6 +1173,12 @@ impl FlowContext : InlineLayout {
 
         assert self.starts_inline_flow();
 
+        // the boxes of a hidden flow still take up space on their lines
+        match self.d().visibility {
+            Visible => {}
+            Hidden => return
+        }
+
         // TODO(Issue #228): once we form line boxes and have their cached bounds, we can be 
         // smarter and not recurse on a line if nothing in it can intersect dirty
         debug!("FlowContext[%d]: building display list for %u inline boxes",
@@ -734,4 +1191,462 @@ impl FlowContext : InlineLayout {
         // of the inline flow, or should the flow be nested inside the box somehow?
     }
 
+    /* The areas covering characters `start` to `end` of the content of
+       `node`, one per line that the selection crosses, relative to this
+       flow. Offsets count the characters of the node's text boxes in
+       order; any other box of the node counts as one character. Only
+       meaningful after layout. */
+    fn selection_rects(@self, node: Node, start: uint, end: uint) -> ~[Rect<Au>] {
+        assert self.starts_inline_flow();
+        assert start <= end;
+
+        let selection = NodeRange::new(node, &Range::new(start, end - start));
+        let mut rects = ~[];
+        // the offset of the next box of the node
+        let mut offset = 0;
+        for self.inline().lines.each |line| {
+            let mut line_rect: Option<Rect<Au>> = None;
+            for line.eachi |i| {
+                let box = self.inline().boxes[i];
+                if !box.d().is_box_of(selection.node) { loop; }
+
+                let chars = match box {
+                    @TextBox(_, ref d) => d.range.length(),
+                    _ => 1
+                };
+                let begin = uint::max(selection.range.begin(), offset) - offset;
+                let end = uint::min(selection.range.end(), offset + chars) - offset;
+                offset += chars;
+                if begin >= end { loop; }
+
+                let position = copy box.d().position;
+                let rect = match box {
+                    @TextBox(_, ref d) => {
+                        let mut x = position.origin.x;
+                        if begin > 0 {
+                            x += d.run.metrics_for_range(&Range::new(d.range.begin(), begin)).advance_width;
+                        }
+                        let selected = Range::new(d.range.begin() + begin, end - begin);
+                        let width = d.run.metrics_for_range(&selected).advance_width;
+                        Rect(Point2D(x, position.origin.y), Size2D(width, position.size.height))
+                    }
+                    _ => position
+                };
+                line_rect = match line_rect {
+                    Some(line_rect) => Some(line_rect.union(&rect)),
+                    None => Some(rect)
+                };
+            }
+            match line_rect {
+                Some(rect) => rects.push(rect),
+                None => {}
+            }
+        }
+        rects
+    }
+
 } // @FlowContext : InlineLayout
+
+#[cfg(test)]
+mod test {
+    use dom::node::{NodeScope, Text};
+    use gfx::image::holder::ImageHolder;
+    use gfx::font_context::dummy_style;
+    use gfx::util::url::make_url;
+    use layout::block::BlockFlowData;
+    use layout::context::test_layout_context;
+    use layout::flow::{BlockFlow, FixedHeight, FlowData, InlineBlockFlow, Length, OverflowHidden};
+    use layout::test_builder;
+
+    // An inline flow of the given width, holding `n` generic boxes (45px wide each).
+    fn inline_flow_with_boxes(width: Au, n: uint) -> @FlowContext {
+        let flow = test_builder::root().inline_boxes(n).last_flow();
+        flow.d().position.size.width = width;
+        flow
+    }
+
+    #[test]
+    fn boxes_with_different_ascents_share_a_baseline() {
+        let metrics = ~[BoxVerticalMetrics { ascent: Au::from_px(10), descent: Au::from_px(2) },
+                        BoxVerticalMetrics { ascent: Au::from_px(20), descent: Au::from_px(4) }];
+        let (offsets, height) = place_boxes_on_baseline(metrics, None);
+
+        assert height == Au::from_px(24);
+        assert offsets[0] + metrics[0].ascent == Au::from_px(20);
+        assert offsets[1] + metrics[1].ascent == Au::from_px(20);
+    }
+
+    #[test]
+    fn line_height_adds_half_leading_above_baseline() {
+        let metrics = ~[BoxVerticalMetrics { ascent: Au::from_px(10), descent: Au::from_px(2) },
+                        BoxVerticalMetrics { ascent: Au::from_px(20), descent: Au::from_px(4) }];
+        let (offsets, height) = place_boxes_on_baseline(metrics, Some(Au::from_px(30)));
+
+        assert height == Au::from_px(30);
+        assert offsets[0] + metrics[0].ascent == Au::from_px(23);
+        assert offsets[1] + metrics[1].ascent == Au::from_px(23);
+    }
+
+    #[test]
+    fn center_aligned_line_is_offset_by_half_the_slack() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = inline_flow_with_boxes(Au::from_px(300), 2);
+        flow.inline().text_align = Some(CSSTextAlignCenter);
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().lines.len() == 1;
+        assert flow.inline().boxes[0].d().position.origin.x == Au::from_px(105);
+        assert flow.inline().boxes[1].d().position.origin.x == Au::from_px(150);
+    }
+
+    #[test]
+    fn text_indent_shifts_and_narrows_only_the_first_line() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        // two boxes would fit on an unindented line
+        let flow = inline_flow_with_boxes(Au::from_px(100), 3);
+        flow.inline().text_indent = Au::from_px(20);
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().lines.len() == 2;
+        assert flow.inline().lines[0].length() == 1;
+        assert flow.inline().boxes[0].d().position.origin.x == Au::from_px(20);
+        assert flow.inline().boxes[1].d().position.origin.x == Au(0);
+        assert flow.inline().boxes[2].d().position.origin.x == Au::from_px(45);
+    }
+
+    #[test]
+    fn right_aligned_line_is_offset_by_all_the_slack() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = inline_flow_with_boxes(Au::from_px(300), 2);
+        flow.inline().text_align = Some(CSSTextAlignRight);
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().boxes[0].d().position.origin.x == Au::from_px(210);
+        assert flow.inline().boxes[1].d().position.origin.x == Au::from_px(255);
+    }
+
+    #[test]
+    fn justified_lines_spread_boxes_except_on_the_last_line() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        // three boxes fit per line, so the fourth starts a second (last) line.
+        let flow = inline_flow_with_boxes(Au::from_px(145), 4);
+        flow.inline().text_align = Some(CSSTextAlignJustify);
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().lines.len() == 2;
+        assert flow.inline().boxes[0].d().position.origin.x == Au(0);
+        assert flow.inline().boxes[1].d().position.origin.x == Au::from_px(50);
+        assert flow.inline().boxes[2].d().position.origin.x == Au::from_px(100);
+        assert flow.inline().boxes[3].d().position.origin.x == Au(0);
+    }
+
+    #[test]
+    fn whitespace_collapses_across_box_boundaries() {
+        let texts = transform_clump_text([~"foo ", ~" bar"], compression_for(WhiteSpaceNormal));
+        assert str::concat(texts) == ~"foo bar";
+        assert texts[1] == ~"bar";
+    }
+
+    #[test]
+    fn leading_tab_advances_to_tab_stop() {
+        assert expand_tabs([~"\tx"], 4) == ~[~"    x"];
+        assert expand_tabs([~"\tx"], 8) == ~[~"        x"];
+    }
+
+    #[test]
+    fn tab_stops_continue_across_boxes_and_restart_after_newline() {
+        assert expand_tabs([~"ab", ~"\tc\n\td"], 4) == ~[~"ab", ~"  c\n    d"];
+    }
+
+    #[test]
+    fn zero_tab_size_drops_tabs() {
+        assert expand_tabs([~"a\tb", ~"\tc"], 0) == ~[~"ab", ~"c"];
+    }
+
+    #[test]
+    fn whitespace_is_kept_across_box_boundaries_for_pre() {
+        let texts = transform_clump_text([~"foo ", ~" bar"], compression_for(WhiteSpacePre));
+        assert str::concat(texts) == ~"foo  bar";
+    }
+
+    #[test]
+    fn pre_wrap_keeps_spaces_ends_lines_at_newlines_and_wraps() {
+        let text = ~"foo   bar  \n   baz";
+        let texts = transform_clump_text([copy text], compression_for(WhiteSpacePreWrap));
+        assert texts[0] == text;
+
+        // the newline ends the first piece; spaces on both sides of it are kept.
+        let pieces = newline_split_ranges(texts[0], &const Range::new(0, str::char_len(text)));
+        assert pieces.len() == 2;
+        assert pieces[0].begin() == 0 && pieces[0].length() == 12;
+        assert pieces[1].begin() == 12 && pieces[1].length() == 6;
+
+        // lines still wrap where they're full.
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = inline_flow_with_boxes(Au::from_px(100), 3);
+        flow.inline().white_space = WhiteSpacePreWrap;
+        flow.assign_widths_inline(&ctx);
+        assert flow.inline().lines.len() == 2;
+    }
+
+    // An inline flow of text boxes for `text`, cut after each newline, in
+    // a flow that is as wide as the text up to `fitting`.
+    fn preformatted_text_flow(ctx: &LayoutContext, white_space: WhiteSpace, text: ~str,
+                              fitting: uint) -> @FlowContext {
+        let builder = test_builder::root().inline_boxes(0);
+        let flow = builder.last_flow();
+        flow.inline().white_space = white_space;
+
+        let fontgroup = ctx.font_ctx.get_resolved_font_for_style(&dummy_style());
+        let run = @fontgroup.create_textrun(move text);
+        let data = RenderBoxData(builder.new_node(), flow, 0);
+        push_text_boxes(flow, &data, run, &const Range::new(0, run.char_len()), &flow.inline().boxes);
+        // a pixel of room left, less than a space
+        let width = run.metrics_for_range(&const Range::new(0, fitting)).advance_width;
+        flow.d().position.size.width = width + Au::from_px(1);
+        flow
+    }
+
+    fn box_ranges(flow: @FlowContext) -> ~[Range] {
+        do flow.inline().boxes.get().map |box| {
+            match *box {
+                @TextBox(_, ref d) => d.range,
+                _ => fail ~"expected a text box"
+            }
+        }
+    }
+
+    #[test]
+    fn pre_wrap_text_keeps_spaces_and_wraps_where_the_line_is_full() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = preformatted_text_flow(&ctx, WhiteSpacePreWrap, ~"aa  bb cccc\ndd", 6);
+        flow.assign_widths_inline(&ctx);
+
+        // "aa  bb", then "cccc\n", which the newline ends, then "dd"
+        assert flow.inline().lines.len() == 3;
+        let ranges = box_ranges(flow);
+        assert ranges[0].begin() == 0 && ranges[0].length() == 6;
+        assert ranges[1].begin() == 7 && ranges[1].length() == 5;
+        assert ranges[2].begin() == 12 && ranges[2].length() == 2;
+    }
+
+    #[test]
+    fn pre_text_only_breaks_at_newlines() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = preformatted_text_flow(&ctx, WhiteSpacePre, ~"aa  bb cccc\ndd", 6);
+        flow.assign_widths_inline(&ctx);
+
+        // the first line overflows instead of wrapping
+        assert flow.inline().lines.len() == 2;
+        let ranges = box_ranges(flow);
+        assert ranges[0].begin() == 0 && ranges[0].length() == 12;
+        assert ranges[1].begin() == 12 && ranges[1].length() == 2;
+    }
+
+    struct EveryBoxLineBreaker;
+
+    impl EveryBoxLineBreaker : LineBreaker {
+        fn break_opportunities(&self, boxes: &[@RenderBox]) -> ~[uint] {
+            vec::from_fn(boxes.len(), |i| i)
+        }
+
+        fn forced_breaks(&self, boxes: &[@RenderBox]) -> ~[uint] {
+            vec::from_fn(boxes.len(), |i| i)
+        }
+
+        fn name(&self) -> &static/str { "EveryBoxLineBreaker" }
+    }
+
+    #[test]
+    fn line_breaker_can_force_breaks() {
+        let ctx = LayoutContext {
+            line_breaker: @EveryBoxLineBreaker as @LineBreaker,
+            .. test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)))
+        };
+        // all three boxes would fit on one line.
+        let flow = inline_flow_with_boxes(Au::from_px(300), 3);
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().lines.len() == 3;
+    }
+
+    // allows a break only after the first box
+    struct FirstBoxLineBreaker;
+
+    impl FirstBoxLineBreaker : LineBreaker {
+        fn break_opportunities(&self, _boxes: &[@RenderBox]) -> ~[uint] {
+            ~[0]
+        }
+
+        fn forced_breaks(&self, _boxes: &[@RenderBox]) -> ~[uint] {
+            ~[]
+        }
+
+        fn name(&self) -> &static/str { "FirstBoxLineBreaker" }
+    }
+
+    #[test]
+    fn full_line_ends_at_its_last_break_opportunity() {
+        let ctx = LayoutContext {
+            line_breaker: @FirstBoxLineBreaker as @LineBreaker,
+            .. test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)))
+        };
+        // two boxes fit per line, but the line may only end after the first,
+        // and the second line has no break, so it overflows.
+        let flow = inline_flow_with_boxes(Au::from_px(100), 4);
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().lines.len() == 2;
+        assert flow.inline().lines[0].length() == 1;
+        assert flow.inline().lines[1].length() == 3;
+        assert flow.inline().boxes[3].d().position.origin.x == Au::from_px(90);
+    }
+
+    // Adds an inline-block box of the given declared size to `flow`.
+    fn add_inline_block(flow: @FlowContext, width: Au, height: Au) -> @RenderBox {
+        let inline_block = @InlineBlockFlow(FlowData(100));
+        inline_block.d().width = Length(width);
+        inline_block.d().height = FixedHeight(height);
+        let node = flow.inline().boxes[0].d().node;
+        let box = @InlineBlockBox(RenderBoxData(node, flow, 100), inline_block);
+        flow.inline().boxes.push(box);
+        box
+    }
+
+    #[test]
+    fn inline_block_is_one_atomic_box_on_the_line() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = inline_flow_with_boxes(Au::from_px(85), 1);
+        let box = add_inline_block(flow, Au::from_px(40), Au::from_px(20));
+        flow.bubble_widths_inline(&ctx);
+        flow.assign_widths_inline(&ctx);
+        flow.assign_height_inline(&ctx);
+
+        assert flow.inline().lines.len() == 1;
+        assert box.d().position.origin.x == Au::from_px(45);
+        assert box.d().position.size.width == Au::from_px(40);
+        assert box.d().position.size.height == Au::from_px(20);
+    }
+
+    #[test]
+    fn auto_width_inline_block_lays_out_its_block_children() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = inline_flow_with_boxes(Au::from_px(300), 1);
+        let node = flow.inline().boxes[0].d().node;
+
+        // inline-block > block > inline holding a 40x20 image
+        let inline_block = @InlineBlockFlow(FlowData(100));
+        let block = @BlockFlow(FlowData(101), BlockFlowData());
+        let inner = @InlineFlow(FlowData(102), InlineFlowData());
+        FlowTree.add_child(inline_block, block);
+        FlowTree.add_child(block, inner);
+        let holder = ImageHolder::new(make_url(~"http://example.com/a.png", None), ctx.image_cache);
+        let image = @ImageBox(RenderBoxData(node, inner, 102), move holder);
+        image.d().intrinsic_size = Some(Size2D(Au::from_px(40), Au::from_px(20)));
+        inner.inline().boxes.push(image);
+        let box = @InlineBlockBox(RenderBoxData(node, flow, 100), inline_block);
+        flow.inline().boxes.push(box);
+
+        flow.bubble_widths_inline(&ctx);
+        flow.assign_widths_inline(&ctx);
+        flow.assign_height_inline(&ctx);
+
+        assert box.d().position.size == Size2D(Au::from_px(40), Au::from_px(20));
+        assert block.d().position.size == Size2D(Au::from_px(40), Au::from_px(20));
+        assert image.d().position.size == Size2D(Au::from_px(40), Au::from_px(20));
+    }
+
+    #[test]
+    fn replaced_width_follows_height_by_intrinsic_ratio() {
+        let size = used_replaced_size(None, Some(Au::from_px(100)), None, Some(2f));
+        assert size == Size2D(Au::from_px(200), Au::from_px(100));
+
+        // without a declared ratio, the intrinsic size gives it
+        let intrinsic = Some(Size2D(Au::from_px(40), Au::from_px(20)));
+        let size = used_replaced_size(Some(Au::from_px(60)), None, intrinsic, None);
+        assert size == Size2D(Au::from_px(60), Au::from_px(30));
+        assert used_replaced_size(None, None, intrinsic, None) == intrinsic.get();
+        assert used_replaced_size(None, None, None, None) == Size2D(Au::from_px(300), Au::from_px(150));
+    }
+
+    #[test]
+    fn image_box_is_sized_as_replaced_content() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = inline_flow_with_boxes(Au::from_px(400), 1);
+        let node = flow.inline().boxes[0].d().node;
+        let holder = ImageHolder::new(make_url(~"http://example.com/a.png", None), ctx.image_cache);
+        let box = @ImageBox(RenderBoxData(node, flow, 100), move holder);
+        box.d().intrinsic_size = Some(Size2D(Au::from_px(40), Au::from_px(20)));
+        box.d().declared_height = Some(Au::from_px(100));
+        flow.inline().boxes.push(box);
+        flow.assign_widths_inline(&ctx);
+        flow.assign_height_inline(&ctx);
+
+        assert box.d().position.size == Size2D(Au::from_px(200), Au::from_px(100));
+    }
+
+    #[test]
+    fn first_line_range_covers_boxes_of_first_line() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        // three boxes fit per line, so the fourth starts a second line.
+        let flow = inline_flow_with_boxes(Au::from_px(145), 4);
+        assert flow.first_line_range().is_none();
+
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().lines.len() == 2;
+        let range = flow.first_line_range().get().range;
+        assert range.begin() == 0 && range.length() == 3;
+    }
+
+    #[test]
+    fn selection_across_wrapped_line_gives_one_rect_per_line() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let flow = inline_flow_with_boxes(Au::from_px(100), 0);
+        // two boxes fit per line
+        let node = NodeScope().new_node(Text(~"test"));
+        for uint::range(0, 4) |i| {
+            flow.inline().boxes.push(@GenericBox(RenderBoxData(node, flow, i as int)));
+        }
+        flow.assign_widths_inline(&ctx);
+        flow.assign_height_inline(&ctx);
+        assert flow.inline().lines.len() == 2;
+
+        let rects = flow.selection_rects(node, 1, 3);
+        assert rects.len() == 2;
+        assert rects[0] == copy flow.inline().boxes[1].d().position;
+        assert rects[1] == copy flow.inline().boxes[2].d().position;
+        assert rects[1].origin.y > rects[0].origin.y;
+    }
+
+    #[test]
+    fn long_line_in_clipped_block_ends_with_ellipsis() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let builder = test_builder::root().block().inline_boxes(0);
+        let flow = builder.last_flow();
+        let block = tree::parent(&FlowTree, &flow).get();
+        block.d().overflow = OverflowHidden;
+        flow.d().position.size.width = Au::from_px(50);
+        flow.inline().text_overflow = TextOverflowEllipsis;
+
+        // one word, far wider than the flow, so the line can't wrap
+        let fontgroup = ctx.font_ctx.get_resolved_font_for_style(&dummy_style());
+        let run = @fontgroup.create_textrun(~"abcdefghijklmnopqrstuvwxyz");
+        let data = RenderBoxData(builder.new_node(), flow, 0);
+        flow.inline().boxes.push(adapt_textbox_with_range(&data, run, &const Range::new(0, run.char_len())));
+        flow.assign_widths_inline(&ctx);
+
+        assert flow.inline().lines.len() == 1;
+        let boxes = flow.inline().boxes.get();
+        match boxes.last() {
+            @TextBox(_, ref d) => assert d.run.text == ~"\u2026",
+            _ => fail ~"expected an ellipsis box"
+        }
+        match boxes[0] {
+            @TextBox(_, ref d) => assert d.range.length() < run.char_len(),
+            _ => fail ~"expected the start of the word"
+        }
+        let right = boxes.last().d().position.origin.x + boxes.last().d().position.size.width;
+        assert right <= Au::from_px(50);
+    }
+}
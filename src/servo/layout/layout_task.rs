@@ -9,8 +9,9 @@ use dom::event::{Event, ReflowEvent};
 use dom::node::{Node, LayoutData};
 use layout::box::RenderBox;
 use layout::box_builder::LayoutTreeBuilder;
-use layout::context::LayoutContext;
+use layout::context::{LayoutContext, ReflowCache};
 use layout::display_list_builder::DisplayListBuilder;
+use layout::inline::{LineBreaker, SpaceLineBreaker};
 use layout::traverse::*;
 use resource::image_cache_task::{ImageCacheTask, ImageResponseMsg};
 use resource::local_image_cache::LocalImageCache;
@@ -36,8 +37,10 @@ use newcss::stylesheet::Stylesheet;
 use newcss::types::OriginAuthor;
 use opt = core::option;
 use std::arc::ARC;
+use std::arc;
 use std::cell::Cell;
 use std::net::url::Url;
+use std::time::precise_time_ns;
 
 pub type LayoutTask = oldcomm::Chan<Msg>;
 
@@ -83,6 +86,7 @@ pub struct BuildData {
     window_size: Size2D<uint>,
     content_join_chan: pipes::Chan<()>,
     damage: Damage,
+    cancel_token: CancelToken,
 }
 
 pub fn LayoutTask(render_task: RenderTask,
@@ -93,6 +97,132 @@ pub fn LayoutTask(render_task: RenderTask,
     }
 }
 
+/**
+Performs the layout passes over a constructed flow tree. The token is
+checked between subtrees; once it is cancelled, no further flows are
+visited and the tree is left partially laid out.
+*/
+pub fn reflow(layout_root: @FlowContext, ctx: &LayoutContext,
+              token: &CancelToken) -> Result<(), LayoutAborted> {
+    match reflow_with_stats(layout_root, ctx, token) {
+        Ok(_) => Ok(()),
+        Err(move e) => Err(move e)
+    }
+}
+
+/**
+Like `reflow`, but also builds the display list of the laid out tree. If
+the tree and viewport are the same as those of the last call with the
+same cache, the layout passes are skipped and the cached list returned;
+the nodes of the tree are then pointed at the cached, laid out flows.
+*/
+pub fn reflow_cached(layout_root: @FlowContext, ctx: &LayoutContext,
+                     token: &CancelToken) -> Result<ARC<DisplayList>, LayoutAborted> {
+    match reflow_cached_with_stats(layout_root, ctx, token) {
+        Ok((move list, _)) => Ok(move list),
+        Err(move e) => Err(move e)
+    }
+}
+
+/// Like `reflow_cached`, but measures the passes. There are no stats for a cache hit.
+pub fn reflow_cached_with_stats(layout_root: @FlowContext, ctx: &LayoutContext, token: &CancelToken)
+                             -> Result<(ARC<DisplayList>, Option<ReflowStats>), LayoutAborted> {
+    let hash = layout_root.content_hash(ctx);
+    match ctx.reflow_cache.get(hash, &ctx.screen_size) {
+        Some((cached_root, move list)) => {
+            adopt_cached_layout(cached_root, layout_root);
+            return Ok((move list, None));
+        }
+        None => {}
+    }
+
+    let stats = match reflow_with_stats(layout_root, ctx, token) {
+        Ok(move stats) => move stats,
+        Err(move e) => return Err(move e)
+    };
+    let display_list_start = precise_time_ns();
+    let builder = DisplayListBuilder {
+        ctx: ctx,
+    };
+    let mut list = DisplayList::new();
+    layout_root.build_display_list(&builder, &copy layout_root.d().position, &mut list);
+    let list = ARC(move list);
+    stats.display_list_ns = precise_time_ns() - display_list_start;
+    ctx.reflow_cache.put(hash, &ctx.screen_size, layout_root, &list);
+    Ok((move list, Some(move stats)))
+}
+
+/* Points the nodes that map to flows of `fresh` at the matching flows
+   of `cached`. The trees have the same content hash, so they have the
+   same shape. */
+priv fn adopt_cached_layout(cached: @FlowContext, fresh: @FlowContext) {
+    do (copy fresh.d().node).iter |node| {
+        if node.has_aux() {
+            do node.aux |data| {
+                match data.flow {
+                    Some(flow) if core::managed::ptr_eq(flow, fresh) => data.flow = Some(cached),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let mut cached_children = ~[];
+    for FlowTree.each_child(cached) |child| {
+        cached_children.push(child);
+    }
+    let mut i = 0;
+    for FlowTree.each_child(fresh) |child| {
+        adopt_cached_layout(cached_children[i], child);
+        i += 1;
+    }
+}
+
+/// Time spent in each layout pass, in nanoseconds, and the size of the laid out tree.
+pub struct ReflowStats {
+    mut bubble_widths_ns: u64,
+    mut assign_widths_ns: u64,
+    mut assign_height_ns: u64,
+    // not measured by reflow_with_stats; filled in by the display list builder's caller
+    mut display_list_ns: u64,
+    mut flow_count: uint,
+    mut box_count: uint,
+}
+
+/// Like `reflow`, but measures each pass.
+pub fn reflow_with_stats(layout_root: @FlowContext, ctx: &LayoutContext,
+                         token: &CancelToken) -> Result<ReflowStats, LayoutAborted> {
+    let mut stats = ReflowStats {
+        bubble_widths_ns: 0,
+        assign_widths_ns: 0,
+        assign_height_ns: 0,
+        display_list_ns: 0,
+        flow_count: 0,
+        box_count: 0,
+    };
+
+    let start = precise_time_ns();
+    let result = do layout_root.traverse_postorder_cancellable(token) |f| {
+        stats.flow_count += 1;
+        stats.box_count += f.box_count();
+        f.bubble_widths(ctx)
+    };
+    if result::is_err(&result) { return Err(LayoutAborted); }
+    let widths_start = precise_time_ns();
+    stats.bubble_widths_ns = widths_start - start;
+
+    let result = do layout_root.traverse_preorder_cancellable(token) |f| { f.assign_widths(ctx) };
+    if result::is_err(&result) { return Err(LayoutAborted); }
+    let height_start = precise_time_ns();
+    stats.assign_widths_ns = height_start - widths_start;
+
+    let result = do layout_root.traverse_postorder_cancellable(token) |f| { f.assign_height(ctx) };
+    if result::is_err(&result) { return Err(LayoutAborted); }
+    stats.assign_height_ns = precise_time_ns() - height_start;
+
+    Ok(move stats)
+}
+
 struct Layout {
     render_task: RenderTask,
     image_cache_task: ImageCacheTask,
@@ -103,6 +233,7 @@ struct Layout {
     // This is used to root auxilliary RCU reader data
     layout_refs: DVec<@LayoutData>,
     css_select_ctx: Mut<SelectCtx>,
+    reflow_cache: @ReflowCache,
 }
 
 fn Layout(render_task: RenderTask, 
@@ -119,7 +250,8 @@ fn Layout(render_task: RenderTask,
         from_content: from_content,
         font_ctx: fctx,
         layout_refs: DVec(),
-        css_select_ctx: Mut(new_css_select_ctx())
+        css_select_ctx: Mut(new_css_select_ctx()),
+        reflow_cache: @ReflowCache()
     }
 }
 
@@ -188,7 +320,11 @@ impl Layout {
             image_cache: self.local_image_cache,
             font_ctx: self.font_ctx,
             doc_url: move doc_url,
-            screen_size: Rect(Point2D(Au(0), Au(0)), screen_size)
+            screen_size: Rect(Point2D(Au(0), Au(0)), screen_size),
+            line_breaker: @SpaceLineBreaker as @LineBreaker,
+            reflow_cache: self.reflow_cache,
+            tab_size: 8,
+            zoom: 1f
         };
 
         do time("layout: aux initialization") {
@@ -198,8 +334,10 @@ impl Layout {
 
         // Perform CSS selector matching if necessary.
         match data.damage {
-            NoDamage | ReflowDamage => {}
+            NoDamage => {}
+            ReflowDamage => self.reflow_cache.mark_dirty(),
             MatchSelectorsDamage => {
+                self.reflow_cache.mark_dirty();
                 do time("layout: selector matching") {
                     do self.css_select_ctx.borrow_imm |ctx| {
                         node.restyle_subtree(ctx);
@@ -222,29 +360,29 @@ impl Layout {
             layout_root
         };
 
-        do time("layout: main layout") {
-            /* perform layout passes over the flow tree */
-            do layout_root.traverse_postorder |f| { f.bubble_widths(&layout_ctx) }
-            do layout_root.traverse_preorder  |f| { f.assign_widths(&layout_ctx) }
-            do layout_root.traverse_postorder |f| { f.assign_height(&layout_ctx) }
-        }
-
-        do time("layout: display list building") {
-            let builder = DisplayListBuilder {
-                ctx: &layout_ctx,
-            };
-            let mut render_layer = RenderLayer {
-                display_list: DisplayList::new(),
-                size: Size2D(screen_size.width.to_px() as uint,
-                             screen_size.height.to_px() as uint)
-            };
+        let reflow_result = do time("layout: main layout") {
+            reflow_cached_with_stats(layout_root, &layout_ctx, &data.cancel_token)
+        };
 
-            // TODO: set options on the builder before building
-            // TODO: be smarter about what needs painting
-            layout_root.build_display_list(&builder, &copy layout_root.d().position,
-                                           &mut render_layer.display_list);
-            self.render_task.send(RenderMsg(move render_layer));
-        } // time(layout: display list building)
+        match move reflow_result {
+            Ok((move display_list, move stats)) => {
+                match stats {
+                    Some(stats) => debug!("layout: reflow stats: %?", stats),
+                    None => debug!("layout: tree is unchanged; reusing the display list")
+                }
+                // TODO: set options on the builder before building
+                // TODO: be smarter about what needs painting
+                let render_layer = RenderLayer {
+                    display_list: move display_list,
+                    size: Size2D(screen_size.width.to_px() as uint,
+                                 screen_size.height.to_px() as uint)
+                };
+                self.render_task.send(RenderMsg(move render_layer));
+            }
+            Err(LayoutAborted) => {
+                debug!("layout: reflow was cancelled; not building a display list");
+            }
+        }
 
         // Tell content we're done
         data.content_join_chan.send(());
@@ -305,3 +443,73 @@ impl Layout {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use layout::context::test_layout_context;
+    use layout::test_builder;
+    use newcss::values::CSSTextAlignRight;
+
+    #[test]
+    fn reflow_stats_count_flows_and_boxes() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let builder = test_builder::root().block().inline_boxes(3);
+        let root = builder.build();
+
+        match reflow_with_stats(root, &ctx, &CancelToken::new()) {
+            Ok(stats) => {
+                assert stats.flow_count == 3;
+                assert stats.box_count == 3;
+                assert stats.display_list_ns == 0;
+            }
+            Err(*) => fail ~"reflow wasn't cancelled"
+        }
+    }
+
+    #[test]
+    fn reflow_of_unchanged_tree_hits_cache() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let builder = test_builder::root().block().inline_boxes(2);
+        let root = builder.build();
+
+        let first = reflow_cached(root, &ctx, &CancelToken::new()).get();
+        assert ctx.reflow_cache.skipped == 0;
+        let second = reflow_cached(root, &ctx, &CancelToken::new()).get();
+        assert ctx.reflow_cache.skipped == 1;
+        assert ptr::ref_eq(arc::get(&first), arc::get(&second));
+
+        ctx.reflow_cache.mark_dirty();
+        reflow_cached(root, &ctx, &CancelToken::new());
+        assert ctx.reflow_cache.skipped == 1;
+    }
+
+    #[test]
+    fn reflow_after_layout_input_changes_misses_cache() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let builder = test_builder::root().block().inline_boxes(2);
+        let inline = builder.last_flow();
+        let root = builder.build();
+
+        reflow_cached(root, &ctx, &CancelToken::new());
+        inline.inline().text_align = Some(CSSTextAlignRight);
+        reflow_cached(root, &ctx, &CancelToken::new());
+        assert ctx.reflow_cache.skipped == 0;
+
+        // so do the context's layout parameters
+        let zoomed = LayoutContext { zoom: 2f, .. ctx };
+        reflow_cached(root, &zoomed, &CancelToken::new());
+        assert zoomed.reflow_cache.skipped == 0;
+        reflow_cached(root, &zoomed, &CancelToken::new());
+        assert zoomed.reflow_cache.skipped == 1;
+    }
+
+    #[test]
+    fn reflow_in_empty_viewport_paints_nothing() {
+        let ctx = test_layout_context(Size2D(Au(0), Au(0)));
+        let builder = test_builder::root().block().inline_boxes(2);
+        let root = builder.build();
+
+        let list = reflow_cached(root, &ctx, &CancelToken::new()).get();
+        assert arc::get(&list).list.is_empty();
+        assert root.d().position.size == Size2D(Au(0), Au(0));
+    }
+}
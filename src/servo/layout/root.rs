@@ -2,8 +2,10 @@ use au = gfx::geometry;
 use newcss::values::*;
 use geom::point::Point2D;
 use geom::rect::Rect;
+use geom::size::Size2D;
 use gfx::display_list::DisplayList;
 use gfx::geometry::Au;
+use layout::block::stack_child_flows;
 use layout::box::RenderBox;
 use layout::context::LayoutContext;
 use layout::flow::{FlowContext, FlowTree, InlineBlockFlow, BlockFlow, RootFlow};
@@ -11,12 +13,16 @@ use layout::display_list_builder::DisplayListBuilder;
 use util::tree;
 
 pub struct RootFlowData {
-    mut box: Option<@RenderBox>
+    mut box: Option<@RenderBox>,
+    // the viewport that the root flow covers. If None, the
+    // LayoutContext's screen size is used.
+    mut viewport: Option<Rect<Au>>
 }
 
 pub fn RootFlowData() -> RootFlowData {
     RootFlowData {
-        box: None
+        box: None,
+        viewport: None
     }
 }
 
@@ -28,6 +34,7 @@ trait RootLayout {
     fn assign_height_root(@self, ctx: &LayoutContext);
     fn build_display_list_root(@self, a: &DisplayListBuilder, b: &Rect<Au>,
                                c: &Point2D<Au>, d: &mut DisplayList);
+    fn scrollable_size(@self) -> Size2D<Au>;
 }
 
 impl FlowContext : RootLayout {
@@ -48,8 +55,13 @@ impl FlowContext : RootLayout {
     fn assign_widths_root(@self, ctx: &LayoutContext) { 
         assert self.starts_root_flow();
 
+        let viewport = self.root().viewport.get_default(ctx.screen_size);
         self.d().position.origin = Au::zero_point();
-        self.d().position.size.width = ctx.screen_size.size.width;
+        self.d().position.size.width = viewport.size.width;
+        // e.g. a minimized window: there's nothing to lay out, or to paint
+        if is_empty_viewport(&viewport) {
+            return;
+        }
 
         self.assign_widths_block(ctx)
     }
@@ -57,20 +69,21 @@ impl FlowContext : RootLayout {
     fn assign_height_root(@self, ctx: &LayoutContext) {
         assert self.starts_root_flow();
 
+        let viewport = self.root().viewport.get_default(ctx.screen_size);
+        if is_empty_viewport(&viewport) {
+            self.d().position.size.height = Au(0);
+            return;
+        }
+
         // this is essentially the same as assign_height_block(), except
         // the root adjusts self height to at least cover the viewport.
-        let mut cur_y = Au(0);
+        let cur_y = stack_child_flows(self, ctx.zoom);
 
-        for FlowTree.each_child(self) |child_ctx| {
-            child_ctx.d().position.origin.y = cur_y;
-            cur_y += child_ctx.d().position.size.height;
-        }
-
-        self.d().position.size.height = Au::max(ctx.screen_size.size.height, cur_y);
+        self.d().position.size.height = Au::max(viewport.size.height, cur_y);
 
         do self.with_block_box |box| {
             box.d().position.origin.y = Au(0);
-            box.d().position.size.height = Au::max(ctx.screen_size.size.height, cur_y);
+            box.d().position.size.height = Au::max(viewport.size.height, cur_y);
             let (_used_top, _used_bot) = box.get_used_height();
         }
     }
@@ -79,6 +92,86 @@ impl FlowContext : RootLayout {
                                offset: &Point2D<Au>, list: &mut DisplayList) {
         assert self.starts_root_flow();
 
+        // the children weren't laid out in an empty viewport
+        let size = self.d().position.size;
+        if size.width == Au(0) || size.height == Au(0) {
+            return;
+        }
         self.build_display_list_block(builder, dirty, offset, list);
     }
+
+    /* The size of the document that can be scrolled to: everything from
+       the root's origin to the far edges of the furthest descendant. This
+       may exceed the viewport. Only meaningful after layout. */
+    fn scrollable_size(@self) -> Size2D<Au> {
+        assert self.starts_root_flow();
+
+        let rect = descendant_overflow_rect(self);
+        Size2D(rect.origin.x + rect.size.width, rect.origin.y + rect.size.height)
+    }
+}
+
+/* Whether a viewport has no area. */
+priv fn is_empty_viewport(viewport: &Rect<Au>) -> bool {
+    viewport.size.width <= Au(0) || viewport.size.height <= Au(0)
+}
+
+/* Like `overflow_rect`, but also covers the child flows' own overflow,
+   all the way down. Relative to `flow`'s origin. */
+priv fn descendant_overflow_rect(flow: @FlowContext) -> Rect<Au> {
+    let mut rect = Rect(Au::zero_point(), copy flow.d().position.size);
+    for FlowTree.each_child(flow) |child| {
+        let child_rect = descendant_overflow_rect(child).translate(&child.d().position.origin);
+        rect = rect.union(&child_rect);
+    }
+    rect
+}
+
+#[cfg(test)]
+mod test {
+    use layout::context::test_layout_context;
+    use layout::flow::FixedHeight;
+    use layout::test_builder;
+    use layout::traverse::*;
+
+    fn viewport(width: int, height: int) -> Rect<Au> {
+        Rect(Point2D(Au(0), Au(0)), Size2D(Au::from_px(width), Au::from_px(height)))
+    }
+
+    #[test]
+    fn root_width_follows_explicit_viewport() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().build();
+        let block = tree::first_child(&FlowTree, &root).get();
+
+        root.root().viewport = Some(viewport(1024, 768));
+        root.assign_widths_root(&ctx);
+        assert root.d().position.size.width == Au::from_px(1024);
+        assert block.d().position.size.width == Au::from_px(1024);
+
+        root.root().viewport = Some(viewport(320, 480));
+        root.assign_widths_root(&ctx);
+        assert root.d().position.size.width == Au::from_px(320);
+        assert block.d().position.size.width == Au::from_px(320);
+    }
+
+    #[test]
+    fn scrollable_size_covers_content_taller_than_viewport() {
+        let ctx = test_layout_context(Size2D(Au::from_px(800), Au::from_px(600)));
+        let root = test_builder::root().block().block().up().build();
+        let outer = tree::first_child(&FlowTree, &root).get();
+        let inner = tree::first_child(&FlowTree, &outer).get();
+        // the inner block spills out of its shorter parent
+        outer.d().height = FixedHeight(Au::from_px(100));
+        inner.d().height = FixedHeight(Au::from_px(900));
+
+        do root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        assert root.d().position.size.height == Au::from_px(600);
+        let size = root.scrollable_size();
+        assert size.height == Au::from_px(900);
+        assert size.width == Au::from_px(800);
+    }
 }
@@ -0,0 +1,129 @@
+/** Column widths of tables (CSS 2.1, Section 17.5.2).
+
+    Table flows don't have row and cell flows yet, so the algorithms here
+    work on the widths that were measured for each cell, row by row. */
+
+use gfx::geometry::Au;
+
+/* How the widths of a table's columns are found ('table-layout'). */
+pub enum TableLayout {
+    // from the content of every cell
+    AutoLayout,
+    // from the first row alone, without looking at the content of the rest
+    FixedLayout
+}
+
+/* The widths of one cell's content, and its declared width, if any. */
+pub struct CellWidths {
+    min_width: Au,
+    pref_width: Au,
+    declared_width: Option<Au>
+}
+
+/* The width of each column of a table that is `table_width` wide, with
+   the cells of `rows`. */
+pub fn column_widths(layout: TableLayout, table_width: Au, rows: &[~[CellWidths]]) -> ~[Au] {
+    if rows.is_empty() {
+        return ~[];
+    }
+    match layout {
+        AutoLayout => auto_column_widths(table_width, rows),
+        FixedLayout => fixed_column_widths(table_width, rows[0])
+    }
+}
+
+/* The fixed table layout algorithm (17.5.2.1): columns with a declared
+   width in the first row get it, and the others share what is left
+   equally. Later rows are never looked at. */
+pub fn fixed_column_widths(table_width: Au, first_row: &[CellWidths]) -> ~[Au] {
+    let mut declared_total = Au(0);
+    let mut auto_count = 0;
+    for first_row.each |cell| {
+        match cell.declared_width {
+            Some(width) => declared_total += width,
+            None => auto_count += 1
+        }
+    }
+
+    let remaining = Au::max(table_width - declared_total, Au(0));
+    let auto_width = if auto_count == 0 { Au(0) } else { Au(*remaining / (auto_count as i32)) };
+    do first_row.map |cell| {
+        cell.declared_width.get_default(auto_width)
+    }
+}
+
+/* The automatic table layout algorithm (17.5.2.2): each column is as wide
+   as the widest content of its cells, if there is room for that, and the
+   space beyond the columns' preferred widths is shared equally. When there
+   isn't room, each column gets its minimum width and a share of the rest
+   in proportion to how much more it would like. */
+pub fn auto_column_widths(table_width: Au, rows: &[~[CellWidths]]) -> ~[Au] {
+    let mut min_widths: ~[Au] = ~[];
+    let mut pref_widths: ~[Au] = ~[];
+    for rows.each |row| {
+        for row.eachi |i, cell| {
+            let (min, pref) = match cell.declared_width {
+                Some(width) => (Au::max(width, cell.min_width), Au::max(width, cell.min_width)),
+                None => (cell.min_width, cell.pref_width)
+            };
+            if i < min_widths.len() {
+                min_widths[i] = Au::max(min_widths[i], min);
+                pref_widths[i] = Au::max(pref_widths[i], pref);
+            } else {
+                min_widths.push(min);
+                pref_widths.push(pref);
+            }
+        }
+    }
+
+    if min_widths.is_empty() {
+        return ~[];
+    }
+    let min_total = min_widths.foldl(Au(0), |a, b| *a + *b);
+    let pref_total = pref_widths.foldl(Au(0), |a, b| *a + *b);
+    let column_count = min_widths.len() as i32;
+    if table_width >= pref_total {
+        let extra = Au(*(table_width - pref_total) / column_count);
+        do pref_widths.map |pref| { *pref + extra }
+    } else if table_width > min_total {
+        let ratio = (*(table_width - min_total) as float) / (*(pref_total - min_total) as float);
+        do vec::from_fn(min_widths.len()) |i| {
+            min_widths[i] + (pref_widths[i] - min_widths[i]).scale_by(ratio)
+        }
+    } else {
+        move min_widths
+    }
+}
+
+#[cfg(test)]
+mod test {
+    fn cell(pref: int) -> CellWidths {
+        CellWidths { min_width: Au::from_px(pref / 2), pref_width: Au::from_px(pref), declared_width: None }
+    }
+
+    // narrow cells in the first row, and wide content in the first column later
+    fn rows() -> ~[~[CellWidths]] {
+        ~[~[cell(50), cell(50)],
+          ~[cell(300), cell(50)]]
+    }
+
+    #[test]
+    fn fixed_layout_ignores_content_of_later_rows() {
+        let widths = column_widths(FixedLayout, Au::from_px(400), rows());
+        assert widths == ~[Au::from_px(200), Au::from_px(200)];
+    }
+
+    #[test]
+    fn auto_layout_widens_columns_for_content() {
+        let widths = column_widths(AutoLayout, Au::from_px(400), rows());
+        assert widths == ~[Au::from_px(325), Au::from_px(75)];
+    }
+
+    #[test]
+    fn fixed_layout_shares_what_declared_columns_leave() {
+        let declared = CellWidths { declared_width: Some(Au::from_px(100)), .. cell(50) };
+        let first_row = ~[declared, cell(50), cell(50)];
+        let widths = fixed_column_widths(Au::from_px(400), first_row);
+        assert widths == ~[Au::from_px(100), Au::from_px(150), Au::from_px(150)];
+    }
+}
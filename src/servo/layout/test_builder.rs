@@ -0,0 +1,140 @@
+/**
+A convenience API for building flow trees in tests, without wiring up
+`FlowData`, variant data and tree links by hand. For example,
+
+    let flow = root().block().inline_boxes(2).build();
+
+builds a root flow containing a block flow, which in turn contains an
+inline flow of two generic boxes. Flow and box ids are assigned in
+creation order, starting from zero.
+*/
+
+use dom::node::{Node, NodeScope, Text};
+use layout::block::BlockFlowData;
+use layout::box::{GenericBox, RenderBoxData};
+use layout::flow::{BlockFlow, FlowContext, FlowData, FlowTree, InlineFlow, RootFlow};
+use layout::inline::InlineFlowData;
+use layout::root::RootFlowData;
+use util::tree;
+
+pub struct FlowTreeBuilder {
+    priv root: @FlowContext,
+    // the flow that new flows are added to as children
+    priv mut cursor: @FlowContext,
+    // the most recently created flow
+    priv mut last: @FlowContext,
+    priv mut next_flow_id: int,
+    priv mut next_box_id: int,
+    priv scope: NodeScope,
+}
+
+/// Starts building a tree with a root flow.
+pub fn root() -> @FlowTreeBuilder {
+    let root = @RootFlow(FlowData(0), RootFlowData());
+    @FlowTreeBuilder {
+        root: root,
+        cursor: root,
+        last: root,
+        next_flow_id: 1,
+        next_box_id: 0,
+        scope: NodeScope(),
+    }
+}
+
+impl FlowTreeBuilder {
+    /// Adds a block flow as a child of the current flow, and continues
+    /// building inside it.
+    fn block(@self) -> @FlowTreeBuilder {
+        let flow = @BlockFlow(FlowData(self.flow_id()), BlockFlowData());
+        self.attach(flow);
+        self.cursor = flow;
+        self
+    }
+
+    /// Adds an inline flow holding `n` generic boxes as a child of the
+    /// current flow. Building continues in the current flow.
+    fn inline_boxes(@self, n: uint) -> @FlowTreeBuilder {
+        let flow = @InlineFlow(FlowData(self.flow_id()), InlineFlowData());
+        for uint::range(0, n) |_i| {
+            let box = @GenericBox(RenderBoxData(self.new_node(), flow, self.box_id()));
+            flow.inline().boxes.push(box);
+        }
+        self.attach(flow);
+        self
+    }
+
+    /// Continues building in the parent of the current flow.
+    fn up(@self) -> @FlowTreeBuilder {
+        match tree::parent(&FlowTree, &self.cursor) {
+            Some(parent) => self.cursor = parent,
+            None => fail ~"FlowTreeBuilder: can't go up from the root flow"
+        }
+        self
+    }
+
+    /// The most recently created flow.
+    fn last_flow(@self) -> @FlowContext {
+        self.last
+    }
+
+    /// Finishes building, and returns the root of the tree.
+    fn build(@self) -> @FlowContext {
+        self.root
+    }
+
+    /// A fresh DOM node, for flows and boxes that need one.
+    fn new_node(@self) -> Node {
+        self.scope.new_node(Text(~"test"))
+    }
+
+    priv fn attach(@self, flow: @FlowContext) {
+        FlowTree.add_child(self.cursor, flow);
+        self.last = flow;
+    }
+
+    priv fn flow_id(@self) -> int {
+        let id = self.next_flow_id;
+        self.next_flow_id += 1;
+        id
+    }
+
+    priv fn box_id(@self) -> int {
+        let id = self.next_box_id;
+        self.next_box_id += 1;
+        id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use layout::debug::BoxedDebugMethods;
+
+    #[test]
+    fn builds_three_level_tree() {
+        let root = root().block().inline_boxes(2).build();
+        root.dump();
+
+        assert root.starts_root_flow();
+        let block = tree::first_child(&FlowTree, &root).get();
+        assert block.starts_block_flow();
+        assert block.d().id == 1;
+
+        let inline = tree::first_child(&FlowTree, &block).get();
+        assert inline.starts_inline_flow();
+        assert inline.d().id == 2;
+        assert inline.inline().boxes.len() == 2;
+        assert inline.inline().boxes[1].d().id == 1;
+        assert tree::is_leaf(&FlowTree, &inline);
+    }
+
+    #[test]
+    fn up_returns_to_parent_flow() {
+        let root = root().block().up().block().build();
+        let mut count = 0;
+        for FlowTree.each_child(root) |child| {
+            assert child.starts_block_flow();
+            count += 1;
+        }
+        assert count == 2;
+    }
+}
@@ -1,19 +1,136 @@
 use layout::flow::{FlowContext, FlowTree};
+use util::tree;
+
+use std::arc::MutexARC;
 
 /** Trait for running tree-based traversals over layout contexts */
 trait FlowContextTraversals {
     fn traverse_preorder(preorder_cb: &fn(@FlowContext));
     fn traverse_postorder(postorder_cb: &fn(@FlowContext));
+    fn traverse_preorder_cancellable(token: &CancelToken,
+                                     preorder_cb: &fn(@FlowContext)) -> Result<(), LayoutAborted>;
+    fn traverse_postorder_cancellable(token: &CancelToken,
+                                      postorder_cb: &fn(@FlowContext)) -> Result<(), LayoutAborted>;
 }
 
 impl @FlowContext : FlowContextTraversals {
     fn traverse_preorder(preorder_cb: &fn(@FlowContext)) {
-        preorder_cb(self);
-        do FlowTree.each_child(self) |child| { child.traverse_preorder(preorder_cb); true }
+        do tree::dfs(&FlowTree, &self) |flow| { preorder_cb(*flow); true };
     }
 
     fn traverse_postorder(postorder_cb: &fn(@FlowContext)) {
-        do FlowTree.each_child(self) |child| { child.traverse_postorder(postorder_cb); true }
-        postorder_cb(self);
+        do tree::dfs_postorder(&FlowTree, &self) |flow| { postorder_cb(*flow); true };
+    }
+
+    /* Like traverse_preorder, but checks the token before visiting each
+       flow, and stops visiting flows once it has been cancelled. */
+    fn traverse_preorder_cancellable(token: &CancelToken,
+                                     preorder_cb: &fn(@FlowContext)) -> Result<(), LayoutAborted> {
+        let finished = do tree::dfs(&FlowTree, &self) |flow| {
+            if token.is_cancelled() { false } else { preorder_cb(*flow); true }
+        };
+        if finished { Ok(()) } else { Err(LayoutAborted) }
+    }
+
+    fn traverse_postorder_cancellable(token: &CancelToken,
+                                      postorder_cb: &fn(@FlowContext)) -> Result<(), LayoutAborted> {
+        let finished = do tree::dfs_postorder(&FlowTree, &self) |flow| {
+            if token.is_cancelled() { false } else { postorder_cb(*flow); true }
+        };
+        if finished { Ok(()) } else { Err(LayoutAborted) }
+    }
+}
+
+/// The result of a traversal that was stopped by its `CancelToken`.
+pub enum LayoutAborted { LayoutAborted }
+
+/**
+A flag used to abort a long-running reflow. Clones of a token share the
+same flag, so another task can hold a clone and trip it while layout is
+traversing the flow tree.
+*/
+pub struct CancelToken {
+    priv flag: MutexARC<bool>
+}
+
+pub impl CancelToken {
+    static fn new() -> CancelToken {
+        CancelToken { flag: MutexARC(false) }
+    }
+
+    fn clone(&self) -> CancelToken {
+        CancelToken { flag: self.flag.clone() }
+    }
+
+    fn cancel(&self) {
+        unsafe { do self.flag.access |cancelled| { *cancelled = true; } }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        unsafe { do self.flag.access |cancelled| { *cancelled } }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use layout::block::BlockFlowData;
+    use layout::flow::{BlockFlow, FlowData};
+
+    fn flow_with_children(n: uint) -> @FlowContext {
+        let root = @BlockFlow(FlowData(0), BlockFlowData());
+        for uint::range(0, n) |i| {
+            FlowTree.add_child(root, @BlockFlow(FlowData((i + 1) as int), BlockFlowData()));
+        }
+        root
+    }
+
+    // Reference order: the hand-written recursion that the traversals used to use.
+    fn recursive_preorder_ids(flow: @FlowContext, ids: &mut ~[int]) {
+        ids.push(flow.d().id);
+        for FlowTree.each_child(flow) |child| { recursive_preorder_ids(child, ids); }
+    }
+
+    #[test]
+    fn dfs_matches_recursive_preorder() {
+        let root = flow_with_children(2);
+        let first = tree::first_child(&FlowTree, &root).get();
+        FlowTree.add_child(first, @BlockFlow(FlowData(3), BlockFlowData()));
+        FlowTree.add_child(first, @BlockFlow(FlowData(4), BlockFlowData()));
+
+        let mut expected = ~[];
+        recursive_preorder_ids(root, &mut expected);
+
+        let mut ids = ~[];
+        do root.traverse_preorder |f| { ids.push(f.d().id); }
+        assert ids == expected;
+        assert ids == ~[0, 1, 3, 4, 2];
+
+        let mut post_ids = ~[];
+        do root.traverse_postorder |f| { post_ids.push(f.d().id); }
+        assert post_ids == ~[3, 4, 1, 2, 0];
+    }
+
+    #[test]
+    fn cancellable_traversal_visits_all_flows() {
+        let root = flow_with_children(3);
+        let token = CancelToken::new();
+        let mut visited = 0;
+        let result = do root.traverse_preorder_cancellable(&token) |_f| { visited += 1; };
+        assert result::is_ok(&result);
+        assert visited == 4;
+    }
+
+    #[test]
+    fn cancelling_mid_traversal_stops_visits() {
+        let root = flow_with_children(3);
+        let token = CancelToken::new();
+        let remote_token = token.clone();
+        let mut visited = 0;
+        let result = do root.traverse_preorder_cancellable(&token) |_f| {
+            visited += 1;
+            if visited == 2 { remote_token.cancel(); }
+        };
+        assert result::is_err(&result);
+        assert visited == 2;
     }
 }
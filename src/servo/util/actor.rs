@@ -3,7 +3,10 @@ An actor type
 */
 
 use pipes::{Port, Chan, SharedChan, stream};
+use core::either::{Left, Right};
 use core::task;
+use std::arc::MutexARC;
+use std::cell::Cell;
 
 /**
 The client reference to an actor
@@ -14,12 +17,285 @@ actor's interface.
 */
 struct ActorRef<M: Owned> {
     chan: Chan<M>,
+    priv mailbox: MutexARC<Mailbox>,
 }
 
 impl<M: Owned> ActorRef<M> {
     fn send(&self, msg: M) {
+        trace_send(&msg);
+        unsafe { do self.mailbox.access |mailbox| { mailbox.queued += 1; } }
         self.chan.send(move msg);
     }
+
+    /**
+    Sends a message if the actor's mailbox has room for it, or returns it
+    without blocking otherwise. Only actors spawned by `spawn_bounded`
+    have a mailbox that can be full.
+    */
+    fn try_send(&self, msg: M) -> Result<(), M> {
+        let accepted = unsafe {
+            do self.mailbox.access |mailbox| {
+                if mailbox.queued < mailbox.capacity {
+                    mailbox.queued += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if !accepted {
+            return Err(move msg);
+        }
+        trace_send(&msg);
+        self.chan.send(move msg);
+        Ok(())
+    }
+
+    /**
+    Sends a message if the actor's task is still receiving messages, or
+    returns it otherwise. A message that is sent was queued to a live
+    actor, but the actor may still stop before it handles it.
+    */
+    fn send_confirmed(&self, msg: M) -> Result<(), M> {
+        let mut msg = Some(move msg);
+        unsafe {
+            // the actor is marked as dead before its port is closed, so the
+            // send can't fail while it is alive
+            do self.mailbox.access |mailbox| {
+                if mailbox.alive {
+                    mailbox.queued += 1;
+                    let msg = option::swap_unwrap(&mut msg);
+                    trace_send(&msg);
+                    self.chan.send(move msg);
+                }
+            }
+        }
+        match move msg {
+            Some(move msg) => Err(move msg),
+            None => Ok(())
+        }
+    }
+
+    /**
+    The number of messages sent to the actor, through this reference or a
+    `SharedActorRef` made from it, that the actor hasn't finished handling,
+    including the one it is handling now.
+    */
+    fn queue_len(&self) -> uint {
+        unsafe { do self.mailbox.access |mailbox| { mailbox.queued } }
+    }
+
+    /// Whether the actor's task is still receiving messages.
+    fn is_alive(&self) -> bool {
+        unsafe { do self.mailbox.access |mailbox| { mailbox.alive } }
+    }
+}
+
+impl<M: Owned> ActorRef<M> {
+    /**
+    Sends a message made by `make_msg`, which carries the channel that the
+    actor should reply on, and returns a future for the reply instead of
+    waiting for it.
+    */
+    fn ask_async<R: Owned>(&self, make_msg: fn(Chan<R>) -> M) -> Future<R> {
+        let (port, chan) = stream();
+        self.send(make_msg(move chan));
+        Future { port: move port }
+    }
+
+    /**
+    Like `ask_async`, but waits for the reply. In debug builds, an actor
+    that asks an actor that is waiting, through a chain of asks, on the
+    asker fails instead of waiting forever.
+    */
+    fn ask<R: Owned>(&self, make_msg: fn(Chan<R>) -> M) -> R {
+        start_waiting_on(&self.mailbox);
+        let reply = self.ask_async(make_msg).get();
+        stop_waiting();
+        move reply
+    }
+}
+
+/// The reply to a message sent by `ask_async`
+struct Future<R: Owned> {
+    priv port: Port<R>
+}
+
+impl<R: Owned> Future<R> {
+    /// Waits for the reply.
+    fn get(self) -> R {
+        self.port.recv()
+    }
+
+    /**
+    Returns the reply if it has arrived, without waiting for it. Once this
+    has returned the reply, the future is spent.
+    */
+    fn poll(&self) -> Option<R> {
+        if self.port.peek() {
+            Some(self.port.recv())
+        } else {
+            None
+        }
+    }
+}
+
+fn current_mailbox_key(_mailbox: @MutexARC<Mailbox>) {}
+
+/// Makes the current task known as the actor with the given mailbox, to `ask`.
+#[cfg(debug)]
+fn enter_actor(mailbox: &MutexARC<Mailbox>) {
+    unsafe { task::local_data::local_data_set(current_mailbox_key, @mailbox.clone()); }
+}
+
+#[cfg(ndebug)]
+fn enter_actor(_mailbox: &MutexARC<Mailbox>) {}
+
+/**
+Records that the current task's actor waits on the actor of `target`.
+
+The edge is recorded before the graph is searched for a cycle through it,
+so that of two actors asking each other at the same time, the one that
+records its edge last finds the other's.
+*/
+#[cfg(debug)]
+fn start_waiting_on(target: &MutexARC<Mailbox>) {
+    // tasks that aren't actors can't be asked, so they can't be in a cycle
+    let own = unsafe { task::local_data::local_data_get(current_mailbox_key) };
+    do own.iter |own| {
+        unsafe { do own.access |mailbox| { mailbox.waiting_on = Some(target.clone()); } }
+        if would_deadlock(*own, target) {
+            stop_waiting();
+            fail ~"actor deadlock: asked an actor that is waiting on the asker";
+        }
+    }
+}
+
+#[cfg(ndebug)]
+fn start_waiting_on(_target: &MutexARC<Mailbox>) {}
+
+#[cfg(debug)]
+fn stop_waiting() {
+    let own = unsafe { task::local_data::local_data_get(current_mailbox_key) };
+    do own.iter |own| {
+        unsafe { do own.access |mailbox| { mailbox.waiting_on = None; } }
+    }
+}
+
+#[cfg(ndebug)]
+fn stop_waiting() {}
+
+/**
+Whether the actor of `asker` asking the actor of `target` would never get
+a reply, because `target`'s actor is waiting, through a chain of asks, on
+`asker`'s.
+*/
+fn would_deadlock(asker: &MutexARC<Mailbox>, target: &MutexARC<Mailbox>) -> bool {
+    let asker_id = mailbox_id(asker);
+    // other actors may be in a cycle that the asker isn't part of
+    let mut visited = ~[];
+    let mut next = Some(target.clone());
+    loop {
+        match move next {
+            None => return false,
+            Some(move mailbox) => {
+                let id = mailbox_id(&mailbox);
+                if id == asker_id {
+                    return true;
+                }
+                if visited.contains(&id) {
+                    return false;
+                }
+                visited.push(id);
+                next = unsafe {
+                    do mailbox.access |mailbox| {
+                        match mailbox.waiting_on {
+                            Some(ref waited_on) => Some(waited_on.clone()),
+                            None => None
+                        }
+                    }
+                };
+            }
+        }
+    }
+}
+
+fn mailbox_id(mailbox: &MutexARC<Mailbox>) -> uint {
+    unsafe { do mailbox.access |mailbox| { ptr::to_unsafe_ptr(&*mailbox) as uint } }
+}
+
+fn tracer_key(_tracer: @fn@(type_name: &str)) {}
+
+/**
+Makes every `ActorRef` send performed by the current task report the name
+of the message's type to `tracer`, for debugging.
+
+Rust can't name a type at run time, so the name is read from the front of
+the message's debug representation: for an enum message, that's the name of
+its variant.
+*/
+fn set_actor_tracer(tracer: fn@(type_name: &str)) {
+    unsafe { task::local_data::local_data_set(tracer_key, @move tracer); }
+}
+
+/// Stops the tracing started by `set_actor_tracer` in the current task.
+fn clear_actor_tracer() {
+    unsafe { task::local_data::local_data_pop(tracer_key); }
+}
+
+fn trace_send<M>(msg: &M) {
+    let tracer = unsafe { task::local_data::local_data_get(tracer_key) };
+    match tracer {
+        Some(tracer) => (*tracer)(message_name(msg)),
+        None => {}
+    }
+}
+
+fn message_name<M>(msg: &M) -> ~str {
+    let repr = sys::log_str(msg);
+    match str::find(repr, |c| c == '(' || c == '{' || c == ' ') {
+        Some(end) => str::slice(repr, 0, end),
+        None => move repr
+    }
+}
+
+/// The number of messages sent to an actor that it hasn't handled yet
+struct Mailbox {
+    capacity: uint,
+    mut queued: uint,
+    mut alive: bool,
+    // the actor that this one is waiting on a reply from; only recorded
+    // in debug builds, by `ask`
+    mut waiting_on: Option<MutexARC<Mailbox>>
+}
+
+fn new_mailbox(capacity: uint) -> MutexARC<Mailbox> {
+    MutexARC(Mailbox { capacity: capacity, queued: 0, alive: true, waiting_on: None })
+}
+
+/// Counts `count` messages as handled by the actor.
+fn mailbox_handled(mailbox: &MutexARC<Mailbox>, count: uint) {
+    unsafe {
+        do mailbox.access |mailbox| {
+            // every way of sending a message counts it as queued
+            assert mailbox.queued >= count;
+            mailbox.queued -= count;
+        }
+    }
+}
+
+/// Marks the actor as dead when its task ends, even by failing.
+struct MailboxCloser {
+    mailbox: MutexARC<Mailbox>,
+
+    drop {
+        unsafe {
+            do self.mailbox.access |mailbox| {
+                mailbox.alive = false;
+                mailbox.waiting_on = None;
+            }
+        }
+    }
 }
 
 /// The local actor interface
@@ -29,105 +305,600 @@ trait Actor<M> {
 
 /// A helper function used by actor constructors
 fn spawn<A: Actor<M>, M: Owned>(f: ~fn() -> A) -> ActorRef<M> {
+    spawn_bounded(uint::max_value, move f)
+}
+
+/// What an actor spawned by `spawn_with_deadletter` did with a message
+enum HandleResult<M> {
+    // the message was handled; keep receiving
+    Handled,
+    // the actor doesn't understand the message; forward it to the dead letters
+    NotHandled(M),
+    // the message was handled; stop receiving
+    Finished
+}
+
+/// The local interface of an actor that may not understand every message
+trait DeadLetterActor<M> {
+    fn try_handle(&self, msg: M) -> HandleResult<M>;
+}
+
+/**
+Spawns an actor that forwards the messages it doesn't handle to `dead`,
+instead of dropping them, so that they can be logged or handled elsewhere.
+*/
+fn spawn_with_deadletter<A: DeadLetterActor<M>, M: Owned>(f: ~fn() -> A,
+                                                          dead: Chan<M>) -> ActorRef<M> {
+    let (port, chan) = stream();
+    let mailbox = new_mailbox(uint::max_value);
+    let actor_mailbox = mailbox.clone();
+    do task::spawn |move f, move port, move dead, move actor_mailbox| {
+        let _closer = MailboxCloser { mailbox: actor_mailbox.clone() };
+        enter_actor(&actor_mailbox);
+        let actor = f();
+        loop {
+            let result = actor.try_handle(port.recv());
+            mailbox_handled(&actor_mailbox, 1);
+            match move result {
+                Handled => {}
+                NotHandled(move msg) => dead.send(move msg),
+                Finished => break
+            }
+        }
+    }
+
+    return ActorRef {
+        chan: move chan,
+        mailbox: move mailbox
+    }
+}
+
+/**
+Spawns an actor with a mailbox of `capacity` messages. `try_send` refuses
+messages while the actor has that many messages that it hasn't finished
+handling; `send` always delivers, and may overfill the mailbox.
+*/
+fn spawn_bounded<A: Actor<M>, M: Owned>(capacity: uint, f: ~fn() -> A) -> ActorRef<M> {
     let (port, chan) = stream();
-    do task::spawn |move f, move port| {
+    let mailbox = new_mailbox(capacity);
+    let actor_mailbox = mailbox.clone();
+    do task::spawn |move f, move port, move actor_mailbox| {
+        let _closer = MailboxCloser { mailbox: actor_mailbox.clone() };
+        enter_actor(&actor_mailbox);
         let actor = f();
         loop {
             let msg = port.recv();
-            if !actor.handle(move msg) {
+            let keep_going = actor.handle(move msg);
+            mailbox_handled(&actor_mailbox, 1);
+            if !keep_going {
+                break;
+            }
+        }
+    }
+
+    return ActorRef {
+        chan: move chan,
+        mailbox: move mailbox
+    }
+}
+
+/// The local interface of an actor that handles messages in batches
+trait BatchActor<M> {
+    fn handle_batch(&self, msgs: ~[M]) -> bool;
+}
+
+/**
+Spawns an actor whose messages are delivered in batches. A batch starts
+with the first message received while idle, and collects every message
+that arrives within the following `window_ms` milliseconds.
+*/
+fn spawn_batched<A: BatchActor<M>, M: Owned>(window_ms: uint, f: ~fn() -> A) -> ActorRef<M> {
+    let (port, chan) = stream();
+    let mailbox = new_mailbox(uint::max_value);
+    let actor_mailbox = mailbox.clone();
+    do task::spawn |move f, move port, move actor_mailbox| {
+        let _closer = MailboxCloser { mailbox: actor_mailbox.clone() };
+        enter_actor(&actor_mailbox);
+        let actor = f();
+        loop {
+            let mut batch = ~[port.recv()];
+
+            let (timer_port, timer_chan) = stream();
+            do task::spawn |move timer_chan| {
+                std::timer::sleep(std::uv_global_loop::get(), window_ms);
+                timer_chan.send(());
+            }
+
+            loop {
+                match pipes::select2i(&timer_port, &port) {
+                    Left(*) => { timer_port.recv(); break; }
+                    Right(*) => batch.push(port.recv())
+                }
+            }
+
+            let batch_len = batch.len();
+            let keep_going = actor.handle_batch(move batch);
+            mailbox_handled(&actor_mailbox, batch_len);
+            if !keep_going {
                 break;
             }
         }
     }
 
     return ActorRef {
-        chan: move chan
+        chan: move chan,
+        mailbox: move mailbox
+    }
+}
+
+/**
+Spawns an actor that only handles a message once no other message has
+arrived for `quiet_ms` milliseconds. Each message replaces the one waiting
+to be handled, whether or not they are equal, and starts the wait over, so
+only the last message of a burst is handled.
+*/
+fn spawn_debounced<A: Actor<M>, M: Owned>(quiet_ms: uint, f: ~fn() -> A) -> ActorRef<M> {
+    let (port, chan) = stream();
+    let mailbox = new_mailbox(uint::max_value);
+    let actor_mailbox = mailbox.clone();
+    do task::spawn |move f, move port, move actor_mailbox| {
+        let _closer = MailboxCloser { mailbox: actor_mailbox.clone() };
+        enter_actor(&actor_mailbox);
+        let actor = f();
+        let mut pending = port.recv();
+        // the messages that the pending one stands for
+        let mut pending_count = 1;
+        loop {
+            let (timer_port, timer_chan) = stream();
+            do task::spawn |move timer_chan| {
+                std::timer::sleep(std::uv_global_loop::get(), quiet_ms);
+                // nobody listens for a timer that a new message cut short
+                timer_chan.try_send(());
+            }
+
+            match pipes::select2i(&timer_port, &port) {
+                Left(*) => {
+                    let keep_going = actor.handle(move pending);
+                    mailbox_handled(&actor_mailbox, pending_count);
+                    if !keep_going {
+                        break;
+                    }
+                    pending = port.recv();
+                    pending_count = 1;
+                }
+                Right(*) => {
+                    pending = port.recv();
+                    pending_count += 1;
+                }
+            }
+        }
+    }
+
+    return ActorRef {
+        chan: move chan,
+        mailbox: move mailbox
+    }
+}
+
+/**
+Spawns an actor that a watchdog restarts when it stops responding. Every
+`interval_ms` milliseconds the watchdog pings the actor, which answers
+between messages, ahead of those that are waiting. An actor that doesn't
+answer within `deadline_ms` milliseconds, or that has failed, is replaced
+by a new one made by `factory`; the messages sent to the old one are lost.
+
+Tasks can't be killed from outside, so a hung actor's task is abandoned
+instead: it gets no more messages, and stops if it ever gets unstuck.
+*/
+fn spawn_watched<A: Actor<M> Owned, M: Owned>(interval_ms: uint, deadline_ms: uint,
+                                              factory: ~fn() -> A) -> ActorRef<M> {
+    let (port, chan) = stream();
+    let mailbox = new_mailbox(uint::max_value);
+    let actor_mailbox = mailbox.clone();
+    do task::spawn |move factory, move port, move actor_mailbox| {
+        let _closer = MailboxCloser { mailbox: actor_mailbox.clone() };
+        let mut watched = start_watched(factory());
+        loop {
+            // forward messages until it's time for a ping
+            let tick = timer_port(interval_ms);
+            loop {
+                match pipes::select2i(&tick, &port) {
+                    Left(*) => { tick.recv(); break; }
+                    Right(*) => {
+                        watched.chan.try_send(port.recv());
+                        mailbox_handled(&actor_mailbox, 1);
+                    }
+                }
+            }
+
+            let (pong_port, pong_chan) = stream();
+            let mut answered = false;
+            if watched.pings.try_send(move pong_chan) {
+                let deadline = timer_port(deadline_ms);
+                answered = match pipes::select2i(&deadline, &pong_port) {
+                    Left(*) => false,
+                    Right(*) => pong_port.try_recv().is_some()
+                };
+            }
+            if !answered {
+                // an actor that stopped by itself isn't restarted
+                if watched.finished.peek() {
+                    break;
+                }
+                watched = start_watched(factory());
+            }
+        }
+    }
+
+    return ActorRef {
+        chan: move chan,
+        mailbox: move mailbox
+    }
+}
+
+/// The channels between a watchdog and the actor it watches
+struct Watched<M: Owned> {
+    chan: Chan<M>,
+    // each ping carries the channel that the actor answers on
+    pings: Chan<Chan<()>>,
+    // receives once the actor has stopped receiving by itself
+    finished: Port<()>
+}
+
+fn start_watched<A: Actor<M> Owned, M: Owned>(actor: A) -> Watched<M> {
+    let (port, chan) = stream();
+    let (ping_port, ping_chan) = stream();
+    let (finished_port, finished_chan) = stream();
+    // unlinked, so that the actor's failure doesn't take the watchdog down
+    do task::spawn_unlinked |move actor, move port, move ping_port, move finished_chan| {
+        loop {
+            let ping = ping_port.peek() || match pipes::select2i(&ping_port, &port) {
+                Left(*) => true,
+                Right(*) => false
+            };
+            if ping {
+                match ping_port.try_recv() {
+                    Some(move pong) => { pong.try_send(()); }
+                    // the watchdog has given up on this actor
+                    None => break
+                }
+            } else {
+                match port.try_recv() {
+                    Some(move msg) => {
+                        if !actor.handle(move msg) {
+                            finished_chan.try_send(());
+                            break;
+                        }
+                    }
+                    None => break
+                }
+            }
+        }
+    }
+
+    Watched {
+        chan: move chan,
+        pings: move ping_chan,
+        finished: move finished_port
+    }
+}
+
+/// A port that receives `()` once `ms` milliseconds have passed.
+fn timer_port(ms: uint) -> Port<()> {
+    let (port, chan) = stream();
+    do task::spawn |move chan| {
+        std::timer::sleep(std::uv_global_loop::get(), ms);
+        // nobody listens for a timer that was cut short
+        chan.try_send(());
+    }
+    move port
+}
+
+/// Forwards the messages it receives to another actor, converted by `f`,
+/// and stops after forwarding one that `is_last` holds for.
+struct Forwarder<Outer, Inner: Owned> {
+    inner: ActorRef<Inner>,
+    f: ~fn(Outer) -> Inner,
+    is_last: ~fn(&Outer) -> bool
+}
+
+impl<Outer, Inner: Owned> Forwarder<Outer, Inner> : Actor<Outer> {
+    fn handle(&self, msg: Outer) -> bool {
+        let last = (self.is_last)(&msg);
+        self.inner.send((self.f)(move msg));
+        !last
+    }
+}
+
+/**
+Adapts `actor` to receive `Outer` messages, by spawning a task that
+converts each message with `f` and forwards it. Messages keep their order.
+The task stops once it has forwarded a message that `is_last` holds for,
+such as the one that stops `actor`. `f` and `is_last` run on the
+forwarding task, so they must be owned closures.
+*/
+fn comap<Outer: Owned, Inner: Owned>(actor: ActorRef<Inner>, f: ~fn(Outer) -> Inner,
+                                      is_last: ~fn(&Outer) -> bool) -> ActorRef<Outer> {
+    let forwarder = Cell(Forwarder { inner: move actor, f: move f, is_last: move is_last });
+    do spawn |move forwarder| {
+        forwarder.take()
     }
 }
 
 struct SharedActorRef<M: Owned> {
-    chan: SharedChan<M>
+    chan: SharedChan<M>,
+    priv mailbox: MutexARC<Mailbox>
 }
 
 impl<M: Owned> SharedActorRef<M> {
     fn send(&self, msg: M) {
+        trace_send(&msg);
+        unsafe { do self.mailbox.access |mailbox| { mailbox.queued += 1; } }
         self.chan.send(move msg);
     }
 
+    // Like `send`, but returns false instead of failing if the actor has stopped.
+    priv fn try_send(&self, msg: M) -> bool {
+        trace_send(&msg);
+        unsafe { do self.mailbox.access |mailbox| { mailbox.queued += 1; } }
+        if self.chan.try_send(move msg) {
+            return true;
+        }
+        unsafe { do self.mailbox.access |mailbox| { mailbox.queued -= 1; } }
+        false
+    }
+
+    /// Like `ActorRef::queue_len`.
+    fn queue_len(&self) -> uint {
+        unsafe { do self.mailbox.access |mailbox| { mailbox.queued } }
+    }
+
     fn clone(&self) -> SharedActorRef<M> {
         SharedActorRef {
-            chan: self.chan.clone()
+            chan: self.chan.clone(),
+            mailbox: self.mailbox.clone()
         }
     }
+
+    /// Like `ActorRef::ask`.
+    fn ask<R: Owned>(&self, make_msg: fn(Chan<R>) -> M) -> R {
+        start_waiting_on(&self.mailbox);
+        let (port, chan) = stream();
+        self.send(make_msg(move chan));
+        let reply = port.recv();
+        stop_waiting();
+        move reply
+    }
+
+    /**
+    Sends a barrier message made by `make_barrier`, and blocks until the
+    actor acknowledges it by sending `()` on the channel it carries. Since
+    the actor handles messages in order, every message that was sent
+    through this handle, or a clone of it, before the barrier has been
+    handled by the time this returns.
+    */
+    fn flush(&self, make_barrier: fn(Chan<()>) -> M) {
+        let (ack_port, ack_chan) = stream();
+        self.send(make_barrier(move ack_chan));
+        ack_port.recv();
+    }
 }
 
 fn SharedActorRef<M: Owned>(actor: ActorRef<M>) -> SharedActorRef<M> {
-    let chan = match move actor {
+    let (chan, mailbox) = match move actor {
         ActorRef {
-            chan: move chan
+            chan: move chan,
+            mailbox: move mailbox
         } => {
-            move chan
+            (move chan, move mailbox)
         }
     };
 
     SharedActorRef {
-        chan: SharedChan(move chan)
+        chan: SharedChan(move chan),
+        mailbox: move mailbox
     }
 }
 
-#[cfg(test)]
-mod test {
+/**
+A group of actors that receive the same messages
 
-    enum HelloMsg {
-        GetName(Chan<~str>),
-        Exit(Chan<()>)
+Members that take part in `broadcast_sync` must acknowledge each such
+message by sending `()` on the channel it carries once they have handled it.
+*/
+struct BroadcastGroup<M: Owned> {
+    priv mut members: ~[SharedActorRef<M>]
+}
+
+fn BroadcastGroup<M: Owned>() -> BroadcastGroup<M> {
+    BroadcastGroup {
+        members: ~[]
     }
+}
 
-    struct HelloActor {
-        name: ~str
+impl<M: Owned> BroadcastGroup<M> {
+    fn add(&self, actor: SharedActorRef<M>) {
+        self.members.push(move actor);
     }
 
-    impl HelloActor: Actor<HelloMsg> {
-        fn handle(&self, msg: HelloMsg) -> bool {
-            match msg {
-                GetName(chan) => chan.send(copy self.name),
-                Exit(chan) => {
-                    chan.send(());
-                    return false;
-                }
-            }
+    fn len(&self) -> uint {
+        self.members.len()
+    }
 
-            return true;
+    /**
+    Sends a message made by `make_msg` to every member, then blocks until
+    every member has acknowledged it, so that a producer can't run ahead of
+    the slowest member.
+    */
+    fn broadcast_sync(&self, make_msg: fn(Chan<()>) -> M) {
+        let mut acks = ~[];
+        for self.members.each |member| {
+            let (ack_port, ack_chan) = stream();
+            member.send(make_msg(move ack_chan));
+            acks.push(move ack_port);
+        }
+
+        for acks.each |ack_port| {
+            ack_port.recv();
         }
     }
+}
 
-    fn HelloActor(name: ~str) -> ActorRef<HelloMsg> {
-        do spawn |move name| {
-            HelloActor {
-                name: copy name
-            }
-        }        
+impl<M: Owned Copy> BroadcastGroup<M> {
+    /// Sends a copy of `msg` to every member, without waiting for them.
+    fn broadcast(&self, msg: M) {
+        for self.members.each |member| {
+            member.send(copy msg);
+        }
     }
+}
 
+/// Identical actors behind one handle, which take turns receiving messages
+struct ActorPool<M: Owned> {
+    priv workers: ~[ActorRef<M>],
+    // the worker that receives the next message
+    priv mut next: uint
+}
 
-    #[test]
-    fn test_exit() {
-        let actor = HelloActor(~"bob");
-        let (port, chan) = stream();
-        actor.send(Exit(move chan));
-        port.recv();
+/// Starts a pool of `size` workers, each made by `factory`.
+fn ActorPool<M: Owned>(size: uint, factory: fn() -> ActorRef<M>) -> ActorPool<M> {
+    assert size > 0;
+    ActorPool {
+        workers: vec::from_fn(size, |_i| factory()),
+        next: 0
     }
+}
 
-    #[test]
-    fn test_shared() {
-        let actor = HelloActor(~"bob");
-        let actor1 = SharedActorRef(move actor);
-        let actor2 = actor1.clone();
+impl<M: Owned> ActorPool<M> {
+    fn len(&self) -> uint {
+        self.workers.len()
+    }
 
-        let (port1, chan1) = stream();
-        actor1.send(GetName(move chan1));
-        let (port2, chan2) = stream();
-        actor2.send(GetName(move chan2));
+    /// Sends `msg` to the next worker in turn.
+    fn send(&self, msg: M) {
+        let worker = self.next;
+        self.next = (self.next + 1) % self.workers.len();
+        self.workers[worker].send(move msg);
+    }
+
+    /**
+    Sends every worker a message made by `make_stop`, which should make it
+    stop receiving, and blocks until every worker has acknowledged it by
+    sending `()` on the channel that the message carries.
+    */
+    fn shutdown(self, make_stop: fn(Chan<()>) -> M) {
+        let mut acks = ~[];
+        for self.workers.each |worker| {
+            let (ack_port, ack_chan) = stream();
+            worker.send(make_stop(move ack_chan));
+            acks.push(move ack_port);
+        }
+
+        for acks.each |ack_port| {
+            ack_port.recv();
+        }
+    }
+}
+
+/// A handle to a task started by `every`
+struct Ticker {
+    priv cancel_chan: Chan<()>
+}
+
+impl Ticker {
+    /// Stops the ticks. Ticks already sent may still be delivered.
+    fn cancel(&self) {
+        self.cancel_chan.try_send(());
+    }
+}
+
+/**
+Spawns a task that sends a message made by `make_tick` to `actor` every
+`interval_ms` milliseconds, until the returned handle is cancelled or the
+actor stops receiving messages.
+*/
+fn every<M: Owned>(interval_ms: u64, actor: SharedActorRef<M>, make_tick: ~fn() -> M) -> Ticker {
+    let (cancel_port, cancel_chan) = stream();
+    do task::spawn |move actor, move make_tick, move cancel_port| {
+        loop {
+            std::timer::sleep(std::uv_global_loop::get(), interval_ms as uint);
+            if cancel_port.peek() {
+                break;
+            }
+            if !actor.try_send(make_tick()) {
+                break;
+            }
+        }
+    }
+
+    Ticker {
+        cancel_chan: move cancel_chan
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::dvec::DVec;
+
+    enum HelloMsg {
+        GetName(Chan<~str>),
+        Exit(Chan<()>)
+    }
+
+    struct HelloActor {
+        name: ~str
+    }
+
+    impl HelloActor: Actor<HelloMsg> {
+        fn handle(&self, msg: HelloMsg) -> bool {
+            match msg {
+                GetName(chan) => chan.send(copy self.name),
+                Exit(chan) => {
+                    chan.send(());
+                    return false;
+                }
+            }
+
+            return true;
+        }
+    }
+
+    fn HelloActor(name: ~str) -> ActorRef<HelloMsg> {
+        do spawn |move name| {
+            HelloActor {
+                name: copy name
+            }
+        }        
+    }
+
+    // Waits for `actor` to stop receiving messages, failing if it doesn't soon.
+    fn wait_until_stopped<M: Owned>(actor: &ActorRef<M>) {
+        for uint::range(0, 1000) |_i| {
+            if !actor.is_alive() { return; }
+            std::timer::sleep(std::uv_global_loop::get(), 1);
+        }
+        fail ~"expected the actor to stop";
+    }
+
+
+    #[test]
+    fn test_exit() {
+        let actor = HelloActor(~"bob");
+        let (port, chan) = stream();
+        actor.send(Exit(move chan));
+        port.recv();
+    }
+
+    #[test]
+    fn test_shared() {
+        let actor = HelloActor(~"bob");
+        let actor1 = SharedActorRef(move actor);
+        let actor2 = actor1.clone();
+
+        let (port1, chan1) = stream();
+        actor1.send(GetName(move chan1));
+        let (port2, chan2) = stream();
+        actor2.send(GetName(move chan2));
 
         assert port1.recv() == ~"bob";
         assert port2.recv() == ~"bob";
@@ -137,4 +908,629 @@ mod test {
         port.recv();
     }
 
+    struct BatchCounter {
+        lengths: Chan<uint>
+    }
+
+    impl BatchCounter: BatchActor<uint> {
+        fn handle_batch(&self, msgs: ~[uint]) -> bool {
+            self.lengths.send(msgs.len());
+            return false;
+        }
+    }
+
+    #[test]
+    fn test_batched() {
+        let (port, chan) = stream();
+        let chan = Cell(move chan);
+        let actor = do spawn_batched(100) |move chan| {
+            BatchCounter {
+                lengths: chan.take()
+            }
+        };
+
+        for uint::range(0, 5) |i| {
+            actor.send(i);
+        }
+
+        assert port.recv() == 5;
+        // the counter stops after its first batch
+        wait_until_stopped(&actor);
+    }
+
+    enum CounterMsg {
+        Tick,
+        GetCount(Chan<uint>),
+        StopCounting(Chan<()>)
+    }
+
+    struct CounterActor {
+        mut count: uint
+    }
+
+    impl CounterActor: Actor<CounterMsg> {
+        fn handle(&self, msg: CounterMsg) -> bool {
+            match msg {
+                Tick => self.count += 1,
+                GetCount(chan) => chan.send(self.count),
+                StopCounting(chan) => {
+                    chan.send(());
+                    return false;
+                }
+            }
+
+            return true;
+        }
+    }
+
+    #[test]
+    fn test_every() {
+        let actor = do spawn { CounterActor { count: 0 } };
+        let actor = SharedActorRef(move actor);
+        let ticker = every(10, actor.clone(), || Tick);
+
+        std::timer::sleep(std::uv_global_loop::get(), 105);
+        ticker.cancel();
+
+        let (port, chan) = stream();
+        actor.send(GetCount(move chan));
+        let count = port.recv();
+        assert count >= 5 && count <= 11;
+
+        let (port, chan) = stream();
+        actor.send(StopCounting(move chan));
+        port.recv();
+    }
+
+    enum WorkMsg {
+        Work(Chan<()>),
+        StopWork(Chan<()>)
+    }
+
+    struct WorkActor {
+        delay_ms: uint,
+        done: Chan<()>
+    }
+
+    impl WorkActor: Actor<WorkMsg> {
+        fn handle(&self, msg: WorkMsg) -> bool {
+            match msg {
+                Work(ack) => {
+                    std::timer::sleep(std::uv_global_loop::get(), self.delay_ms);
+                    self.done.send(());
+                    ack.send(());
+                }
+                StopWork(ack) => {
+                    ack.send(());
+                    return false;
+                }
+            }
+
+            return true;
+        }
+    }
+
+    fn WorkActor(delay_ms: uint, done: Chan<()>) -> SharedActorRef<WorkMsg> {
+        let done = Cell(move done);
+        let actor = do spawn |move done| {
+            WorkActor {
+                delay_ms: delay_ms,
+                done: done.take()
+            }
+        };
+        SharedActorRef(move actor)
+    }
+
+    #[test]
+    fn test_broadcast_sync() {
+        let (fast_port, fast_chan) = stream();
+        let (slow_port, slow_chan) = stream();
+        let fast = WorkActor(0, move fast_chan);
+        let slow = WorkActor(100, move slow_chan);
+        let group = BroadcastGroup();
+        group.add(fast.clone());
+        group.add(slow.clone());
+
+        group.broadcast_sync(|ack| Work(move ack));
+
+        // Both members finished handling the message before it returned.
+        assert fast_port.peek();
+        assert slow_port.peek();
+
+        fast.ask(|ack| StopWork(move ack));
+        slow.ask(|ack| StopWork(move ack));
+    }
+
+    enum PingMsg {
+        Ping(Chan<()>),
+        Pong(uint),
+        StopPinging(Chan<()>)
+    }
+
+    struct PingActor;
+
+    impl PingActor: DeadLetterActor<PingMsg> {
+        fn try_handle(&self, msg: PingMsg) -> HandleResult<PingMsg> {
+            match move msg {
+                Ping(move chan) => { chan.send(()); Handled }
+                StopPinging(move chan) => { chan.send(()); Finished }
+                move msg => NotHandled(move msg)
+            }
+        }
+    }
+
+    #[test]
+    fn test_deadletter() {
+        let (dead_port, dead_chan) = stream();
+        let actor = spawn_with_deadletter(|| PingActor, move dead_chan);
+
+        let (port, chan) = stream();
+        actor.send(Ping(move chan));
+        port.recv();
+
+        actor.send(Pong(7));
+        match dead_port.recv() {
+            Pong(7) => {}
+            _ => fail ~"expected the unhandled message to be forwarded"
+        }
+
+        let (port, chan) = stream();
+        actor.send(StopPinging(move chan));
+        port.recv();
+    }
+
+    enum GateMsg {
+        WaitFor(Port<()>),
+        Noop(uint),
+        StopGate(Chan<()>)
+    }
+
+    struct GateActor;
+
+    impl GateActor: Actor<GateMsg> {
+        fn handle(&self, msg: GateMsg) -> bool {
+            match move msg {
+                WaitFor(move gate) => gate.recv(),
+                Noop(_) => {}
+                StopGate(move ack) => {
+                    ack.send(());
+                    return false;
+                }
+            }
+
+            return true;
+        }
+    }
+
+    #[test]
+    fn test_try_send_to_full_mailbox() {
+        let actor = spawn_bounded(1, || GateActor);
+        let (gate_port, gate_chan) = stream();
+        actor.send(WaitFor(move gate_port));
+
+        match actor.try_send(Noop(5)) {
+            Err(Noop(5)) => {}
+            _ => fail ~"expected the full mailbox to refuse the message"
+        }
+
+        gate_chan.send(());
+        actor.ask(|ack| StopGate(move ack));
+    }
+
+    #[test]
+    fn test_tracer() {
+        let traced = @DVec();
+        set_actor_tracer(|type_name| traced.push(str::from_slice(type_name)));
+
+        let actor = HelloActor(~"bob");
+        let (port, chan) = stream();
+        actor.send(GetName(move chan));
+        port.recv();
+        assert traced.get() == ~[~"GetName"];
+
+        clear_actor_tracer();
+        let (port, chan) = stream();
+        actor.send(Exit(move chan));
+        port.recv();
+        assert traced.len() == 1;
+    }
+
+    enum AnswerMsg {
+        // waits for the gate to open, then replies, then acknowledges the reply
+        AnswerAfter(Port<()>, Chan<()>, Chan<uint>),
+        StopAnswering(Chan<()>)
+    }
+
+    struct AnswerActor;
+
+    impl AnswerActor: Actor<AnswerMsg> {
+        fn handle(&self, msg: AnswerMsg) -> bool {
+            match move msg {
+                AnswerAfter(move gate, move ack, move reply) => {
+                    gate.recv();
+                    reply.send(42);
+                    ack.send(());
+                }
+                StopAnswering(move ack) => {
+                    ack.send(());
+                    return false;
+                }
+            }
+
+            return true;
+        }
+    }
+
+    #[test]
+    fn test_poll_future() {
+        let actor = spawn(|| AnswerActor);
+        let (gate_port, gate_chan) = stream();
+        let (ack_port, ack_chan) = stream();
+        let gate_port = Cell(move gate_port);
+        let ack_chan = Cell(move ack_chan);
+        let future = actor.ask_async(|reply| AnswerAfter(gate_port.take(), ack_chan.take(), move reply));
+
+        assert future.poll().is_none();
+
+        gate_chan.send(());
+        ack_port.recv();
+        assert future.poll() == Some(42);
+
+        actor.ask(|ack| StopAnswering(move ack));
+    }
+
+    enum PoolMsg {
+        CountMe,
+        StopWorking(Chan<()>)
+    }
+
+    struct PoolWorker {
+        id: uint,
+        // the number of messages handled by each worker
+        counts: MutexARC<~[uint]>
+    }
+
+    impl PoolWorker: Actor<PoolMsg> {
+        fn handle(&self, msg: PoolMsg) -> bool {
+            match move msg {
+                CountMe => unsafe {
+                    do self.counts.access |counts| { counts[self.id] += 1; }
+                },
+                StopWorking(move ack) => {
+                    ack.send(());
+                    return false;
+                }
+            }
+
+            return true;
+        }
+    }
+
+    #[test]
+    fn test_pool_round_robin() {
+        let counts = MutexARC(~[0u, 0u, 0u]);
+        let mut next_id = 0u;
+        let pool = do ActorPool(3) {
+            let id = next_id;
+            next_id += 1;
+            let worker_counts = Cell(counts.clone());
+            do spawn |move worker_counts| {
+                PoolWorker { id: id, counts: worker_counts.take() }
+            }
+        };
+
+        for uint::range(0, 9) |_i| {
+            pool.send(CountMe);
+        }
+        pool.shutdown(|ack| StopWorking(move ack));
+
+        let handled = unsafe { do counts.access |counts| { copy *counts } };
+        assert handled == ~[3u, 3u, 3u];
+    }
+
+    #[test]
+    fn test_queue_len_of_paused_actor() {
+        let actor = spawn(|| GateActor);
+        let (gate_port, gate_chan) = stream();
+        actor.send(WaitFor(move gate_port));
+        assert actor.queue_len() == 1;
+
+        for uint::range(0, 3) |i| {
+            actor.send(Noop(i));
+            assert actor.queue_len() == i + 2;
+        }
+
+        gate_chan.send(());
+        actor.ask(|ack| StopGate(move ack));
+    }
+
+    #[test]
+    fn test_queue_len_counts_shared_sends() {
+        let actor = spawn(|| GateActor);
+        let (gate_port, gate_chan) = stream();
+        actor.send(WaitFor(move gate_port));
+        let actor = SharedActorRef(move actor);
+        let other = actor.clone();
+
+        actor.send(Noop(0));
+        other.send(Noop(1));
+        assert actor.queue_len() == 3;
+        assert other.queue_len() == 3;
+
+        gate_chan.send(());
+        actor.ask(|ack| StopGate(move ack));
+    }
+
+    #[test]
+    fn test_is_alive() {
+        let actor = HelloActor(~"bob");
+        assert actor.is_alive();
+
+        let (port, chan) = stream();
+        actor.send(Exit(move chan));
+        port.recv();
+        // the actor acknowledges before its task ends
+        for uint::range(0, 1000) |_i| {
+            if !actor.is_alive() { break; }
+            task::yield();
+        }
+        assert !actor.is_alive();
+    }
+
+    #[test]
+    fn test_send_confirmed_to_stopped_actor() {
+        let actor = HelloActor(~"bob");
+        let (port, chan) = stream();
+        assert actor.send_confirmed(GetName(move chan)).is_ok();
+        assert port.recv() == ~"bob";
+
+        let (port, chan) = stream();
+        actor.send(Exit(move chan));
+        port.recv();
+        for uint::range(0, 1000) |_i| {
+            if !actor.is_alive() { break; }
+            task::yield();
+        }
+
+        let (_port, chan) = stream();
+        match actor.send_confirmed(GetName(move chan)) {
+            Err(GetName(_)) => {}
+            _ => fail ~"expected the message back from a stopped actor"
+        }
+    }
+
+    enum FlushMsg {
+        Job,
+        Barrier(Chan<()>),
+        StopJobs(Chan<()>)
+    }
+
+    struct SlowJobActor {
+        done: Chan<()>
+    }
+
+    impl SlowJobActor: Actor<FlushMsg> {
+        fn handle(&self, msg: FlushMsg) -> bool {
+            match move msg {
+                Job => {
+                    std::timer::sleep(std::uv_global_loop::get(), 10);
+                    self.done.send(());
+                }
+                Barrier(move ack) => ack.send(()),
+                StopJobs(move ack) => {
+                    ack.send(());
+                    return false;
+                }
+            }
+            return true;
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        let (done_port, done_chan) = stream();
+        let done_chan = Cell(move done_chan);
+        let actor = do spawn |move done_chan| {
+            SlowJobActor { done: done_chan.take() }
+        };
+        let actor = SharedActorRef(move actor);
+        let other = actor.clone();
+
+        for uint::range(0, 5) |i| {
+            if i % 2 == 0 { actor.send(Job) } else { other.send(Job) }
+        }
+        actor.flush(|ack| Barrier(move ack));
+
+        // every job was handled before the barrier was acknowledged
+        for uint::range(0, 5) |_i| {
+            assert done_port.peek();
+            done_port.recv();
+        }
+
+        let (port, chan) = stream();
+        actor.send(StopJobs(move chan));
+        port.recv();
+    }
+
+    #[test]
+    fn test_ask_cycle_would_deadlock() {
+        let a = new_mailbox(uint::max_value);
+        let b = new_mailbox(uint::max_value);
+        assert !would_deadlock(&b, &a);
+
+        // a's actor is waiting on a reply from b's
+        unsafe { do a.access |mailbox| { mailbox.waiting_on = Some(b.clone()); } }
+        assert would_deadlock(&b, &a);
+        assert !would_deadlock(&a, &b);
+        assert would_deadlock(&a, &a);
+    }
+
+    enum RelayMsg {
+        // asks the first actor to relay the rest, then replies
+        Relay(~[SharedActorRef<RelayMsg>], Chan<()>)
+    }
+
+    struct RelayActor;
+
+    impl RelayActor: Actor<RelayMsg> {
+        fn handle(&self, msg: RelayMsg) -> bool {
+            match move msg {
+                Relay(move chain, move reply) => {
+                    if !chain.is_empty() {
+                        let mut chain = move chain;
+                        let next = chain.shift();
+                        let rest = Cell(move chain);
+                        next.ask(|reply| Relay(rest.take(), move reply));
+                    }
+                    reply.send(());
+                }
+            }
+            return true;
+        }
+    }
+
+    #[test]
+    #[ignore(cfg(ndebug))]
+    fn test_ask_cycle_fails_instead_of_hanging() {
+        // the actors are linked to this task, so their failure ends it
+        let result = do task::try {
+            let a = SharedActorRef(spawn(|| RelayActor));
+            let b = SharedActorRef(spawn(|| RelayActor));
+            let chain = Cell(~[b.clone(), a.clone()]);
+            // a asks b, which asks a back while a is waiting on it
+            a.ask(|reply| Relay(chain.take(), move reply));
+        };
+        assert result.is_err();
+    }
+
+    enum GreeterMsg {
+        Greet(Chan<~str>),
+        Leave(Chan<()>)
+    }
+
+    #[test]
+    fn test_comap() {
+        let actor = HelloActor(~"bob");
+        let greeter = comap(move actor, |msg: GreeterMsg| {
+            match move msg {
+                Greet(move chan) => GetName(move chan),
+                Leave(move chan) => Exit(move chan)
+            }
+        }, |msg: &GreeterMsg| {
+            match *msg { Leave(*) => true, Greet(*) => false }
+        });
+
+        let (port, chan) = stream();
+        greeter.send(Greet(move chan));
+        assert port.recv() == ~"bob";
+        assert greeter.is_alive();
+
+        let (port, chan) = stream();
+        greeter.send(Leave(move chan));
+        port.recv();
+        // forwarding the actor's stop message stops the forwarder, too
+        wait_until_stopped(&greeter);
+    }
+
+    struct ResizeActor {
+        handled: Chan<uint>
+    }
+
+    impl ResizeActor: Actor<uint> {
+        fn handle(&self, width: uint) -> bool {
+            if width == 0 {
+                return false;
+            }
+            self.handled.send(width);
+            return true;
+        }
+    }
+
+    enum HangMsg {
+        // doesn't return until the gate opens
+        HangUntil(Port<()>),
+        Echo(uint, Chan<uint>),
+        StopHanging
+    }
+
+    struct HangActor;
+
+    impl HangActor: Actor<HangMsg> {
+        fn handle(&self, msg: HangMsg) -> bool {
+            match move msg {
+                HangUntil(move gate) => { gate.try_recv(); }
+                Echo(n, move chan) => chan.send(n),
+                StopHanging => return false
+            }
+            return true;
+        }
+    }
+
+    #[test]
+    fn test_watchdog_restarts_hung_actor() {
+        let (starts_port, starts_chan) = stream();
+        let actor = do spawn_watched(20, 50) |move starts_chan| {
+            starts_chan.send(());
+            HangActor
+        };
+        starts_port.recv();
+
+        let (port, chan) = stream();
+        actor.send(Echo(1, move chan));
+        assert port.recv() == 1;
+
+        let (gate_port, gate_chan) = stream();
+        actor.send(HangUntil(move gate_port));
+        // the hung actor misses a ping, and is replaced
+        starts_port.recv();
+
+        let (port, chan) = stream();
+        actor.send(Echo(2, move chan));
+        assert port.recv() == 2;
+
+        gate_chan.send(());
+        actor.send(StopHanging);
+        // the watchdog stops with the actor that replaced the hung one
+        wait_until_stopped(&actor);
+    }
+
+    #[test]
+    fn test_debounced() {
+        let (port, chan) = stream();
+        let chan = Cell(move chan);
+        let actor = do spawn_debounced(50) |move chan| {
+            ResizeActor { handled: chan.take() }
+        };
+
+        for uint::range(0, 10) |_i| {
+            actor.send(800);
+        }
+        std::timer::sleep(std::uv_global_loop::get(), 200);
+
+        // the burst was handled once, after it ended
+        assert port.recv() == 800;
+        assert !port.peek();
+        actor.send(0);
+        wait_until_stopped(&actor);
+    }
+
+    #[test]
+    fn test_debounced_handles_last_of_distinct_burst() {
+        let (port, chan) = stream();
+        let chan = Cell(move chan);
+        let actor = do spawn_debounced(50) |move chan| {
+            ResizeActor { handled: chan.take() }
+        };
+
+        for uint::range(1, 11) |width| {
+            actor.send(width * 100);
+        }
+        std::timer::sleep(std::uv_global_loop::get(), 200);
+
+        // only the newest width was handled; the others weren't flushed
+        assert port.recv() == 1000;
+        assert !port.peek();
+        actor.send(0);
+        wait_until_stopped(&actor);
+    }
 }
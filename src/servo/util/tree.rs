@@ -34,6 +34,42 @@ pub fn each_child<T:Copy,O:ReadMethods<T>>(ops: &O, node: &T, f: fn(&T) -> bool)
     }
 }
 
+// Trees deeper than this are assumed to be malformed (e.g. to contain a cycle).
+const max_dfs_depth: uint = 10000;
+
+/**
+Visits `root` and all of its descendants in depth-first preorder. `f`
+returns false to stop the traversal; returns false if it was stopped.
+*/
+pub fn dfs<T:Copy,O:ReadMethods<T>>(ops: &O, root: &T, f: fn(&T) -> bool) -> bool {
+    dfs_at_depth(ops, root, 0, false, f)
+}
+
+/// Like `dfs`, but visits each node after all of its descendants.
+pub fn dfs_postorder<T:Copy,O:ReadMethods<T>>(ops: &O, root: &T, f: fn(&T) -> bool) -> bool {
+    dfs_at_depth(ops, root, 0, true, f)
+}
+
+priv fn dfs_at_depth<T:Copy,O:ReadMethods<T>>(ops: &O, node: &T, depth: uint, postorder: bool,
+                                              f: fn(&T) -> bool) -> bool {
+    if depth > max_dfs_depth {
+        fail fmt!("Tree is deeper than %u levels; is there a cycle?", max_dfs_depth);
+    }
+
+    if !postorder && !f(node) { return false; }
+
+    let mut finished = true;
+    for each_child(ops, node) |child| {
+        if !dfs_at_depth(ops, child, depth + 1, postorder, f) {
+            finished = false;
+            break;
+        }
+    }
+    if !finished { return false; }
+
+    if postorder { f(node) } else { true }
+}
+
 pub fn is_leaf<T:Copy,O:ReadMethods<T>>(ops: &O, node: &T) -> bool {
     tree::first_child(ops, node).is_none()
 }